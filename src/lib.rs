@@ -0,0 +1,56 @@
+//! A small Vulkan renderer built on `ash` and `winit`.
+//!
+//! Create a [`Renderer`] for a window with a [`RendererConfig`] and forward
+//! the window's events to [`Renderer::handle_event`], which resizes the
+//! swapchain and renders a frame whenever the window needs redrawing:
+//!
+//! ```no_run
+//! use vre::{Renderer, RendererConfig};
+//! use winit::{
+//!     event::{Event, WindowEvent},
+//!     event_loop::{ControlFlow, EventLoop},
+//!     window::WindowBuilder,
+//! };
+//!
+//! let event_loop = EventLoop::new();
+//! let window = WindowBuilder::new().build(&event_loop).unwrap();
+//! let config = RendererConfig::new().app_name("Example");
+//! let mut renderer = Renderer::new(&window, &config).unwrap();
+//!
+//! event_loop.run(move |event, _, control_flow| {
+//!     if let Event::WindowEvent {
+//!         event: WindowEvent::CloseRequested,
+//!         ..
+//!     } = event
+//!     {
+//!         *control_flow = ControlFlow::Exit;
+//!     }
+//!
+//!     renderer
+//!         .handle_event(&window, &event, control_flow)
+//!         .expect("Failed to render frame.");
+//! });
+//! ```
+//!
+//! [`Renderer::new_headless`] renders without a window, e.g. for CI.
+
+mod renderer;
+mod utils;
+
+/// Vulkan types used in the API, e.g. formats for [`TextureDesc`].
+pub use ash::vk;
+/// The device render graph passes record commands with, see
+/// [`PassContext::device`], and the trait providing its commands.
+pub use ash::{version::DeviceV1_0, Device};
+
+pub use renderer::{
+    build_shaders, compile_shader, compile_shader_dir, compile_shader_file, cpu_scope, Buffer,
+    BufferAccess, BufferHandle, BufferUsage, ComputePipelineDesc, CpuProfiler, CpuScope,
+    CpuScopeStats, DescriptorSet, DescriptorSetBuilder, DeviceOverride, FrameCapture,
+    GpuFrameTimings, GpuProfiler, GpuScope, GraphicsPipelineDesc, HeapStats, ImageDesc,
+    ImageHandle, ImageSize, LoadOp, PassBuilder, PassContext, PipelineId, PipelineLayout,
+    PipelineStatistics, PresentMode, QueryResult, QuerySupport, RenderGraph, RenderGraphDump,
+    Renderer, RendererConfig, SamplerDesc, ShaderCompileError, ShaderLanguage, ShaderModule,
+    ShaderStage, SurfaceFormat, Texture, TextureDesc, TextureKind, TransientMemoryReport,
+    ValidationCounters, Version, VreError,
+};
@@ -1,39 +1,132 @@
 use winit::{
     dpi::LogicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-use std::error::Error;
+use std::{env, error::Error};
 
-mod renderer;
-mod utils;
+use vre::{Renderer, RendererConfig};
 
-use renderer::Renderer;
-
-pub const WINDOW_TITLE: &'static str = "Vulkan Tutorial";
-pub const WINDOW_WIDTH: u32 = 800;
-pub const WINDOW_HEIGHT: u32 = 600;
+const WINDOW_TITLE: &str = "Vulkan Tutorial";
+const WINDOW_WIDTH: u32 = 800;
+const WINDOW_HEIGHT: u32 = 600;
+const HEADLESS_OUTPUT: &str = "frame.png";
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.1, 0.15, 1.0];
+/// Key that dumps the next frame's render graph.
+const GRAPH_DUMP_KEY: VirtualKeyCode = VirtualKeyCode::F9;
+const GRAPH_DUMP_DOT: &str = "render_graph.dot";
+const GRAPH_DUMP_JSON: &str = "render_graph.json";
+/// Key that captures a CPU trace of the next `CPU_TRACE_FRAMES` frames.
+const CPU_TRACE_KEY: VirtualKeyCode = VirtualKeyCode::F10;
+const CPU_TRACE_FRAMES: usize = 120;
+const CPU_TRACE: &str = "cpu_trace.json";
 
 fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut args = env::args().skip(1).peekable();
+    let mut headless_output = None;
+    let mut config = RendererConfig::new().app_name(WINDOW_TITLE);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => {
+                let output = args
+                    .next_if(|arg| !arg.starts_with("--"))
+                    .unwrap_or_else(|| HEADLESS_OUTPUT.to_string());
+                headless_output = Some(output);
+            }
+            "--config" => {
+                let path = args.next().ok_or("--config requires a path")?;
+                config = RendererConfig::load(path)?;
+            }
+            _ => return Err(format!("Unknown argument {}", arg).into()),
+        }
+    }
+
+    if let Some(output) = headless_output {
+        return run_headless(&output, &config);
+    }
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_inner_size(LogicalSize::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32))
-        .with_resizable(false)
+        .with_resizable(true)
         .with_title(WINDOW_TITLE)
         .build(&event_loop)
         .expect("Could not create window.");
 
-    let _app = Renderer::new(&window);
+    let mut app = Renderer::new(&window, &config)?;
+    app.set_clear_color(CLEAR_COLOR);
 
     event_loop.run(move |event, _, control_flow| {
-        if let Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-        } = event
-        {
+        if let Event::WindowEvent { event, .. } = &event {
+            match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(GRAPH_DUMP_KEY),
+                            ..
+                        },
+                    ..
+                } => app.request_graph_dump(),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(CPU_TRACE_KEY),
+                            ..
+                        },
+                    ..
+                } => app.capture_cpu_trace(CPU_TRACE_FRAMES, CPU_TRACE),
+                _ => {}
+            }
+        }
+
+        if let Err(err) = app.handle_event(&window, &event, control_flow) {
+            log::error!("Failed to render frame: {}", err);
             *control_flow = ControlFlow::Exit;
         }
+
+        if let Some(dump) = app.take_graph_dump() {
+            match dump
+                .save_dot(GRAPH_DUMP_DOT)
+                .and_then(|_| dump.save_json(GRAPH_DUMP_JSON))
+            {
+                Ok(()) => log::info!(
+                    "Wrote render graph to {} and {}",
+                    GRAPH_DUMP_DOT,
+                    GRAPH_DUMP_JSON
+                ),
+                Err(err) => log::error!("Failed to write render graph: {}", err),
+            }
+        }
     });
 }
+
+/// Renders a single frame without a window and writes it to `output` as PNG.
+fn run_headless(output: &str, config: &RendererConfig) -> Result<(), Box<dyn Error>> {
+    let mut renderer = Renderer::new_headless(WINDOW_WIDTH, WINDOW_HEIGHT, config)?;
+    renderer.set_clear_color(CLEAR_COLOR);
+    let frame = renderer
+        .capture_frame()?
+        .expect("Headless renderer did not produce a frame.");
+
+    frame.save_png(output)?;
+    log::info!("Wrote {}x{} frame to {}", frame.width, frame.height, output);
+
+    let counters = renderer.validation_counters();
+    if counters.warnings > 0 || counters.errors > 0 {
+        log::warn!(
+            "Validation reported {} warning(s) and {} error(s).",
+            counters.warnings,
+            counters.errors
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use naga::{
+    back::spv,
+    front::{glsl, wgsl},
+    valid::{Capabilities, ValidationFlags, Validator},
+    Module, SourceLocation,
+};
+
+/// Pipeline stage a shader is compiled for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    /// Guesses the stage from a `vert`, `frag` or `comp` extension anywhere
+    /// in the file name, e.g. `sky.frag` or `sky.frag.wgsl`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let file_name = path.as_ref().file_name()?.to_str()?;
+
+        file_name
+            .split('.')
+            .skip(1)
+            .find_map(|extension| match extension {
+                "vert" => Some(ShaderStage::Vertex),
+                "frag" => Some(ShaderStage::Fragment),
+                "comp" => Some(ShaderStage::Compute),
+                _ => None,
+            })
+    }
+
+    fn to_naga(self) -> naga::ShaderStage {
+        match self {
+            ShaderStage::Vertex => naga::ShaderStage::Vertex,
+            ShaderStage::Fragment => naga::ShaderStage::Fragment,
+            ShaderStage::Compute => naga::ShaderStage::Compute,
+        }
+    }
+}
+
+/// Source language of a shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Glsl,
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    /// WGSL for files ending in `.wgsl`, GLSL otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("wgsl") => ShaderLanguage::Wgsl,
+            _ => ShaderLanguage::Glsl,
+        }
+    }
+}
+
+/// A shader that could not be read or compiled. `line` and `column` are
+/// 1-based and point at the first error; further errors are listed in
+/// `message`.
+#[derive(Clone, Debug)]
+pub struct ShaderCompileError {
+    pub path: Option<PathBuf>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderCompileError {
+    fn new(message: String, location: Option<SourceLocation>) -> Self {
+        Self {
+            path: None,
+            line: location.map(|location| location.line_number),
+            column: location.map(|location| location.line_position),
+            message,
+        }
+    }
+
+    fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ShaderCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = self.path.as_ref() {
+            write!(f, "{}:", path.display())?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        if self.path.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ShaderCompileError {}
+
+/// Compiles GLSL or WGSL to SPIR-V, ready for
+/// `Renderer::create_shader_module`. Only the entry point for `stage` is
+/// kept, which for GLSL is `main`.
+///
+/// Doesn't need a device, so it can also run in a build script.
+pub fn compile_shader(
+    source: &str,
+    language: ShaderLanguage,
+    stage: ShaderStage,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    let module = match language {
+        ShaderLanguage::Glsl => parse_glsl(source, stage)?,
+        ShaderLanguage::Wgsl => wgsl::parse_str(source).map_err(|err| {
+            ShaderCompileError::new(err.message().to_string(), err.location(source))
+        })?,
+    };
+
+    // Push constants are reflected into pipeline layouts, so shaders may
+    // declare them.
+    let info = Validator::new(ValidationFlags::all(), Capabilities::PUSH_CONSTANT)
+        .validate(&module)
+        .map_err(|err| ShaderCompileError::new(error_chain(&err), err.location(source)))?;
+
+    let entry_point = module
+        .entry_points
+        .iter()
+        .find(|entry_point| entry_point.stage == stage.to_naga())
+        .ok_or_else(|| ShaderCompileError::new(format!("no {:?} entry point", stage), None))?;
+    let pipeline_options = spv::PipelineOptions {
+        shader_stage: stage.to_naga(),
+        entry_point: entry_point.name.clone(),
+    };
+
+    let mut options = spv::Options::default();
+    if language == ShaderLanguage::Glsl {
+        // GLSL written for Vulkan already uses its clip space; flipping
+        // `gl_Position.y` would render it upside down compared to glslc.
+        options
+            .flags
+            .remove(spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    }
+
+    let words = spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|err| ShaderCompileError::new(err.to_string(), None))?;
+
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+/// Reads and compiles the shader at `path`, in the language given by its
+/// extension. Errors carry the path.
+///
+/// In a build script, write the result to `OUT_DIR` and embed it with
+/// `include_bytes!` to ship precompiled shaders.
+pub fn compile_shader_file<P: AsRef<Path>>(
+    path: P,
+    stage: ShaderStage,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(io_error(path))?;
+
+    compile_shader(&source, ShaderLanguage::from_path(path), stage)
+        .map_err(|err| err.with_path(path))
+}
+
+/// Compiles every file in `dir` whose stage `ShaderStage::from_path` can
+/// tell into `out_dir`, named after the source with `.spv` appended, e.g.
+/// `sky.frag` to `sky.frag.spv`. Other files and subdirectories are skipped.
+/// Returns the compiled source files in name order.
+pub fn compile_shader_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    out_dir: Q,
+) -> Result<Vec<PathBuf>, ShaderCompileError> {
+    let (dir, out_dir) = (dir.as_ref(), out_dir.as_ref());
+
+    let mut sources = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io_error(dir))? {
+        let path = entry.map_err(io_error(dir))?.path();
+        if let (true, Some(stage)) = (path.is_file(), ShaderStage::from_path(&path)) {
+            sources.push((path, stage));
+        }
+    }
+    sources.sort_by(|a, b| a.0.cmp(&b.0));
+
+    fs::create_dir_all(out_dir).map_err(io_error(out_dir))?;
+    for (path, stage) in sources.iter() {
+        let spirv = compile_shader_file(path, *stage)?;
+        let mut file_name = path.file_name().unwrap().to_os_string();
+        file_name.push(".spv");
+        let out_path = out_dir.join(file_name);
+        fs::write(&out_path, spirv).map_err(io_error(&out_path))?;
+    }
+
+    Ok(sources.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Compiles the shaders in `dir` into Cargo's `OUT_DIR` like
+/// `compile_shader_dir`, and has Cargo rerun the build script when one of
+/// them changes or a shader is added. Call it from the `main` of `build.rs`,
+/// with vre as a build dependency:
+///
+/// ```no_run
+/// if let Err(err) = vre::build_shaders("shaders") {
+///     panic!("{}", err);
+/// }
+/// ```
+///
+/// The crate then embeds `shaders/sky.frag` with
+/// `include_bytes!(concat!(env!("OUT_DIR"), "/sky.frag.spv"))`.
+pub fn build_shaders<P: AsRef<Path>>(dir: P) -> Result<(), ShaderCompileError> {
+    let out_dir = env::var_os("OUT_DIR").ok_or_else(|| {
+        ShaderCompileError::new(
+            "OUT_DIR is not set, not running in a build script".into(),
+            None,
+        )
+    })?;
+
+    println!("cargo:rerun-if-changed={}", dir.as_ref().display());
+    for source in compile_shader_dir(&dir, out_dir)? {
+        println!("cargo:rerun-if-changed={}", source.display());
+    }
+
+    Ok(())
+}
+
+/// Reads SPIR-V from a `.spv` file, or compiles any other shader file.
+pub(crate) fn load_shader_file(
+    path: &Path,
+    stage: ShaderStage,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    if path.extension().is_some_and(|extension| extension == "spv") {
+        fs::read(path).map_err(io_error(path))
+    } else {
+        compile_shader_file(path, stage)
+    }
+}
+
+fn parse_glsl(source: &str, stage: ShaderStage) -> Result<Module, ShaderCompileError> {
+    glsl::Frontend::default()
+        .parse(&glsl::Options::from(stage.to_naga()), source)
+        .map_err(|errors| {
+            let location =
+                |error: &glsl::Error| error.meta.is_defined().then(|| error.meta.location(source));
+            let first = &errors[0];
+            let mut message = first.kind.to_string();
+
+            // Later errors are appended with their own positions.
+            for error in errors.iter().skip(1) {
+                message.push('\n');
+                if let Some(location) = location(error) {
+                    message.push_str(&format!(
+                        "{}:{}: ",
+                        location.line_number, location.line_position
+                    ));
+                }
+                message.push_str(&error.kind.to_string());
+            }
+
+            ShaderCompileError::new(message, location(first))
+        })
+}
+
+/// Turns an error reading or writing `path` into a compile error.
+fn io_error(path: &Path) -> impl Fn(io::Error) -> ShaderCompileError + '_ {
+    move |err| ShaderCompileError::new(err.to_string(), None).with_path(path)
+}
+
+/// `err` followed by the errors that caused it, which carry the details of
+/// validation failures.
+fn error_chain(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_glsl_push_constants() {
+        let source = r#"
+            #version 450
+
+            layout(push_constant) uniform Push {
+                mat4 transform;
+            } push;
+
+            layout(location = 0) in vec3 position;
+
+            void main() {
+                gl_Position = push.transform * vec4(position, 1.0);
+            }
+        "#;
+
+        let spirv = compile_shader(source, ShaderLanguage::Glsl, ShaderStage::Vertex).unwrap();
+        assert_eq!(&spirv[..4], &0x0723_0203u32.to_le_bytes());
+    }
+
+    #[test]
+    fn keeps_glsl_clip_space() {
+        const OP_F_NEGATE: u32 = 127;
+        let source = r#"
+            #version 450
+
+            layout(location = 0) in vec2 position;
+
+            void main() {
+                gl_Position = vec4(position, 0.0, 1.0);
+            }
+        "#;
+
+        let spirv = compile_shader(source, ShaderLanguage::Glsl, ShaderStage::Vertex).unwrap();
+        let words: Vec<u32> = spirv
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        // Instructions start after the 5-word header; the low half of each
+        // first word is the opcode and the high half the word count.
+        let mut offset = 5;
+        while offset < words.len() {
+            assert_ne!(
+                words[offset] & 0xffff,
+                OP_F_NEGATE,
+                "gl_Position.y was flipped"
+            );
+            offset += (words[offset] >> 16) as usize;
+        }
+    }
+
+    #[test]
+    fn compiles_shader_directories() {
+        let dir = env::temp_dir().join(format!("vre-shaders-{}", std::process::id()));
+        let out_dir = dir.join("out");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("fill.frag"),
+            "#version 450\nlayout(location = 0) out vec4 color;\nvoid main() { color = vec4(1.0); }\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a shader").unwrap();
+
+        let compiled = compile_shader_dir(&dir, &out_dir).unwrap();
+        assert_eq!(compiled, vec![dir.join("fill.frag")]);
+        assert!(out_dir.join("fill.frag.spv").is_file());
+        assert!(!out_dir.join("notes.txt.spv").exists());
+
+        fs::write(
+            dir.join("broken.vert"),
+            "#version 450\nvoid main() { oops }\n",
+        )
+        .unwrap();
+        let err = compile_shader_dir(&dir, &out_dir).unwrap_err();
+        assert_eq!(err.path, Some(dir.join("broken.vert")));
+        assert_eq!(err.line, Some(2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
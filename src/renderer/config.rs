@@ -0,0 +1,431 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+use serde::{de, Deserialize, Deserializer};
+
+use super::{DeviceOverride, VreError};
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// A `major.minor.patch` version, written as `[1, 0, 0]` in TOML.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    pub(crate) fn to_vk(self) -> u32 {
+        vk::make_version(self.0, self.1, self.2)
+    }
+}
+
+/// Presentation mode requested for the swapchain. Falls back to `Fifo`,
+/// which every surface supports, when unavailable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    Immediate,
+    Mailbox,
+    Fifo,
+    FifoRelaxed,
+}
+
+impl PresentMode {
+    pub(crate) fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+}
+
+/// Encoding preferred for swapchain images. Falls back to the first format
+/// the surface reports when none of the matching formats is available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SurfaceFormat {
+    /// 8-bit sRGB formats, written to with automatic linear to sRGB
+    /// conversion.
+    Srgb,
+    /// 8-bit UNORM formats, written to as-is.
+    Unorm,
+}
+
+impl SurfaceFormat {
+    /// Formats matching the preference, most preferred first.
+    pub(crate) fn candidates(self) -> &'static [vk::Format] {
+        match self {
+            SurfaceFormat::Srgb => &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB],
+            SurfaceFormat::Unorm => &[vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM],
+        }
+    }
+}
+
+/// Settings used to create a `Renderer`. Start from `RendererConfig::new()`
+/// and chain setters, or load a TOML file with the same keys:
+///
+/// ```toml
+/// app_name = "My App"
+/// app_version = [0, 2, 0]
+/// validation = false
+/// validation_min_level = "info"
+/// suppressed_validation_ids = [1303270965]
+/// device_extensions = ["VK_KHR_maintenance1"]
+/// present_mode = "fifo"
+/// surface_format = "unorm"
+/// frames_in_flight = 3
+/// device = "llvmpipe"
+/// pipeline_cache = "cache/pipelines.bin"
+/// hot_reload = true
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererConfig {
+    pub(crate) app_name: String,
+    pub(crate) app_version: Version,
+    pub(crate) engine_name: String,
+    pub(crate) engine_version: Version,
+    pub(crate) api_version: Version,
+    pub(crate) validation: bool,
+    #[serde(deserialize_with = "deserialize_level")]
+    pub(crate) validation_min_level: log::Level,
+    pub(crate) suppressed_validation_ids: Vec<i32>,
+    pub(crate) instance_extensions: Vec<String>,
+    pub(crate) device_extensions: Vec<String>,
+    pub(crate) layers: Vec<String>,
+    pub(crate) present_mode: PresentMode,
+    pub(crate) surface_format: SurfaceFormat,
+    pub(crate) frames_in_flight: usize,
+    #[serde(deserialize_with = "deserialize_device")]
+    pub(crate) device: Option<DeviceOverride>,
+    pub(crate) pipeline_cache: Option<PathBuf>,
+    pub(crate) hot_reload: bool,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            app_name: "vre".to_string(),
+            app_version: Version(1, 0, 0),
+            engine_name: "vre".to_string(),
+            engine_version: Version(1, 0, 0),
+            api_version: Version(1, 0, 92),
+            validation: true,
+            validation_min_level: log::Level::Warn,
+            suppressed_validation_ids: Vec::new(),
+            instance_extensions: Vec::new(),
+            device_extensions: Vec::new(),
+            layers: Vec::new(),
+            present_mode: PresentMode::Mailbox,
+            surface_format: SurfaceFormat::Srgb,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            device: None,
+            pipeline_cache: None,
+            hot_reload: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl RendererConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a config from TOML. Missing keys keep their defaults, unknown
+    /// keys are rejected.
+    pub fn from_toml(source: &str) -> Result<Self, VreError> {
+        RendererConfig::parse(source).map_err(VreError::Config)
+    }
+
+    /// Reads and parses a TOML config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VreError> {
+        let path = path.as_ref();
+        let with_path =
+            |message: String| VreError::Config(format!("{}: {}", path.display(), message));
+
+        let source = fs::read_to_string(path).map_err(|err| with_path(err.to_string()))?;
+
+        RendererConfig::parse(&source).map_err(with_path)
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let config: RendererConfig = toml::from_str(source).map_err(|err| err.to_string())?;
+
+        validate_frames_in_flight(config.frames_in_flight)?;
+
+        Ok(config)
+    }
+
+    /// Application name reported to the driver.
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = app_name.to_string();
+        self
+    }
+
+    pub fn app_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.app_version = Version(major, minor, patch);
+        self
+    }
+
+    /// Engine name reported to the driver.
+    pub fn engine_name(mut self, engine_name: &str) -> Self {
+        self.engine_name = engine_name.to_string();
+        self
+    }
+
+    pub fn engine_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.engine_version = Version(major, minor, patch);
+        self
+    }
+
+    /// Highest Vulkan API version the application uses.
+    pub fn api_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.api_version = Version(major, minor, patch);
+        self
+    }
+
+    /// Enables `VK_LAYER_KHRONOS_validation` and the debug messenger when the
+    /// layer is installed. On by default.
+    pub fn validation(mut self, validation: bool) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Least severe validation message forwarded to `log`. Defaults to
+    /// warnings; `Renderer::set_validation_min_level` changes it later.
+    pub fn validation_min_level(mut self, min_level: log::Level) -> Self {
+        self.validation_min_level = min_level;
+        self
+    }
+
+    /// Drops validation messages with the given `messageIdNumber` from the
+    /// start, including those reported while creating the instance.
+    pub fn suppress_validation_message(mut self, message_id: i32) -> Self {
+        self.suppressed_validation_ids.push(message_id);
+        self
+    }
+
+    /// Enables an instance extension on top of the ones needed for the
+    /// window surface and validation.
+    pub fn instance_extension(mut self, name: &str) -> Self {
+        self.instance_extensions.push(name.to_string());
+        self
+    }
+
+    /// Requires a device extension on top of `VK_KHR_swapchain`. Devices
+    /// without it are never picked.
+    pub fn device_extension(mut self, name: &str) -> Self {
+        self.device_extensions.push(name.to_string());
+        self
+    }
+
+    /// Enables an instance layer on top of the validation layer.
+    pub fn layer(mut self, name: &str) -> Self {
+        self.layers.push(name.to_string());
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn surface_format(mut self, surface_format: SurfaceFormat) -> Self {
+        self.surface_format = surface_format;
+        self
+    }
+
+    /// How many frames the CPU may record ahead of the GPU. Creating a
+    /// renderer with zero fails with `VreError::Config`.
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    /// Forces a physical device instead of the highest scoring one. The
+    /// `VRE_DEVICE` environment variable takes precedence over this.
+    pub fn device(mut self, device: DeviceOverride) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Loads the pipeline cache from `path` on startup and writes it back
+    /// when the renderer is dropped. Files written for another device or
+    /// driver version are ignored.
+    pub fn pipeline_cache<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.pipeline_cache = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Watches the shader files of every pipeline and rebuilds pipelines
+    /// whose shaders changed. On by default in debug builds.
+    pub fn hot_reload(mut self, hot_reload: bool) -> Self {
+        self.hot_reload = hot_reload;
+        self
+    }
+}
+
+/// Parses `device` with the same syntax as `VRE_DEVICE`.
+fn deserialize_device<'de, D>(deserializer: D) -> Result<Option<DeviceOverride>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+/// Parses a `log::Level` name, e.g. `"info"`, ignoring case.
+fn deserialize_level<'de, D>(deserializer: D) -> Result<log::Level, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Checks a number of frames in flight, whether it comes from a config or
+/// `Renderer::set_frames_in_flight`.
+pub(crate) fn validate_frames_in_flight(frames_in_flight: usize) -> Result<(), String> {
+    if frames_in_flight == 0 {
+        return Err("frames_in_flight must be at least 1".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const FULL_CONFIG: &str = r#"
+        app_name = "My App"
+        app_version = [0, 2, 0]
+        engine_name = "My Engine"
+        engine_version = [3, 1, 4]
+        api_version = [1, 2, 0]
+        validation = false
+        validation_min_level = "info"
+        suppressed_validation_ids = [1303270965, -1]
+        instance_extensions = ["VK_EXT_debug_utils"]
+        device_extensions = ["VK_KHR_maintenance1"]
+        layers = ["VK_LAYER_LUNARG_monitor"]
+        present_mode = "fifo_relaxed"
+        surface_format = "unorm"
+        frames_in_flight = 3
+        device = "10de:2484"
+        pipeline_cache = "cache/pipelines.bin"
+        hot_reload = true
+    "#;
+
+    #[test]
+    fn parses_every_key() {
+        let config = RendererConfig::from_toml(FULL_CONFIG).unwrap();
+
+        assert_eq!(config.app_name, "My App");
+        assert_eq!(config.app_version, Version(0, 2, 0));
+        assert_eq!(config.engine_name, "My Engine");
+        assert_eq!(config.engine_version, Version(3, 1, 4));
+        assert_eq!(config.api_version, Version(1, 2, 0));
+        assert!(!config.validation);
+        assert_eq!(config.validation_min_level, log::Level::Info);
+        assert_eq!(config.suppressed_validation_ids, vec![1303270965, -1]);
+        assert_eq!(config.instance_extensions, vec!["VK_EXT_debug_utils"]);
+        assert_eq!(config.device_extensions, vec!["VK_KHR_maintenance1"]);
+        assert_eq!(config.layers, vec!["VK_LAYER_LUNARG_monitor"]);
+        assert_eq!(config.present_mode, PresentMode::FifoRelaxed);
+        assert_eq!(config.surface_format, SurfaceFormat::Unorm);
+        assert_eq!(config.frames_in_flight, 3);
+        assert_eq!(
+            config.device,
+            Some(DeviceOverride::Id {
+                vendor_id: 0x10de,
+                device_id: 0x2484
+            })
+        );
+        assert_eq!(
+            config.pipeline_cache,
+            Some(PathBuf::from("cache/pipelines.bin"))
+        );
+        assert!(config.hot_reload);
+    }
+
+    #[test]
+    fn keeps_defaults_for_missing_keys() {
+        let config = RendererConfig::from_toml("app_name = \"My App\"").unwrap();
+        let defaults = RendererConfig::default();
+
+        assert_eq!(config.app_name, "My App");
+        assert_eq!(config.app_version, defaults.app_version);
+        assert_eq!(config.validation, defaults.validation);
+        assert_eq!(config.validation_min_level, log::Level::Warn);
+        assert!(config.suppressed_validation_ids.is_empty());
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+        assert_eq!(config.surface_format, SurfaceFormat::Srgb);
+        assert_eq!(config.frames_in_flight, DEFAULT_FRAMES_IN_FLIGHT);
+        assert_eq!(config.device, None);
+        assert_eq!(config.pipeline_cache, None);
+        assert_eq!(config.hot_reload, defaults.hot_reload);
+
+        let empty = RendererConfig::from_toml("").unwrap();
+        assert_eq!(empty.app_name, defaults.app_name);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_values() {
+        let err = RendererConfig::from_toml("present_mod = \"fifo\"").unwrap_err();
+        match err {
+            VreError::Config(message) => assert!(message.contains("present_mod"), "{}", message),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let err = RendererConfig::from_toml("present_mode = \"vsync\"").unwrap_err();
+        assert!(matches!(err, VreError::Config(_)));
+        let err = RendererConfig::from_toml("validation_min_level = \"loud\"").unwrap_err();
+        assert!(matches!(err, VreError::Config(_)));
+        let err = RendererConfig::from_toml("device = \"10de:xyz\"").unwrap_err();
+        assert!(matches!(err, VreError::Config(_)));
+    }
+
+    #[test]
+    fn loads_files() {
+        let path = env::temp_dir().join(format!("vre-config-{}.toml", std::process::id()));
+        fs::write(&path, FULL_CONFIG).unwrap();
+        let config = RendererConfig::load(&path);
+        fs::write(&path, "validation = \"yes\"").unwrap();
+        let invalid = RendererConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.unwrap().present_mode, PresentMode::FifoRelaxed);
+        match invalid.unwrap_err() {
+            VreError::Config(message) => {
+                assert!(
+                    message.starts_with(&path.display().to_string()),
+                    "{}",
+                    message
+                )
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+        assert!(matches!(
+            RendererConfig::load(&path),
+            Err(VreError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_zero_frames_in_flight() {
+        let err = RendererConfig::from_toml("frames_in_flight = 0").unwrap_err();
+        assert!(matches!(err, VreError::Config(_)));
+
+        let config = RendererConfig::new().frames_in_flight(0);
+        assert!(validate_frames_in_flight(config.frames_in_flight).is_err());
+        assert!(validate_frames_in_flight(1).is_ok());
+    }
+}
@@ -0,0 +1,146 @@
+use std::{error::Error, fmt};
+
+use ash::vk;
+
+use super::compiler::ShaderCompileError;
+
+/// Errors returned by the renderer. Variants carrying a `vk::Result` include
+/// a short description of the operation that failed.
+#[derive(Debug)]
+pub enum VreError {
+    /// The Vulkan loader library could not be loaded.
+    Loader(String),
+    /// A `RendererConfig` could not be read or is invalid.
+    Config(String),
+    /// Creating the instance or querying instance-level properties failed.
+    Instance {
+        context: &'static str,
+        result: vk::Result,
+    },
+    /// Creating or querying the window surface failed.
+    Surface {
+        context: &'static str,
+        result: vk::Result,
+    },
+    /// No physical device meets the renderer's requirements.
+    NoSuitableDevice,
+    /// No memory type satisfies a resource's requirements.
+    NoSuitableMemoryType,
+    /// Creating the logical device or one of its objects failed.
+    Device {
+        context: &'static str,
+        result: vk::Result,
+    },
+    /// Creating, acquiring from or presenting to the swapchain failed.
+    Swapchain {
+        context: &'static str,
+        result: vk::Result,
+    },
+    /// A shader is not valid SPIR-V, uses unsupported features or does
+    /// not match the other stages of its pipeline.
+    Shader(String),
+    /// A GLSL or WGSL shader could not be read or compiled.
+    ShaderCompile(ShaderCompileError),
+    /// A render graph declares conflicting or missing resource accesses.
+    RenderGraph(String),
+    /// A buffer, texture or descriptor set was described inconsistently,
+    /// e.g. with data of the wrong size.
+    InvalidResource(String),
+    /// The host or device ran out of memory.
+    OutOfMemory {
+        context: &'static str,
+        result: vk::Result,
+    },
+}
+
+impl VreError {
+    pub(crate) fn instance(context: &'static str) -> impl FnOnce(vk::Result) -> VreError {
+        move |result| VreError::classify(result, VreError::Instance { context, result })
+    }
+
+    pub(crate) fn surface(context: &'static str) -> impl FnOnce(vk::Result) -> VreError {
+        move |result| VreError::classify(result, VreError::Surface { context, result })
+    }
+
+    pub(crate) fn device(context: &'static str) -> impl FnOnce(vk::Result) -> VreError {
+        move |result| VreError::classify(result, VreError::Device { context, result })
+    }
+
+    pub(crate) fn swapchain(context: &'static str) -> impl FnOnce(vk::Result) -> VreError {
+        move |result| VreError::classify(result, VreError::Swapchain { context, result })
+    }
+
+    /// The underlying Vulkan result code, if any.
+    pub fn result(&self) -> Option<vk::Result> {
+        match self {
+            VreError::Instance { result, .. }
+            | VreError::Surface { result, .. }
+            | VreError::Device { result, .. }
+            | VreError::Swapchain { result, .. }
+            | VreError::OutOfMemory { result, .. } => Some(*result),
+            VreError::Loader(_)
+            | VreError::Config(_)
+            | VreError::Shader(_)
+            | VreError::ShaderCompile(_)
+            | VreError::RenderGraph(_)
+            | VreError::InvalidResource(_)
+            | VreError::NoSuitableDevice
+            | VreError::NoSuitableMemoryType => None,
+        }
+    }
+
+    /// Out-of-memory results are reported as `OutOfMemory` no matter which
+    /// call produced them, so callers can handle them in one place.
+    fn classify(result: vk::Result, error: VreError) -> VreError {
+        match (result, error) {
+            (
+                vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+                VreError::Instance { context, .. }
+                | VreError::Surface { context, .. }
+                | VreError::Device { context, .. }
+                | VreError::Swapchain { context, .. },
+            ) => VreError::OutOfMemory { context, result },
+            (_, error) => error,
+        }
+    }
+}
+
+impl fmt::Display for VreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VreError::Loader(message) => write!(f, "Could not load Vulkan: {}", message),
+            VreError::Config(message) => write!(f, "Invalid renderer config: {}", message),
+            VreError::Shader(message) => write!(f, "Invalid shader: {}", message),
+            VreError::ShaderCompile(err) => write!(f, "Could not compile shader: {}", err),
+            VreError::RenderGraph(message) => write!(f, "Invalid render graph: {}", message),
+            VreError::InvalidResource(message) => write!(f, "Invalid resource: {}", message),
+            VreError::NoSuitableDevice => write!(f, "Could not find a suitable PhysicalDevice!"),
+            VreError::NoSuitableMemoryType => write!(f, "Could not find a suitable memory type."),
+            VreError::Instance { context, result }
+            | VreError::Surface { context, result }
+            | VreError::Device { context, result }
+            | VreError::Swapchain { context, result }
+            | VreError::OutOfMemory { context, result } => write!(f, "{} ({})", context, result),
+        }
+    }
+}
+
+impl Error for VreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VreError::Instance { result, .. }
+            | VreError::Surface { result, .. }
+            | VreError::Device { result, .. }
+            | VreError::Swapchain { result, .. }
+            | VreError::OutOfMemory { result, .. } => Some(result),
+            VreError::ShaderCompile(err) => Some(err),
+            VreError::Loader(_)
+            | VreError::Config(_)
+            | VreError::Shader(_)
+            | VreError::RenderGraph(_)
+            | VreError::InvalidResource(_)
+            | VreError::NoSuitableDevice
+            | VreError::NoSuitableMemoryType => None,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::ControlFlow,
+    window::Window,
+};
+
+use super::{Renderer, VreError};
+
+impl Renderer {
+    /// Drives the renderer from `window`'s event loop. Resizes the swapchain
+    /// when the window changes size, renders a frame whenever `window` needs
+    /// redrawing and keeps requesting redraws while rendering is not paused.
+    ///
+    /// Events for other windows are ignored. Closing the window is left to
+    /// the application.
+    pub fn handle_event<T>(
+        &mut self,
+        window: &Window,
+        event: &Event<T>,
+        control_flow: &mut ControlFlow,
+    ) -> Result<(), VreError> {
+        match event {
+            Event::WindowEvent { window_id, event } if *window_id == window.id() => match event {
+                WindowEvent::Resized(size) => self.resize(size.width, size.height),
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    self.resize(new_inner_size.width, new_inner_size.height)
+                }
+                _ => {}
+            },
+            Event::MainEventsCleared if *control_flow != ControlFlow::Exit => {
+                // Sleep until the next event instead of spinning while
+                // minimized.
+                if self.is_paused() {
+                    *control_flow = ControlFlow::Wait;
+                } else {
+                    *control_flow = ControlFlow::Poll;
+                    window.request_redraw();
+                }
+            }
+            Event::RedrawRequested(window_id) if *window_id == window.id() => {
+                self.render_frame()?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
@@ -1,17 +1,343 @@
+use std::path::{Path, PathBuf};
+
+use ash::{vk, Device};
 use winit::window::Window;
 
+mod compiler;
+mod config;
+mod error;
+mod events;
+mod profiler;
+mod trace;
 mod vulkan;
 
 use vulkan::VulkanBackend;
 
+pub use compiler::{
+    build_shaders, compile_shader, compile_shader_dir, compile_shader_file, ShaderCompileError,
+    ShaderLanguage, ShaderStage,
+};
+pub use config::{PresentMode, RendererConfig, SurfaceFormat, Version};
+pub use error::VreError;
+pub use profiler::{cpu_scope, CpuProfiler, CpuScope, CpuScopeStats};
+pub use vulkan::{
+    Buffer, BufferAccess, BufferHandle, BufferUsage, ComputePipelineDesc, DescriptorSet,
+    DescriptorSetBuilder, DeviceOverride, FrameCapture, GpuFrameTimings, GpuProfiler, GpuScope,
+    GraphicsPipelineDesc, HeapStats, ImageDesc, ImageHandle, ImageSize, LoadOp, PassBuilder,
+    PassContext, PipelineId, PipelineLayout, PipelineStatistics, QueryResult, QuerySupport,
+    RenderGraph, RenderGraphDump, SamplerDesc, ShaderModule, Texture, TextureDesc, TextureKind,
+    TransientMemoryReport, ValidationCounters,
+};
+
+/// Renders into a window's swapchain, or into an offscreen image when
+/// created with `new_headless`.
 pub struct Renderer {
-    _backend: VulkanBackend,
+    backend: VulkanBackend,
 }
 
 impl Renderer {
-    pub fn new(window: &Window) -> Renderer {
-        let backend = vulkan::VulkanBackend::new(&window);
+    /// Creates a renderer for `window` with the given settings. The physical
+    /// device can also be forced through the `VRE_DEVICE` environment
+    /// variable, which takes precedence over `RendererConfig::device`.
+    pub fn new(window: &Window, config: &RendererConfig) -> Result<Renderer, VreError> {
+        let backend = VulkanBackend::new(window, config.clone())?;
+
+        Ok(Renderer { backend })
+    }
+
+    /// Creates a renderer that draws into an offscreen image instead of a
+    /// window, for CI and batch jobs. Presentation settings in `config` are
+    /// ignored.
+    pub fn new_headless(
+        width: u32,
+        height: u32,
+        config: &RendererConfig,
+    ) -> Result<Renderer, VreError> {
+        let backend = VulkanBackend::new_headless(width, height, config.clone())?;
+
+        Ok(Renderer { backend })
+    }
+
+    /// Notifies the renderer that the window was resized. A zero-sized
+    /// window, e.g. when minimized, pauses rendering until it is restored.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.backend.resize(width, height);
+    }
+
+    /// Whether rendering is paused because the window has a zero-sized
+    /// extent.
+    pub fn is_paused(&self) -> bool {
+        self.backend.is_paused()
+    }
+
+    /// Sets how many frames the CPU may record ahead of the GPU. Defaults to
+    /// two; zero is rejected with `VreError::Config`.
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) -> Result<(), VreError> {
+        self.backend.set_frames_in_flight(frames_in_flight)
+    }
+
+    /// Sets the color every frame is cleared to.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.backend.set_clear_color(clear_color);
+    }
+
+    /// Warnings and errors reported by the validation layers since the
+    /// renderer was created, e.g. to assert a frame rendered cleanly.
+    pub fn validation_counters(&self) -> ValidationCounters {
+        self.backend.validation_counters()
+    }
+
+    /// Sets the least severe validation message forwarded to `log` under the
+    /// `vulkan` target, replacing `RendererConfig::validation_min_level`.
+    /// Counters are unaffected.
+    pub fn set_validation_min_level(&self, min_level: log::Level) {
+        self.backend.set_validation_min_level(min_level);
+    }
+
+    /// Drops validation messages with the given `messageIdNumber`, which is
+    /// printed with every forwarded message. Suppressed messages are not
+    /// counted.
+    pub fn suppress_validation_message(&self, message_id: i32) {
+        self.backend.suppress_validation_message(message_id);
+    }
+
+    /// Creates a device-local buffer, e.g. for vertices or indices, and
+    /// uploads `data` into it. Blocks until the upload has finished.
+    ///
+    /// Returns `VreError::InvalidResource` if `data` is empty.
+    pub fn create_buffer<T: Copy>(
+        &self,
+        usage: BufferUsage,
+        data: &[T],
+    ) -> Result<Buffer<T>, VreError> {
+        self.backend.create_buffer(usage, data)
+    }
+
+    /// Creates a host-visible buffer of `len` zeroed elements that stays
+    /// mapped, for data rewritten every frame with `Buffer::write`.
+    ///
+    /// Returns `VreError::InvalidResource` if `len` is zero.
+    pub fn create_mapped_buffer<T: Copy>(
+        &self,
+        usage: BufferUsage,
+        len: usize,
+    ) -> Result<Buffer<T>, VreError> {
+        self.backend.create_mapped_buffer(usage, len)
+    }
+
+    /// Creates a texture and uploads `data`, the tightly packed texels of the
+    /// base level for every layer in order. Generates the remaining mip
+    /// levels if `desc` asks for them. Blocks until the upload has finished
+    /// and leaves the texture ready for sampling. Returns
+    /// `VreError::InvalidResource` if `data` has the wrong length.
+    pub fn create_texture(&self, desc: &TextureDesc, data: &[u8]) -> Result<Texture, VreError> {
+        self.backend.create_texture(desc, data)
+    }
+
+    /// Creates a texture without contents, e.g. a render target.
+    pub fn create_empty_texture(&self, desc: &TextureDesc) -> Result<Texture, VreError> {
+        self.backend.create_empty_texture(desc)
+    }
+
+    /// Returns a sampler matching `desc`. Samplers are shared and live as
+    /// long as the renderer.
+    pub fn sampler(&self, desc: &SamplerDesc) -> Result<vk::Sampler, VreError> {
+        self.backend.sampler(desc)
+    }
+
+    /// Allocates a descriptor set with the resources listed in `builder`.
+    /// Sets with the same bindings share one layout. The set stays valid
+    /// until the renderer is dropped; resources bound to it must outlive
+    /// its use.
+    pub fn create_descriptor_set(
+        &mut self,
+        builder: &DescriptorSetBuilder,
+    ) -> Result<DescriptorSet, VreError> {
+        self.backend.create_descriptor_set(builder)
+    }
+
+    /// Allocates a descriptor set for the next frame only, e.g. for
+    /// per-frame uniforms. It is freed automatically once the frame has
+    /// finished on the GPU and its frame slot is reused, and when the number
+    /// of frames in flight changes.
+    pub fn frame_descriptor_set(
+        &mut self,
+        builder: &DescriptorSetBuilder,
+    ) -> Result<DescriptorSet, VreError> {
+        self.backend.frame_descriptor_set(builder)
+    }
+
+    /// Creates a shader module from compiled SPIR-V. The descriptor
+    /// bindings, push constants and vertex inputs of its first entry point
+    /// are reflected from the code.
+    pub fn create_shader_module(&self, spirv: &[u8]) -> Result<ShaderModule, VreError> {
+        self.backend.create_shader_module(spirv)
+    }
+
+    /// Creates a shader module from a file: SPIR-V if it ends in `.spv`,
+    /// otherwise GLSL or WGSL compiled for `stage` on the fly. Compile errors
+    /// are returned as `VreError::ShaderCompile` with file and line.
+    pub fn load_shader_module<P: AsRef<Path>>(
+        &self,
+        path: P,
+        stage: ShaderStage,
+    ) -> Result<ShaderModule, VreError> {
+        let spirv =
+            compiler::load_shader_file(path.as_ref(), stage).map_err(VreError::ShaderCompile)?;
+
+        self.backend.create_shader_module(&spirv)
+    }
+
+    /// Creates a pipeline layout from the reflected interfaces of `stages`.
+    /// Returns `VreError::Shader` if two stages declare a binding with
+    /// different types or counts.
+    pub fn create_pipeline_layout(
+        &self,
+        stages: &[&ShaderModule],
+    ) -> Result<PipelineLayout, VreError> {
+        self.backend.create_pipeline_layout(stages)
+    }
+
+    /// Creates a graphics pipeline drawing into the renderer's render pass,
+    /// with its layout reflected from the shaders. With hot reload enabled
+    /// the pipeline is rebuilt whenever one of its shader files changes.
+    pub fn create_graphics_pipeline(
+        &mut self,
+        desc: &GraphicsPipelineDesc,
+    ) -> Result<PipelineId, VreError> {
+        self.backend.create_graphics_pipeline(desc)
+    }
+
+    /// Creates a compute pipeline, rebuilt like graphics pipelines when its
+    /// shader changes.
+    pub fn create_compute_pipeline(
+        &mut self,
+        desc: &ComputePipelineDesc,
+    ) -> Result<PipelineId, VreError> {
+        self.backend.create_compute_pipeline(desc)
+    }
+
+    /// Records commands with `record` and runs them on the compute queue,
+    /// which is separate from the graphics queue on devices with a dedicated
+    /// compute family. Blocks until they have finished. Buffers are shared
+    /// with the compute family, textures are not.
+    pub fn run_compute<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &self,
+        record: F,
+    ) -> Result<(), VreError> {
+        self.backend.run_compute(record)
+    }
+
+    /// Current version of a pipeline. Look it up whenever recording, the
+    /// handle changes when the pipeline is rebuilt.
+    pub fn pipeline(&self, id: PipelineId) -> vk::Pipeline {
+        self.backend.pipeline(id)
+    }
+
+    pub fn pipeline_layout(&self, id: PipelineId) -> &PipelineLayout {
+        self.backend.pipeline_layout(id)
+    }
+
+    /// Why the last hot reload of a pipeline failed. The previous version
+    /// stays in use until its shaders compile again.
+    pub fn pipeline_error(&self, id: PipelineId) -> Option<&VreError> {
+        self.backend.pipeline_error(id)
+    }
+
+    /// Memory usage of every memory heap of the device, indexed like the
+    /// heaps in `vkGetPhysicalDeviceMemoryProperties`.
+    pub fn memory_stats(&self) -> Vec<HeapStats> {
+        self.backend.memory_stats()
+    }
+
+    /// How much memory the transient images and buffers of the last
+    /// rendered graph took, and how much aliasing them saved.
+    pub fn transient_memory_report(&self) -> TransientMemoryReport {
+        self.backend.transient_memory_report()
+    }
+
+    /// GPU timings of recent frames, each pass of their render graphs and
+    /// the scopes passes opened with `PassContext::begin_gpu_scope`. Frames
+    /// show up once they have finished on the GPU, without waiting for
+    /// them.
+    pub fn gpu_profiler(&self) -> &GpuProfiler {
+        self.backend.gpu_profiler()
+    }
+
+    /// Per-frame averages of the CPU scopes recorded with `cpu_scope` on
+    /// any thread over the last 60 frames. The renderer records `wait`,
+    /// `acquire`, `record`, `submit` and `present` scopes itself.
+    pub fn cpu_profiler(&self) -> &CpuProfiler {
+        self.backend.cpu_profiler()
+    }
+
+    /// Records the CPU scopes of every thread for the next `frames` frames
+    /// and writes them to `path` in the Chrome trace event format. Errors
+    /// writing the file are logged.
+    pub fn capture_cpu_trace<P: Into<PathBuf>>(&mut self, frames: usize, path: P) {
+        self.backend.capture_cpu_trace(frames, path.into());
+    }
+
+    /// Whether the device supports pipeline statistics queries and precise
+    /// occlusion queries. Both are enabled when available.
+    pub fn query_support(&self) -> QuerySupport {
+        self.backend.query_support()
+    }
+
+    /// Counters of the pipeline statistics queries called `name` that
+    /// passes recorded with `PassContext::begin_pipeline_statistics`, from
+    /// the latest frame that recorded one and has finished on the GPU.
+    /// Results arrive without waiting, frames in flight late.
+    pub fn pipeline_statistics(&self, name: &str) -> Option<QueryResult<PipelineStatistics>> {
+        self.backend.pipeline_statistics(name)
+    }
+
+    /// Samples that passed the occlusion query `key` recorded with
+    /// `PassContext::begin_occlusion_query`, read back like
+    /// `pipeline_statistics`. Without precise occlusion queries any non-zero
+    /// count only means some samples passed.
+    pub fn occlusion_samples(&self, key: u64) -> Option<QueryResult<u64>> {
+        self.backend.occlusion_samples(key)
+    }
+
+    /// Dumps the compiled form of the next rendered graph: its passes in
+    /// execution order, the culled ones, the resources they read and write
+    /// and the barriers inserted between them. Take it with
+    /// `take_graph_dump` once the frame has rendered.
+    pub fn request_graph_dump(&mut self) {
+        self.backend.request_graph_dump();
+    }
+
+    /// The graph dumped after `request_graph_dump`, or `None` if no frame
+    /// has rendered since.
+    pub fn take_graph_dump(&mut self) -> Option<RenderGraphDump> {
+        self.backend.take_graph_dump()
+    }
+
+    /// Renders and presents one frame. Returns `false` if nothing was
+    /// rendered, e.g. because the window is minimized or the swapchain had to
+    /// be recreated first.
+    pub fn render_frame(&mut self) -> Result<bool, VreError> {
+        self.backend.draw_frame()
+    }
+
+    /// Renders and presents one frame by running `graph`, whose passes draw
+    /// into the image returned by `RenderGraph::import_swapchain`. Returns
+    /// `false` if nothing was rendered, like `render_frame`.
+    pub fn render_graph(&mut self, graph: RenderGraph) -> Result<bool, VreError> {
+        self.backend.draw_graph(graph)
+    }
+
+    /// Renders a frame cleared to the clear color and reads it back to CPU
+    /// memory. Only available on headless renderers.
+    pub fn capture_frame(&mut self) -> Result<Option<FrameCapture>, VreError> {
+        self.backend.capture_frame()
+    }
 
-        Renderer { _backend: backend }
+    /// Renders a frame by running `graph` and reads the output image back to
+    /// CPU memory. Only available on headless renderers, returns `None`
+    /// otherwise without running the graph.
+    pub fn capture_graph(&mut self, graph: RenderGraph) -> Result<Option<FrameCapture>, VreError> {
+        self.backend.capture_graph(graph)
     }
 }
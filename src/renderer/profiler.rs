@@ -0,0 +1,482 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::trace::ChromeTrace;
+
+/// Events each thread keeps until a profiler collects them. Older events
+/// are overwritten.
+const RING_CAPACITY: usize = 16 * 1024;
+/// Frames the averages of `CpuProfiler` are taken over.
+const AVERAGE_FRAMES: usize = 60;
+/// Process id CPU scopes are listed under in Chrome traces.
+const TRACE_PID: u32 = 0;
+
+/// Every thread that recorded a scope.
+static THREADS: Mutex<Vec<Arc<Mutex<ThreadEvents>>>> = Mutex::new(Vec::new());
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static EVENTS: Arc<Mutex<ThreadEvents>> = ThreadEvents::register();
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CpuEvent {
+    name: &'static str,
+    start: Instant,
+    duration: Duration,
+}
+
+/// Ring buffer of the scopes one thread recorded.
+struct ThreadEvents {
+    id: u64,
+    name: String,
+    events: VecDeque<CpuEvent>,
+    /// Events recorded so far, including overwritten ones.
+    recorded: u64,
+}
+
+impl ThreadEvents {
+    fn register() -> Arc<Mutex<Self>> {
+        let id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+        let name = thread::current()
+            .name()
+            .map_or_else(|| format!("thread {}", id), str::to_string);
+        let events = Arc::new(Mutex::new(Self {
+            id,
+            name,
+            events: VecDeque::new(),
+            recorded: 0,
+        }));
+
+        THREADS.lock().unwrap().push(Arc::clone(&events));
+        events
+    }
+
+    fn push(&mut self, event: CpuEvent) {
+        if self.events.len() == RING_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        self.recorded += 1;
+    }
+}
+
+/// Times the enclosing block as a scope called `name` on the calling
+/// thread, until the returned guard is dropped:
+///
+/// ```no_run
+/// let _scope = vre::cpu_scope("physics");
+/// ```
+///
+/// Scopes show up in `Renderer::cpu_profiler` and CPU trace captures.
+pub fn cpu_scope(name: &'static str) -> CpuScope {
+    CpuScope {
+        name,
+        start: Instant::now(),
+    }
+}
+
+/// A running CPU timing scope, see `cpu_scope`.
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct CpuScope {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for CpuScope {
+    fn drop(&mut self) {
+        let event = CpuEvent {
+            name: self.name,
+            start: self.start,
+            duration: self.start.elapsed(),
+        };
+
+        // Ignored while the thread is being torn down.
+        let _ = EVENTS.try_with(|events| events.lock().unwrap().push(event));
+    }
+}
+
+/// Average time a CPU scope took per frame.
+#[derive(Clone, Debug)]
+pub struct CpuScopeStats {
+    pub name: &'static str,
+    /// Time spent in the scope per frame, summed over all threads and
+    /// calls.
+    pub average_ms: f64,
+    pub calls_per_frame: f64,
+}
+
+struct Capture {
+    remaining: usize,
+    path: PathBuf,
+    /// Recorded events by thread id.
+    events: Vec<(u64, CpuEvent)>,
+    threads: HashMap<u64, String>,
+    /// Start of every captured frame and of the frame after the last.
+    frames: Vec<Instant>,
+}
+
+/// Collects the CPU scopes recorded on every thread once per frame, keeping
+/// per-scope averages over the last 60 frames and optionally capturing
+/// frames into a Chrome trace.
+pub struct CpuProfiler {
+    /// Events read from each thread, by thread id.
+    cursors: HashMap<u64, u64>,
+    /// Total time and calls of every scope in recent frames, oldest first.
+    frames: VecDeque<HashMap<&'static str, (Duration, u32)>>,
+    frame_start: Instant,
+    capture: Option<Capture>,
+}
+
+impl CpuProfiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            cursors: HashMap::new(),
+            frames: VecDeque::with_capacity(AVERAGE_FRAMES),
+            frame_start: Instant::now(),
+            capture: None,
+        }
+    }
+
+    /// Ends the current frame: collects the scopes recorded since the last
+    /// call and writes the capture once it has all its frames.
+    pub(crate) fn end_frame(&mut self) {
+        let frame_end = Instant::now();
+        let mut totals: HashMap<&'static str, (Duration, u32)> = HashMap::new();
+        let mut threads = THREADS.lock().unwrap();
+
+        for thread in threads.iter() {
+            let thread = thread.lock().unwrap();
+            let cursor = self.cursors.entry(thread.id).or_insert(0);
+            let new = ((thread.recorded - *cursor) as usize).min(thread.events.len());
+            *cursor = thread.recorded;
+
+            for event in thread.events.iter().skip(thread.events.len() - new) {
+                let total = totals.entry(event.name).or_default();
+                total.0 += event.duration;
+                total.1 += 1;
+
+                if let Some(capture) = self.capture.as_mut() {
+                    capture.events.push((thread.id, *event));
+                }
+            }
+
+            if let Some(capture) = self.capture.as_mut() {
+                if new > 0 && !capture.threads.contains_key(&thread.id) {
+                    capture.threads.insert(thread.id, thread.name.clone());
+                }
+            }
+        }
+
+        // Threads that have exited and were read to the end are dropped.
+        let cursors = &mut self.cursors;
+        threads.retain(|thread| {
+            let finished = Arc::strong_count(thread) == 1;
+            if finished {
+                cursors.remove(&thread.lock().unwrap().id);
+            }
+            !finished
+        });
+        drop(threads);
+
+        if self.frames.len() == AVERAGE_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(totals);
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture.frames.push(self.frame_start);
+            capture.remaining -= 1;
+            if capture.remaining == 0 {
+                capture.frames.push(frame_end);
+                let capture = self.capture.take().unwrap();
+                write_capture(capture);
+            }
+        }
+        self.frame_start = frame_end;
+    }
+
+    /// Records the next `frames` frames and writes them to `path` as a
+    /// Chrome trace, replacing a capture still in progress. Errors writing
+    /// the file are logged.
+    pub(crate) fn capture(&mut self, frames: usize, path: PathBuf) {
+        self.capture = (frames > 0).then(|| Capture {
+            remaining: frames,
+            path,
+            events: Vec::new(),
+            threads: HashMap::new(),
+            frames: Vec::with_capacity(frames + 1),
+        });
+    }
+
+    /// Whether a capture is still recording frames.
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Per-frame averages of every scope recorded in the last 60 frames,
+    /// most expensive first.
+    pub fn averages(&self) -> Vec<CpuScopeStats> {
+        let mut totals: HashMap<&'static str, (Duration, u32)> = HashMap::new();
+        for frame in self.frames.iter() {
+            for (&name, &(duration, calls)) in frame.iter() {
+                let total = totals.entry(name).or_default();
+                total.0 += duration;
+                total.1 += calls;
+            }
+        }
+
+        let frame_count = self.frames.len().max(1) as f64;
+        let mut averages: Vec<CpuScopeStats> = totals
+            .into_iter()
+            .map(|(name, (duration, calls))| CpuScopeStats {
+                name,
+                average_ms: duration.as_secs_f64() * 1000.0 / frame_count,
+                calls_per_frame: calls as f64 / frame_count,
+            })
+            .collect();
+        averages.sort_by(|a, b| b.average_ms.total_cmp(&a.average_ms));
+        averages
+    }
+
+    /// Per-frame average of the scope called `name`, in milliseconds.
+    pub fn average_ms(&self, name: &str) -> Option<f64> {
+        self.averages()
+            .into_iter()
+            .find(|stats| stats.name == name)
+            .map(|stats| stats.average_ms)
+    }
+}
+
+fn write_capture(capture: Capture) {
+    let origin = capture.frames[0];
+    let micros = |instant: Instant| {
+        if instant >= origin {
+            (instant - origin).as_secs_f64() * 1e6
+        } else {
+            -((origin - instant).as_secs_f64() * 1e6)
+        }
+    };
+
+    // Frames get a row of their own above the threads' scopes.
+    let frame_row = (TRACE_PID, 0);
+    let thread_row = |id: u64| (TRACE_PID, id + 1);
+
+    let mut trace = ChromeTrace::default();
+    trace.process_name(TRACE_PID, "CPU");
+    for (&id, name) in capture.threads.iter() {
+        trace.thread_name(thread_row(id), name);
+    }
+    trace.thread_name(frame_row, "Frames");
+    for (index, bounds) in capture.frames.windows(2).enumerate() {
+        trace.complete(
+            &format!("frame {}", index),
+            "frame",
+            frame_row,
+            micros(bounds[0]),
+            micros(bounds[1]) - micros(bounds[0]),
+            None,
+        );
+    }
+    for (thread, event) in capture.events.iter() {
+        trace.complete(
+            event.name,
+            "cpu",
+            thread_row(*thread),
+            micros(event.start),
+            event.duration.as_secs_f64() * 1e6,
+            None,
+        );
+    }
+
+    match std::fs::write(&capture.path, trace.to_json()) {
+        Ok(()) => log::info!(
+            "Wrote CPU trace of {} frames to {}.",
+            capture.frames.len() - 1,
+            capture.path.display()
+        ),
+        Err(err) => log::error!(
+            "Failed to write CPU trace to {}: {}",
+            capture.path.display(),
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    /// Profilers collect every thread's events and drop exited threads, so
+    /// tests collecting them run one at a time.
+    static COLLECTING: Mutex<()> = Mutex::new(());
+
+    fn collecting() -> std::sync::MutexGuard<'static, ()> {
+        COLLECTING.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a scope called `name` that took `ms` on the calling thread.
+    fn record(name: &'static str, ms: u64) {
+        let event = CpuEvent {
+            name,
+            start: Instant::now(),
+            duration: Duration::from_millis(ms),
+        };
+        EVENTS.with(|events| events.lock().unwrap().push(event));
+    }
+
+    fn stats(profiler: &CpuProfiler, name: &str) -> Option<CpuScopeStats> {
+        profiler
+            .averages()
+            .into_iter()
+            .find(|stats| stats.name == name)
+    }
+
+    #[test]
+    fn reads_overflowed_rings() {
+        let _collecting = collecting();
+        let mut profiler = CpuProfiler::new();
+
+        for _ in 0..RING_CAPACITY + 10 {
+            record("test overflow", 0);
+        }
+        profiler.end_frame();
+        assert_eq!(
+            stats(&profiler, "test overflow").unwrap().calls_per_frame,
+            RING_CAPACITY as f64
+        );
+
+        for _ in 0..5 {
+            record("test overflow", 0);
+        }
+        profiler.end_frame();
+        assert_eq!(
+            stats(&profiler, "test overflow").unwrap().calls_per_frame,
+            (RING_CAPACITY + 5) as f64 / 2.0
+        );
+    }
+
+    #[test]
+    fn averages_recent_frames() {
+        let _collecting = collecting();
+        let mut profiler = CpuProfiler::new();
+
+        record("test average", 4);
+        profiler.end_frame();
+        profiler.end_frame();
+        let average = stats(&profiler, "test average").unwrap();
+        assert_eq!(average.average_ms, 2.0);
+        assert_eq!(average.calls_per_frame, 0.5);
+
+        // Only the last `AVERAGE_FRAMES` frames count.
+        for _ in 0..10 {
+            record("test average", 100);
+            profiler.end_frame();
+        }
+        for _ in 0..AVERAGE_FRAMES {
+            record("test average", 1);
+            record("test average", 2);
+            profiler.end_frame();
+        }
+        let average = stats(&profiler, "test average").unwrap();
+        assert_eq!(average.average_ms, 3.0);
+        assert_eq!(average.calls_per_frame, 2.0);
+        assert_eq!(profiler.average_ms("test average"), Some(3.0));
+        assert_eq!(profiler.average_ms("test missing"), None);
+    }
+
+    #[test]
+    fn sorts_averages_by_cost() {
+        let _collecting = collecting();
+        let mut profiler = CpuProfiler::new();
+
+        record("test cheap", 1);
+        record("test expensive", 5);
+        profiler.end_frame();
+
+        let names: Vec<&str> = profiler
+            .averages()
+            .into_iter()
+            .map(|stats| stats.name)
+            .filter(|&name| name == "test cheap" || name == "test expensive")
+            .collect();
+        assert_eq!(names, vec!["test expensive", "test cheap"]);
+    }
+
+    #[test]
+    fn collects_exited_threads_once() {
+        let _collecting = collecting();
+        let mut profiler = CpuProfiler::new();
+
+        let id = thread::spawn(|| {
+            record("test exited", 1);
+            record("test exited", 1);
+            EVENTS.with(|events| events.lock().unwrap().id)
+        })
+        .join()
+        .unwrap();
+
+        let registered = |id| {
+            THREADS
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|thread| thread.lock().unwrap().id == id)
+        };
+        assert!(registered(id));
+
+        profiler.end_frame();
+        assert_eq!(
+            stats(&profiler, "test exited").unwrap().calls_per_frame,
+            2.0
+        );
+        assert!(!registered(id));
+        assert!(!profiler.cursors.contains_key(&id));
+    }
+
+    #[test]
+    fn writes_captures() {
+        let _collecting = collecting();
+        let mut profiler = CpuProfiler::new();
+        let path = env::temp_dir().join(format!("vre-cpu-trace-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        profiler.capture(0, path.clone());
+        assert!(!profiler.is_capturing());
+
+        profiler.capture(2, path.clone());
+        record("test captured", 1);
+        profiler.end_frame();
+        assert!(profiler.is_capturing());
+        assert!(!path.exists());
+
+        profiler.end_frame();
+        assert!(!profiler.is_capturing());
+
+        let trace: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let names: Vec<&str> = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| event["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"frame 0"));
+        assert!(names.contains(&"frame 1"));
+        assert!(!names.contains(&"frame 2"));
+        assert!(names.contains(&"test captured"));
+    }
+}
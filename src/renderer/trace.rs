@@ -0,0 +1,76 @@
+use serde::Serialize;
+
+/// Events in the Chrome trace event format, as read by `chrome://tracing`
+/// and Perfetto. Timestamps and durations are in microseconds.
+#[derive(Default, Serialize)]
+pub(crate) struct ChromeTrace {
+    #[serde(rename = "traceEvents")]
+    events: Vec<TraceEvent>,
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+impl ChromeTrace {
+    /// Adds a span starting at `ts` and lasting `dur`. Spans on the same
+    /// thread nest when one contains the other.
+    pub fn complete(
+        &mut self,
+        name: &str,
+        category: &'static str,
+        (pid, tid): (u32, u64),
+        ts: f64,
+        dur: f64,
+        args: Option<serde_json::Value>,
+    ) {
+        self.events.push(TraceEvent {
+            name: name.to_string(),
+            cat: category,
+            ph: "X",
+            ts: Some(ts),
+            dur: Some(dur),
+            pid,
+            tid,
+            args,
+        });
+    }
+
+    /// Names the process `pid` in the viewer.
+    pub fn process_name(&mut self, pid: u32, name: &str) {
+        self.metadata("process_name", (pid, 0), name);
+    }
+
+    /// Names the thread `tid` of process `pid` in the viewer.
+    pub fn thread_name(&mut self, (pid, tid): (u32, u64), name: &str) {
+        self.metadata("thread_name", (pid, tid), name);
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Chrome traces are always serializable.")
+    }
+
+    fn metadata(&mut self, kind: &str, (pid, tid): (u32, u64), name: &str) {
+        self.events.push(TraceEvent {
+            name: kind.to_string(),
+            cat: "__metadata",
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid,
+            tid,
+            args: Some(serde_json::json!({ "name": name })),
+        });
+    }
+}
@@ -1,238 +1,1248 @@
-use std::{error::Error, ffi::CString, ptr};
-use std::{ffi::CStr, os::raw::c_void};
+use std::{ffi::CString, os::raw::c_void, path::PathBuf, ptr, sync::Arc};
 
 use ash::extensions::ext::DebugUtils;
 use ash::{
     extensions,
-    version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
+    version::{DeviceV1_0, EntryV1_0},
     vk, Device, Entry, Instance,
 };
 
 use winit::window::Window;
 
+use crate::renderer::profiler::{cpu_scope, CpuProfiler};
+use crate::renderer::{RendererConfig, VreError};
+
+use self::allocator::Allocator;
+use self::commands::submit_one_time;
+use self::debug::{DebugMessenger, DebugState};
+use self::descriptor::{DescriptorAllocator, DescriptorLayoutCache};
+use self::frame::FrameRing;
+use self::graph::{CompiledGraph, GraphRecorder, OutputTarget};
+use self::headless::{OffscreenTarget, OFFSCREEN_FORMAT, OFFSCREEN_USAGE};
+use self::owned::{OwnedCommandPool, OwnedDevice, OwnedInstance};
+use self::pipeline::{PipelineContext, PipelineDesc, PipelineManager};
+use self::pipeline_cache::PipelineCache;
+use self::query::QueryResults;
+use self::render_pass::RenderPassCache;
 use self::swapchain::SwapchainBundle;
+use self::texture::SamplerCache;
+use self::timestamps::TimestampLimits;
 
+mod allocator;
+mod buffer;
+mod commands;
+mod debug;
+mod descriptor;
 mod device;
+mod frame;
+mod graph;
+mod graph_dump;
+mod headless;
+mod owned;
+mod pipeline;
+mod pipeline_cache;
+mod query;
+mod reflect;
+mod render_pass;
+mod selection;
+mod shader;
 mod swapchain;
+mod texture;
+mod timestamps;
+mod transient;
 
-pub const APPLICATION_VERSION: u32 = vk::make_version(1, 0, 0);
-pub const ENGINE_VERSION: u32 = vk::make_version(1, 0, 0);
-pub const API_VERSION: u32 = vk::make_version(1, 0, 92);
-pub const VALIDATION_LAYERS: [&'static str; 1] = ["VK_LAYER_KHRONOS_validation"];
-pub const REQUIRED_DEVICE_EXTENSIONS: [&'static str; 1] = ["VK_KHR_swapchain"];
-
-unsafe extern "system" fn vulkan_debug_utils_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
-) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
-    };
-    let types = match message_type {
-        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
-        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
-        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
-        _ => "[Unknown]",
-    };
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-    println!("[Debug]{}{}{:?}", severity, types, message);
-
-    vk::FALSE
-}
+pub use self::allocator::HeapStats;
+pub use self::buffer::{Buffer, BufferUsage};
+pub use self::debug::ValidationCounters;
+pub use self::descriptor::{DescriptorSet, DescriptorSetBuilder};
+pub use self::graph::{
+    BufferAccess, BufferHandle, ImageDesc, ImageHandle, ImageSize, LoadOp, PassBuilder,
+    PassContext, RenderGraph,
+};
+pub use self::graph_dump::RenderGraphDump;
+pub use self::headless::FrameCapture;
+pub use self::pipeline::{ComputePipelineDesc, GraphicsPipelineDesc, PipelineId};
+pub use self::query::{PipelineStatistics, QueryResult, QuerySupport};
+pub use self::selection::DeviceOverride;
+pub use self::shader::{PipelineLayout, ShaderModule};
+pub use self::texture::{SamplerDesc, Texture, TextureDesc, TextureKind};
+pub use self::timestamps::{GpuFrameTimings, GpuProfiler, GpuScope};
+pub use self::transient::TransientMemoryReport;
 
+pub const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+#[derive(Clone, Copy, Debug)]
 pub struct QueueFamilyIndices {
     graphics_family: Option<u32>,
     present_family: Option<u32>,
+    /// Family supporting transfers but neither graphics nor compute, usually
+    /// backed by a dedicated DMA engine.
+    dedicated_transfer_family: Option<u32>,
+    /// Family supporting compute but not graphics, for async compute.
+    dedicated_compute_family: Option<u32>,
 }
 
 impl QueueFamilyIndices {
+    /// Family uploads are submitted to: the dedicated transfer family if there
+    /// is one, otherwise the graphics family.
+    pub fn transfer_family(&self) -> Option<u32> {
+        self.dedicated_transfer_family.or(self.graphics_family)
+    }
+
+    /// Family compute work is submitted to: the dedicated compute family if
+    /// there is one, otherwise the graphics family.
+    pub fn compute_family(&self) -> Option<u32> {
+        self.dedicated_compute_family.or(self.graphics_family)
+    }
+
+    /// Every distinct family a queue is created from.
+    pub fn unique_families(&self) -> Vec<u32> {
+        QueueFamilyIndices::distinct(&[
+            self.graphics_family,
+            self.present_family,
+            self.dedicated_transfer_family,
+            self.dedicated_compute_family,
+        ])
+    }
+
+    /// Families device-local buffers are accessed from: uploads on the
+    /// transfer family, drawing on the graphics family and dispatches on the
+    /// compute family. Presentation never touches buffers.
+    pub fn buffer_families(&self) -> Vec<u32> {
+        QueueFamilyIndices::distinct(&[
+            self.graphics_family,
+            self.dedicated_transfer_family,
+            self.dedicated_compute_family,
+        ])
+    }
+
+    fn distinct(families: &[Option<u32>]) -> Vec<u32> {
+        let mut distinct = Vec::new();
+
+        for family in families.iter().flatten() {
+            if !distinct.contains(family) {
+                distinct.push(*family);
+            }
+        }
+
+        distinct
+    }
+
     pub fn is_complete(&self) -> bool {
         self.graphics_family.is_some() && self.present_family.is_some()
     }
+
+    /// Headless rendering never presents, so only a graphics queue is needed.
+    pub fn is_complete_headless(&self) -> bool {
+        self.graphics_family.is_some()
+    }
 }
 
 pub struct SurfaceBundle {
-    #[allow(dead_code)]
     surface_loader: extensions::khr::Surface,
-    #[allow(dead_code)]
     surface: vk::SurfaceKHR,
 }
 
+impl Drop for SurfaceBundle {
+    fn drop(&mut self) {
+        unsafe {
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+}
+
+/// Owns every Vulkan object used for rendering. Fields are dropped in
+/// declaration order, so objects are listed before whatever they were created
+/// from: device children, then the device, then surface and debug messenger
+/// and finally the instance. The device and instance are shared with the
+/// buffers, textures, shader modules and pipeline layouts handed out to
+/// users, and destroyed once the last of those is dropped.
 pub struct VulkanBackend {
-    _entry: Entry,
-    #[allow(dead_code)]
-    instance: Instance,
-    #[allow(dead_code)]
-    surface_bundle: SurfaceBundle,
+    frames: FrameRing,
+    render_passes: RenderPassCache,
+    /// Format of the image frames are rendered into, known even while a
+    /// windowed backend has no swapchain.
+    output_format: vk::Format,
+    offscreen_target: Option<OffscreenTarget>,
+    /// `None` when headless, or while a windowed backend has a zero-sized
+    /// surface.
+    swapchain_bundle: Option<SwapchainBundle>,
+    /// Set when the swapchain no longer matches the surface and must be
+    /// rebuilt before the next image is acquired.
+    swapchain_dirty: bool,
+    window_extent: vk::Extent2D,
+    clear_color: [f32; 4],
+    /// Transient memory used by the last frame's render graph.
+    transient_report: TransientMemoryReport,
+    /// Set until the next recorded graph has been dumped into `graph_dump`.
+    graph_dump_requested: bool,
+    graph_dump: Option<RenderGraphDump>,
+    /// Timings of the frames that have finished on the GPU.
+    gpu_profiler: GpuProfiler,
+    cpu_profiler: CpuProfiler,
+    query_support: QuerySupport,
+    /// Latest results of the frames' pipeline statistics and occlusion
+    /// queries.
+    query_results: QueryResults,
+    config: RendererConfig,
+    command_pool: OwnedCommandPool,
+    /// Pool for upload command buffers, created on the transfer family.
+    transfer_command_pool: OwnedCommandPool,
+    /// Pool for `run_compute` command buffers, created on the compute family.
+    compute_command_pool: OwnedCommandPool,
+    /// Pipelines built against `render_passes`, rebuilt when the output
+    /// format changes.
+    pipelines: PipelineManager,
+    sampler_cache: SamplerCache,
+    /// Saved to `config.pipeline_cache` when dropped.
+    pipeline_cache: PipelineCache,
+    /// Allocates descriptor sets that live as long as the backend.
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_layout_cache: DescriptorLayoutCache,
+    /// Shared with every resource holding an allocation. Keeps the device
+    /// alive until the last of them is dropped.
+    allocator: Arc<Allocator>,
 
-    #[allow(dead_code)]
-    debug_utils_loader: ash::extensions::ext::DebugUtils,
-    #[allow(dead_code)]
-    debug_messenger: vk::DebugUtilsMessengerEXT,
-    #[allow(dead_code)]
-    physical_device: vk::PhysicalDevice,
-    #[allow(dead_code)]
-    logical_device: Device,
-    #[allow(dead_code)]
     graphics_queue: vk::Queue,
-    #[allow(dead_code)]
     present_queue: vk::Queue,
+    /// Queue for uploads, which is the graphics queue unless the device has a
+    /// dedicated transfer family.
+    transfer_queue: vk::Queue,
+    /// Queue for async compute, which is the graphics queue unless the device
+    /// has a dedicated compute family.
+    compute_queue: vk::Queue,
+    logical_device: Arc<OwnedDevice>,
+    queue_family_indices: QueueFamilyIndices,
+    physical_device: vk::PhysicalDevice,
 
+    surface_bundle: Option<SurfaceBundle>,
+    /// `None` when validation layers are unavailable.
     #[allow(dead_code)]
-    swapchain_bundle: SwapchainBundle,
+    debug_messenger: Option<DebugMessenger>,
+    instance: Arc<OwnedInstance>,
+    /// Referenced by the debug callback, kept alive by the instance until it
+    /// is destroyed.
+    debug_state: Arc<DebugState>,
+}
+
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(err) = self.logical_device.device_wait_idle() {
+                log::error!("Failed to wait for device idle during teardown: {}", err);
+            }
+        }
+    }
 }
 
 impl VulkanBackend {
-    pub fn new(window: &Window) -> VulkanBackend {
-        let entry = VulkanBackend::create_entry();
-        let instance =
-            VulkanBackend::create_instance(&entry, window).expect("Could not create VK Instance.");
-        let (debug_utils_loader, debug_messenger) =
-            VulkanBackend::setup_debug_utils(&entry, &instance);
-        let surface_bundle = VulkanBackend::create_surface_bundle(&entry, &instance, &window)
-            .expect("Could not create SurfaceBundle.");
-        let physical_device = VulkanBackend::get_physical_device(&instance, &surface_bundle);
-        let (logical_device, indices) =
-            device::create_logical_device(&instance, physical_device, &surface_bundle);
-
-        let graphics_queue =
-            unsafe { logical_device.get_device_queue(indices.graphics_family.unwrap(), 0) };
-        let present_queue =
-            unsafe { logical_device.get_device_queue(indices.present_family.unwrap(), 0) };
-
-        let swapchain_bundle = SwapchainBundle::new(
+    pub fn new(window: &Window, config: RendererConfig) -> Result<VulkanBackend, VreError> {
+        let size = window.inner_size();
+        let extent = vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        };
+
+        VulkanBackend::create(Some(window), extent, config)
+    }
+
+    /// Creates a backend without a window, surface or swapchain. Frames are
+    /// rendered into an offscreen color image of the given size.
+    pub fn new_headless(
+        width: u32,
+        height: u32,
+        config: RendererConfig,
+    ) -> Result<VulkanBackend, VreError> {
+        VulkanBackend::create(None, vk::Extent2D { width, height }, config)
+    }
+
+    /// Creates a backend presenting to `window`, or rendering offscreen at
+    /// `extent` when there is no window.
+    fn create(
+        window: Option<&Window>,
+        extent: vk::Extent2D,
+        config: RendererConfig,
+    ) -> Result<VulkanBackend, VreError> {
+        // Locals are dropped in reverse order if initialization fails
+        // halfway through, which tears down whatever was already created.
+        let entry = VulkanBackend::create_entry()?;
+        // Configured before the instance exists, whose creation is reported
+        // through the same state.
+        let debug_state = DebugState::new(config.validation_min_level);
+        for &message_id in config.suppressed_validation_ids.iter() {
+            debug_state.suppress_message_id(message_id);
+        }
+        let validation_enabled =
+            config.validation && VulkanBackend::check_validation_layer_support(&entry)?;
+        if config.validation && !validation_enabled {
+            log::warn!("Validation layers requested, but not available!");
+        }
+
+        let instance = Arc::new(OwnedInstance::new(
+            &entry,
+            VulkanBackend::create_instance(
+                &entry,
+                window,
+                &config,
+                validation_enabled.then_some(&debug_state),
+            )?,
+            &debug_state,
+        ));
+        let debug_messenger = if validation_enabled {
+            Some(DebugMessenger::new(&entry, &instance, &debug_state)?)
+        } else {
+            None
+        };
+        let surface_bundle = window
+            .map(|window| VulkanBackend::create_surface_bundle(&entry, &instance, window))
+            .transpose()?;
+        let device_extensions = device::required_extensions(&config, surface_bundle.is_some());
+        let device_override = DeviceOverride::from_env().or_else(|| config.device.clone());
+        let physical_device = selection::pick_physical_device(
+            &instance,
+            surface_bundle.as_ref(),
+            &device_extensions,
+            device_override.as_ref(),
+        )?;
+        let (logical_device, indices, query_support) = device::create_logical_device(
             &instance,
-            &logical_device,
             physical_device,
-            &surface_bundle,
-            indices,
+            surface_bundle.as_ref(),
+            &device_extensions,
+        )?;
+        let logical_device = Arc::new(OwnedDevice::new(logical_device, &instance));
+
+        log::info!(
+            "Using queue families: graphics {:?}, present {:?}, transfer {:?}, compute {:?}.",
+            indices.graphics_family,
+            indices.present_family,
+            indices.transfer_family(),
+            indices.compute_family()
         );
 
-        VulkanBackend {
-            _entry: entry,
-            instance,
-            surface_bundle,
-            debug_utils_loader,
-            debug_messenger,
+        // The transfer and compute families fall back to the graphics family,
+        // which every picked device has.
+        let graphics_family = indices.graphics_family.ok_or(VreError::NoSuitableDevice)?;
+        let transfer_family = indices.transfer_family().unwrap_or(graphics_family);
+        let compute_family = indices.compute_family().unwrap_or(graphics_family);
+        let graphics_queue = unsafe { logical_device.get_device_queue(graphics_family, 0) };
+        let present_queue = match indices.present_family {
+            Some(present_family) => unsafe { logical_device.get_device_queue(present_family, 0) },
+            None => vk::Queue::null(),
+        };
+        let transfer_queue = unsafe { logical_device.get_device_queue(transfer_family, 0) };
+        let compute_queue = unsafe { logical_device.get_device_queue(compute_family, 0) };
+
+        let allocator = Arc::new(Allocator::new(&instance, &logical_device, physical_device));
+        let sampler_cache = SamplerCache::new(&logical_device);
+        let pipeline_cache = PipelineCache::new(
+            &instance,
+            &logical_device,
             physical_device,
-            logical_device,
+            config.pipeline_cache.as_deref(),
+        )?;
+        let descriptor_allocator = DescriptorAllocator::new(&logical_device);
+        let descriptor_layout_cache = DescriptorLayoutCache::new(&logical_device);
+
+        let offscreen_target = match window {
+            Some(_) => None,
+            None => Some(OffscreenTarget::new(&logical_device, &allocator, extent)?),
+        };
+        let swapchain_bundle = match surface_bundle.as_ref() {
+            Some(surface_bundle) => SwapchainBundle::new(
+                &instance,
+                &logical_device,
+                physical_device,
+                surface_bundle,
+                &indices,
+                extent,
+                &config,
+                vk::SwapchainKHR::null(),
+            )?,
+            None => None,
+        };
+        let swapchain_dirty = surface_bundle.is_some() && swapchain_bundle.is_none();
+
+        // Without a swapchain yet, the format it will be created with is
+        // used so pipelines can be built against it.
+        let output_format = match (&offscreen_target, &swapchain_bundle, &surface_bundle) {
+            (Some(offscreen_target), _, _) => offscreen_target.format,
+            (None, Some(swapchain_bundle), _) => swapchain_bundle.swapchain_format,
+            (None, None, Some(surface_bundle)) => {
+                swapchain::SwapchainSupportDetails::new(physical_device, surface_bundle)?
+                    .choose_format(config.surface_format)?
+                    .format
+            }
+            (None, None, None) => OFFSCREEN_FORMAT,
+        };
+        let render_passes = RenderPassCache::new(&logical_device);
+
+        let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(graphics_family)
+            .build();
+        let command_pool = OwnedCommandPool::new(&logical_device, &command_pool_create_info)?;
+        let transfer_command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(transfer_family)
+            .build();
+        let transfer_command_pool =
+            OwnedCommandPool::new(&logical_device, &transfer_command_pool_create_info)?;
+        let compute_command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(compute_family)
+            .build();
+        let compute_command_pool =
+            OwnedCommandPool::new(&logical_device, &compute_command_pool_create_info)?;
+        let timestamp_limits = TimestampLimits::new(&instance, physical_device, graphics_family);
+        let frames = FrameRing::new(
+            &logical_device,
+            &allocator,
+            *command_pool,
+            timestamp_limits,
+            query_support,
+            config.frames_in_flight,
+            VulkanBackend::output_image_count(offscreen_target.as_ref(), swapchain_bundle.as_ref()),
+        )?;
+
+        Ok(VulkanBackend {
+            frames,
+            render_passes,
+            output_format,
+            offscreen_target,
+            swapchain_bundle,
+            swapchain_dirty,
+            window_extent: extent,
+            clear_color: CLEAR_COLOR,
+            transient_report: TransientMemoryReport::default(),
+            graph_dump_requested: false,
+            graph_dump: None,
+            gpu_profiler: GpuProfiler::new(timestamp_limits),
+            cpu_profiler: CpuProfiler::new(),
+            query_support,
+            query_results: QueryResults::default(),
+            config,
+            command_pool,
+            transfer_command_pool,
+            compute_command_pool,
+            pipelines: PipelineManager::new(),
+            sampler_cache,
+            pipeline_cache,
+            descriptor_allocator,
+            descriptor_layout_cache,
+            allocator,
             graphics_queue,
             present_queue,
-            swapchain_bundle,
+            transfer_queue,
+            compute_queue,
+            logical_device,
+            queue_family_indices: indices,
+            physical_device,
+            surface_bundle,
+            debug_messenger,
+            instance,
+            debug_state,
+        })
+    }
+
+    /// Number of images frames can be rendered into: the swapchain images,
+    /// or the offscreen target.
+    fn output_image_count(
+        offscreen_target: Option<&OffscreenTarget>,
+        swapchain_bundle: Option<&SwapchainBundle>,
+    ) -> usize {
+        match (offscreen_target, swapchain_bundle) {
+            (Some(_), _) => 1,
+            (None, Some(swapchain_bundle)) => swapchain_bundle.swapchain_images.len(),
+            (None, None) => 0,
         }
     }
 
-    pub fn get_physical_device(
-        instance: &Instance,
-        surface_bundle: &SurfaceBundle,
-    ) -> vk::PhysicalDevice {
-        VulkanBackend::devices(instance)
-            .expect("Could not fetch devices.")
-            .into_iter()
-            .find(|device| VulkanBackend::is_device_suitable(instance, *device, surface_bundle))
-            .expect("Could not find a suitable PhysicalDevice!")
+    /// Sets the color `draw_frame` clears the output image to.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
     }
 
-    pub fn devices(instance: &Instance) -> Result<Vec<vk::PhysicalDevice>, Box<dyn Error>> {
-        let devices = unsafe { instance.enumerate_physical_devices()? };
-        Ok(devices)
+    /// Warnings and errors reported by the validation layers so far. Always
+    /// zero when validation layers are unavailable.
+    pub fn validation_counters(&self) -> ValidationCounters {
+        self.debug_state.counters()
     }
 
-    fn is_device_suitable(
-        instance: &Instance,
-        physical_device: vk::PhysicalDevice,
-        surface_bundle: &SurfaceBundle,
-    ) -> bool {
-        let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
-        let is_required_extensions_supported =
-            device::check_device_extension_support(instance, physical_device);
-        let is_swapchain_supported = if is_required_extensions_supported {
-            let swapchain_details =
-                swapchain::SwapchainSupportDetails::new(physical_device, surface_bundle);
-            !swapchain_details.formats.is_empty() && !swapchain_details.present_modes.is_empty()
+    /// Sets the least severe validation message still forwarded to `log`.
+    pub fn set_validation_min_level(&self, min_level: log::Level) {
+        self.debug_state.set_min_level(min_level);
+    }
+
+    /// Ignores validation messages with the given `messageIdNumber`.
+    pub fn suppress_validation_message(&self, message_id: i32) {
+        self.debug_state.suppress_message_id(message_id);
+    }
+
+    /// Memory usage of every memory heap of the device.
+    pub fn memory_stats(&self) -> Vec<HeapStats> {
+        self.allocator.heap_stats()
+    }
+
+    /// Memory taken by the transient images and buffers of the last frame's
+    /// render graph.
+    pub fn transient_memory_report(&self) -> TransientMemoryReport {
+        self.transient_report
+    }
+
+    /// GPU timings of the last finished frames.
+    pub fn gpu_profiler(&self) -> &GpuProfiler {
+        &self.gpu_profiler
+    }
+
+    /// Averages of the CPU scopes recorded in the last frames.
+    pub fn cpu_profiler(&self) -> &CpuProfiler {
+        &self.cpu_profiler
+    }
+
+    /// Writes the CPU scopes of the next `frames` frames to `path` as a
+    /// Chrome trace.
+    pub fn capture_cpu_trace(&mut self, frames: usize, path: PathBuf) {
+        self.cpu_profiler.capture(frames, path);
+    }
+
+    /// Which pipeline statistics and occlusion query features the device
+    /// has enabled.
+    pub fn query_support(&self) -> QuerySupport {
+        self.query_support
+    }
+
+    /// Pipeline statistics of the latest finished frame that recorded a
+    /// query called `name`.
+    pub fn pipeline_statistics(&self, name: &str) -> Option<QueryResult<PipelineStatistics>> {
+        self.query_results.pipeline_statistics(name)
+    }
+
+    /// Samples that passed in the latest finished frame that recorded the
+    /// occlusion query `key`.
+    pub fn occlusion_samples(&self, key: u64) -> Option<QueryResult<u64>> {
+        self.query_results.occlusion(key)
+    }
+
+    /// Dumps the next recorded render graph, see `take_graph_dump`.
+    pub fn request_graph_dump(&mut self) {
+        self.graph_dump_requested = true;
+    }
+
+    /// The render graph dumped since `request_graph_dump` was called, if a
+    /// frame has been recorded since.
+    pub fn take_graph_dump(&mut self) -> Option<RenderGraphDump> {
+        self.graph_dump.take()
+    }
+
+    /// Creates a device-local buffer holding `data`, uploaded through a
+    /// staging buffer on the transfer queue.
+    pub fn create_buffer<T: Copy>(
+        &self,
+        usage: BufferUsage,
+        data: &[T],
+    ) -> Result<Buffer<T>, VreError> {
+        Buffer::with_data(
+            &self.logical_device,
+            &self.allocator,
+            usage,
+            data,
+            *self.transfer_command_pool,
+            self.transfer_queue,
+            &self.queue_family_indices.buffer_families(),
+        )
+    }
+
+    /// Creates a persistently mapped, host-visible buffer of `len` elements.
+    pub fn create_mapped_buffer<T: Copy>(
+        &self,
+        usage: BufferUsage,
+        len: usize,
+    ) -> Result<Buffer<T>, VreError> {
+        Buffer::mapped(&self.logical_device, &self.allocator, usage, len)
+    }
+
+    /// Creates a texture without contents, in `UNDEFINED` layout.
+    pub fn create_empty_texture(&self, desc: &TextureDesc) -> Result<Texture, VreError> {
+        Texture::new(
+            &self.instance,
+            &self.logical_device,
+            self.physical_device,
+            &self.allocator,
+            desc,
+        )
+    }
+
+    /// Creates a texture and uploads `data` on the graphics queue, which
+    /// mip generation needs for blits.
+    pub fn create_texture(&self, desc: &TextureDesc, data: &[u8]) -> Result<Texture, VreError> {
+        let mut texture = self.create_empty_texture(desc)?;
+        texture.upload(data, *self.command_pool, self.graphics_queue)?;
+
+        Ok(texture)
+    }
+
+    /// Records commands with `record` and submits them to the compute
+    /// queue, blocking until they have finished.
+    pub fn run_compute<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &self,
+        record: F,
+    ) -> Result<(), VreError> {
+        let device: &Device = &self.logical_device;
+        submit_one_time(
+            device,
+            *self.compute_command_pool,
+            self.compute_queue,
+            |command_buffer| record(device, command_buffer),
+        )
+    }
+
+    /// Returns the shared sampler for `desc`.
+    pub fn sampler(&self, desc: &SamplerDesc) -> Result<vk::Sampler, VreError> {
+        self.sampler_cache.get(desc)
+    }
+
+    /// Allocates and writes a descriptor set that lives as long as the
+    /// backend.
+    pub fn create_descriptor_set(
+        &mut self,
+        builder: &DescriptorSetBuilder,
+    ) -> Result<DescriptorSet, VreError> {
+        builder.build(
+            &self.logical_device,
+            &self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+        )
+    }
+
+    /// Allocates and writes a descriptor set from the pool of the frame
+    /// recorded next. The set is freed once that frame context is reused.
+    pub fn frame_descriptor_set(
+        &mut self,
+        builder: &DescriptorSetBuilder,
+    ) -> Result<DescriptorSet, VreError> {
+        let allocator = self.frames.current_descriptors()?;
+
+        builder.build(
+            &self.logical_device,
+            &self.descriptor_layout_cache,
+            allocator,
+        )
+    }
+
+    /// Creates a shader module from SPIR-V and reflects its interface.
+    pub fn create_shader_module(&self, spirv: &[u8]) -> Result<ShaderModule, VreError> {
+        ShaderModule::new(&self.logical_device, spirv)
+    }
+
+    /// Creates the pipeline layout for a pipeline made of `stages`.
+    pub fn create_pipeline_layout(
+        &self,
+        stages: &[&ShaderModule],
+    ) -> Result<PipelineLayout, VreError> {
+        PipelineLayout::new(&self.logical_device, &self.descriptor_layout_cache, stages)
+    }
+
+    pub fn create_graphics_pipeline(
+        &mut self,
+        desc: &GraphicsPipelineDesc,
+    ) -> Result<PipelineId, VreError> {
+        self.create_pipeline(PipelineDesc::Graphics(desc.clone()))
+    }
+
+    pub fn create_compute_pipeline(
+        &mut self,
+        desc: &ComputePipelineDesc,
+    ) -> Result<PipelineId, VreError> {
+        self.create_pipeline(PipelineDesc::Compute(desc.clone()))
+    }
+
+    pub fn pipeline(&self, id: PipelineId) -> vk::Pipeline {
+        self.pipelines.pipeline(id)
+    }
+
+    pub fn pipeline_layout(&self, id: PipelineId) -> &PipelineLayout {
+        self.pipelines.layout(id)
+    }
+
+    pub fn pipeline_error(&self, id: PipelineId) -> Option<&VreError> {
+        self.pipelines.error(id)
+    }
+
+    fn create_pipeline(&mut self, desc: PipelineDesc) -> Result<PipelineId, VreError> {
+        let context = PipelineContext {
+            device: &self.logical_device,
+            layout_cache: &self.descriptor_layout_cache,
+            cache: self.pipeline_cache.handle(),
+            render_passes: &self.render_passes,
+            output_format: self.output_format,
+        };
+
+        self.pipelines.create(&context, desc)
+    }
+
+    /// Rebuilds pipelines whose shader files changed, swapping them in once
+    /// every frame in flight has finished.
+    fn reload_shaders(&mut self) -> Result<(), VreError> {
+        let changed = self.pipelines.changed_shaders();
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let context = PipelineContext {
+            device: &self.logical_device,
+            layout_cache: &self.descriptor_layout_cache,
+            cache: self.pipeline_cache.handle(),
+            render_passes: &self.render_passes,
+            output_format: self.output_format,
+        };
+        let frames = &self.frames;
+
+        self.pipelines
+            .reload(&context, &changed, || frames.wait_all())
+    }
+
+    /// Rebuilds the frame ring so up to `frames_in_flight` frames can be
+    /// recorded while the GPU is still working on earlier ones.
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) -> Result<(), VreError> {
+        if frames_in_flight == self.frames.len() {
+            return Ok(());
+        }
+
+        // Results of the frames in flight would be lost with their contexts.
+        for frame in self.frames.resolve_all()? {
+            if let Some(timings) = frame.timestamps.take_results() {
+                self.gpu_profiler.push(timings);
+            }
+            if let Some(results) = frame.queries.take_results() {
+                self.query_results.update(results);
+            }
+        }
+        self.frames = FrameRing::new(
+            &self.logical_device,
+            &self.allocator,
+            *self.command_pool,
+            self.gpu_profiler.limits(),
+            self.query_support,
+            frames_in_flight,
+            self.image_count(),
+        )?;
+
+        Ok(())
+    }
+
+    fn image_count(&self) -> usize {
+        VulkanBackend::output_image_count(
+            self.offscreen_target.as_ref(),
+            self.swapchain_bundle.as_ref(),
+        )
+    }
+
+    /// Records the new window size. The swapchain is rebuilt before the next
+    /// image is acquired.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.surface_bundle.is_none() {
+            return;
+        }
+
+        self.window_extent = vk::Extent2D { width, height };
+        self.swapchain_dirty = true;
+    }
+
+    /// Whether a windowed backend currently has nothing to render into, e.g.
+    /// because the window is minimized.
+    pub fn is_paused(&self) -> bool {
+        self.surface_bundle.is_some()
+            && (self.window_extent.width == 0 || self.window_extent.height == 0)
+    }
+
+    /// Rebuilds the swapchain for the current window extent, handing the old
+    /// swapchain to the driver so resources can be reused. Leaves the
+    /// swapchain dirty while the surface has a zero-sized extent.
+    fn recreate_swapchain(&mut self) -> Result<(), VreError> {
+        let surface_bundle = match self.surface_bundle.as_ref() {
+            Some(surface_bundle) => surface_bundle,
+            None => return Ok(()),
+        };
+
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        unsafe {
+            self.logical_device
+                .device_wait_idle()
+                .map_err(VreError::device(
+                    "Failed to wait for device idle before swapchain recreation.",
+                ))?;
+        }
+
+        let old_swapchain = self
+            .swapchain_bundle
+            .as_ref()
+            .map_or(vk::SwapchainKHR::null(), |bundle| bundle.swapchain);
+        let swapchain_bundle = match SwapchainBundle::new(
+            &self.instance,
+            &self.logical_device,
+            self.physical_device,
+            surface_bundle,
+            &self.queue_family_indices,
+            self.window_extent,
+            &self.config,
+            old_swapchain,
+        )? {
+            Some(swapchain_bundle) => swapchain_bundle,
+            None => return Ok(()),
+        };
+
+        if swapchain_bundle.swapchain_format != self.output_format {
+            self.output_format = swapchain_bundle.swapchain_format;
+
+            let context = PipelineContext {
+                device: &self.logical_device,
+                layout_cache: &self.descriptor_layout_cache,
+                cache: self.pipeline_cache.handle(),
+                render_passes: &self.render_passes,
+                output_format: self.output_format,
+            };
+            self.pipelines.rebuild_output_pipelines(&context)?;
+        }
+
+        self.swapchain_bundle = Some(swapchain_bundle);
+        let image_count = self.image_count();
+        self.frames.reset_images(image_count);
+        self.swapchain_dirty = false;
+
+        Ok(())
+    }
+
+    /// Acquires the next swapchain image, rebuilding the swapchain first if
+    /// it is dirty. Returns `None` when there is nothing to render into this
+    /// frame, either because the window is minimized or the swapchain turned
+    /// out to be out of date.
+    /// Rebuilds the swapchain if it is dirty. Returns whether there is a
+    /// swapchain to render to.
+    fn prepare_swapchain(&mut self) -> Result<bool, VreError> {
+        if self.swapchain_dirty {
+            self.recreate_swapchain()?;
+        }
+
+        Ok(!self.swapchain_dirty && self.swapchain_bundle.is_some())
+    }
+
+    /// Acquires the next swapchain image, signaling `semaphore`. Call
+    /// `prepare_swapchain` first.
+    fn acquire_next_image(&mut self, semaphore: vk::Semaphore) -> Result<Option<u32>, VreError> {
+        let swapchain_bundle = match self.swapchain_bundle.as_ref() {
+            Some(swapchain_bundle) => swapchain_bundle,
+            None => return Ok(None),
+        };
+
+        match swapchain_bundle.acquire_next_image(semaphore) {
+            Ok((image_index, suboptimal)) => {
+                self.swapchain_dirty |= suboptimal;
+                Ok(Some(image_index))
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.swapchain_dirty = true;
+                Ok(None)
+            }
+            Err(err) => Err(VreError::swapchain("Failed to acquire swapchain image.")(
+                err,
+            )),
+        }
+    }
+
+    /// Consumes the signal of the current frame's acquire semaphore after
+    /// recording failed, so it can be waited on again next time.
+    fn wait_on_image_available(&self) {
+        let wait_semaphores = [self.frames.current().image_available];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .build();
+
+        let result = unsafe {
+            self.logical_device
+                .queue_submit(self.graphics_queue, &[submit_info], vk::Fence::null())
+        };
+        if let Err(err) = result {
+            log::error!("Failed to release the acquire semaphore: {}", err);
+        }
+    }
+
+    /// Presents `image_index` on the present queue. Out of date and
+    /// suboptimal swapchains are rebuilt before the next acquire.
+    fn present(
+        &mut self,
+        image_index: u32,
+        wait_semaphores: &[vk::Semaphore],
+    ) -> Result<(), VreError> {
+        let swapchain_bundle = match self.swapchain_bundle.as_ref() {
+            Some(swapchain_bundle) => swapchain_bundle,
+            None => return Ok(()),
+        };
+
+        match swapchain_bundle.present(self.present_queue, image_index, wait_semaphores) {
+            Ok(suboptimal) => self.swapchain_dirty |= suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.swapchain_dirty = true,
+            Err(err) => {
+                return Err(VreError::swapchain("Failed to present swapchain image.")(
+                    err,
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders a frame that only clears the output image to the clear
+    /// color.
+    pub fn draw_frame(&mut self) -> Result<bool, VreError> {
+        let graph = self.clear_graph();
+        self.draw_graph(graph)
+    }
+
+    fn clear_graph(&self) -> RenderGraph<'static> {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_swapchain();
+        graph
+            .add_pass("clear")
+            .color_attachment(output, LoadOp::ClearColor(self.clear_color))
+            .execute(|_| {});
+
+        graph
+    }
+
+    /// Renders one frame by running `graph`. Windowed backends acquire a
+    /// swapchain image and present it, headless backends render into the
+    /// offscreen target. Returns `false` when nothing was rendered, e.g.
+    /// while minimized.
+    pub fn draw_graph(&mut self, graph: RenderGraph) -> Result<bool, VreError> {
+        let rendered = self.run_graph(graph);
+        // CPU scopes recorded since the previous frame, e.g. by the
+        // application before rendering, count towards this one.
+        self.cpu_profiler.end_frame();
+
+        rendered
+    }
+
+    fn run_graph(&mut self, mut graph: RenderGraph) -> Result<bool, VreError> {
+        if self.config.hot_reload {
+            let _scope = cpu_scope("reload shaders");
+            self.reload_shaders()?;
+        }
+
+        {
+            let _scope = cpu_scope("wait");
+            self.frames.current().wait()?;
+            let frame = self.frames.current_mut()?;
+            if let Some(timings) = frame.timestamps.take_results() {
+                self.gpu_profiler.push(timings);
+            }
+            if let Some(results) = frame.queries.take_results() {
+                self.query_results.update(results);
+            }
+        }
+
+        if self.offscreen_target.is_none() && !self.prepare_swapchain()? {
+            return Ok(false);
+        }
+
+        // Mistakes in the graph are reported before an image is acquired,
+        // which would leave the acquire semaphore signaled. Every swapchain
+        // image has the size, format and usage compiling looks at.
+        let compiled = {
+            let _scope = cpu_scope("compile");
+            graph.compile(&self.output_target(0)?)?
+        };
+
+        let image_index = if self.offscreen_target.is_some() {
+            0
         } else {
-            false
+            let _scope = cpu_scope("acquire");
+            let image_available = self.frames.current().image_available;
+
+            match self.acquire_next_image(image_available)? {
+                Some(image_index) => image_index,
+                None => return Ok(false),
+            }
+        };
+
+        let command_buffer = self.frames.current().command_buffer;
+        let recorded = {
+            let _scope = cpu_scope("record");
+            self.frames
+                .claim_image(image_index)
+                .and_then(|_| self.record_frame(graph, compiled, command_buffer, image_index))
         };
-        let is_queue_family_supported =
-            device::find_queue_family(instance, physical_device, surface_bundle).is_complete();
+        if let Err(err) = recorded {
+            if self.offscreen_target.is_none() {
+                self.wait_on_image_available();
+            }
+            return Err(err);
+        }
+
+        let submit_scope = cpu_scope("submit");
+        let frame = self.frames.current();
+
+        // Only reset the fence once work is guaranteed to be submitted,
+        // otherwise the next wait would never return.
+        unsafe {
+            self.logical_device
+                .reset_fences(&[frame.in_flight])
+                .map_err(VreError::device("Failed to reset frame fence."))?;
+        }
+
+        let is_presenting = self.offscreen_target.is_none();
+        let in_flight = frame.in_flight;
+        let command_buffers = [frame.command_buffer];
+        let wait_semaphores = [frame.image_available];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let signal_semaphores = [frame.render_finished];
+        let submit_info = if is_presenting {
+            vk::SubmitInfo::builder()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores)
+                .build()
+        } else {
+            vk::SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .build()
+        };
+
+        unsafe {
+            self.logical_device
+                .queue_submit(self.graphics_queue, &[submit_info], in_flight)
+                .map_err(VreError::device("Failed to submit frame command buffer."))?;
+        }
+        drop(submit_scope);
+
+        if is_presenting {
+            let _scope = cpu_scope("present");
+            self.present(image_index, &signal_semaphores)?;
+        }
 
-        device_properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU
-            && is_queue_family_supported
-            && is_required_extensions_supported
-            && is_swapchain_supported
+        self.frames.advance();
+
+        Ok(true)
+    }
+
+    fn record_frame(
+        &mut self,
+        graph: RenderGraph,
+        compiled: CompiledGraph,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<(), VreError> {
+        let output = self.output_target(image_index)?;
+
+        unsafe {
+            self.logical_device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .map_err(VreError::device("Failed to reset frame command buffer."))?;
+            self.logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .map_err(VreError::device("Failed to begin frame command buffer."))?;
+        }
+
+        let frame_number = self.gpu_profiler.next_frame();
+        let frame = self.frames.current_mut()?;
+        frame.timestamps.begin_frame(command_buffer, frame_number);
+        frame.queries.begin_frame(command_buffer, frame_number);
+
+        let dump = graph.record(
+            compiled,
+            &output,
+            GraphRecorder {
+                device: &self.logical_device,
+                command_buffer,
+                render_passes: &self.render_passes,
+                transients: &mut frame.transients,
+                pipelines: &self.pipelines,
+                timestamps: &frame.timestamps,
+                queries: &frame.queries,
+                dump: self.graph_dump_requested,
+            },
+        )?;
+        frame.timestamps.end_frame(command_buffer);
+        frame.queries.end_frame();
+        if dump.is_some() {
+            self.graph_dump = dump;
+            self.graph_dump_requested = false;
+        }
+        self.transient_report = frame.transients.report();
+
+        unsafe {
+            self.logical_device
+                .end_command_buffer(command_buffer)
+                .map_err(VreError::device("Failed to end frame command buffer."))
+        }
     }
 
-    fn create_entry() -> Entry {
-        Entry::new().expect("Could not create Vulkan Entry.")
+    /// The image `image_index` refers to and how it is handed over after
+    /// rendering: offscreen frames are copied out, swapchain images are
+    /// presented.
+    fn output_target(&self, image_index: u32) -> Result<OutputTarget, VreError> {
+        if let Some(offscreen_target) = self.offscreen_target.as_ref() {
+            return Ok(OutputTarget {
+                image: offscreen_target.image,
+                view: offscreen_target.image_view,
+                format: offscreen_target.format,
+                extent: offscreen_target.extent,
+                usage: OFFSCREEN_USAGE,
+                // The previous frame may still be reading the image back.
+                initial_stages: vk::PipelineStageFlags::ALL_COMMANDS,
+                final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                final_stages: vk::PipelineStageFlags::TRANSFER,
+                final_access: vk::AccessFlags::TRANSFER_READ,
+            });
+        }
+
+        // `prepare_swapchain` found a swapchain, so it is only missing if it
+        // was destroyed since.
+        let swapchain_bundle = self.swapchain_bundle.as_ref().ok_or_else(|| {
+            VreError::swapchain("Cannot record a frame without a swapchain.")(
+                vk::Result::ERROR_OUT_OF_DATE_KHR,
+            )
+        })?;
+        let image_index = image_index as usize;
+
+        Ok(OutputTarget {
+            image: swapchain_bundle.swapchain_images[image_index],
+            view: swapchain_bundle.swapchain_image_views[image_index],
+            format: swapchain_bundle.swapchain_format,
+            extent: swapchain_bundle.swapchain_extent,
+            usage: swapchain_bundle.swapchain_usage,
+            // Matches the stage the submission waits on the acquire
+            // semaphore in.
+            initial_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            final_access: vk::AccessFlags::empty(),
+        })
     }
 
-    fn create_instance(entry: &Entry, window: &Window) -> Result<Instance, Box<dyn Error>> {
-        let has_validation_layer_support = VulkanBackend::check_validation_layer_support(entry);
+    /// Renders a cleared frame into the offscreen target and reads it back.
+    /// Returns `None` when the backend presents to a window instead.
+    pub fn capture_frame(&mut self) -> Result<Option<FrameCapture>, VreError> {
+        let graph = self.clear_graph();
+        self.capture_graph(graph)
+    }
 
-        if !has_validation_layer_support {
-            eprintln!("Validation layers requested, but not available!");
+    /// Runs `graph` on the offscreen target and reads the result back.
+    /// Returns `None` when the backend presents to a window instead.
+    pub fn capture_graph(&mut self, graph: RenderGraph) -> Result<Option<FrameCapture>, VreError> {
+        if self.offscreen_target.is_none() {
+            return Ok(None);
         }
 
-        let app_name = CString::new(crate::WINDOW_TITLE).unwrap();
-        let engine_name = CString::new("Vulkan Engine").unwrap();
+        self.draw_graph(graph)?;
+        self.frames.wait_all()?;
+
+        self.offscreen_target
+            .as_ref()
+            .map(|offscreen_target| {
+                offscreen_target.read_back(
+                    &self.logical_device,
+                    *self.command_pool,
+                    self.graphics_queue,
+                )
+            })
+            .transpose()
+    }
+
+    fn create_entry() -> Result<Entry, VreError> {
+        Entry::new().map_err(|err| VreError::Loader(err.to_string()))
+    }
+
+    /// Creates the instance with the layers and extensions requested in
+    /// `config`. Validation is enabled and instance creation and destruction
+    /// are reported to `debug_state` when it is set.
+    fn create_instance(
+        entry: &Entry,
+        window: Option<&Window>,
+        config: &RendererConfig,
+        debug_state: Option<&Arc<DebugState>>,
+    ) -> Result<Instance, VreError> {
+        let has_validation_layer_support = debug_state.is_some();
+
+        let app_name = to_c_string(&config.app_name)?;
+        let engine_name = to_c_string(&config.engine_name)?;
         let app_info = vk::ApplicationInfo {
             p_application_name: app_name.as_ptr(),
             s_type: vk::StructureType::APPLICATION_INFO,
             p_next: ptr::null(),
-            application_version: APPLICATION_VERSION,
+            application_version: config.app_version.to_vk(),
             p_engine_name: engine_name.as_ptr(),
-            engine_version: ENGINE_VERSION,
-            api_version: API_VERSION,
+            engine_version: config.engine_version.to_vk(),
+            api_version: config.api_version.to_vk(),
         };
 
-        let debug_utils_messenger_info = create_debug_utils_messenger_info();
-
-        let mut surface_extensions = ash_window::enumerate_required_extensions(window)?;
+        let mut surface_extensions = match window {
+            Some(window) => ash_window::enumerate_required_extensions(window).map_err(
+                VreError::instance("Failed to enumerate required surface extensions."),
+            )?,
+            None => Vec::new(),
+        };
 
         if has_validation_layer_support {
             surface_extensions.push(DebugUtils::name());
         }
 
+        let extra_extensions = config
+            .instance_extensions
+            .iter()
+            .map(|name| to_c_string(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let instance_extensions = surface_extensions
             .iter()
+            .copied()
+            .chain(extra_extensions.iter().map(CString::as_c_str))
             .map(|ext| ext.as_ptr())
             .collect::<Vec<_>>();
 
-        let raw_validation_layer_names: Vec<CString> = VALIDATION_LAYERS
+        let mut raw_layer_names = config
+            .layers
             .iter()
-            .map(|layer_name| CString::new(*layer_name).unwrap())
-            .collect();
+            .map(|name| to_c_string(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        if has_validation_layer_support {
+            raw_layer_names.push(to_c_string(debug::VALIDATION_LAYER)?);
+        }
 
-        let validation_layer_names: Vec<*const i8> = if has_validation_layer_support {
-            raw_validation_layer_names
-                .iter()
-                .map(|layer_name| layer_name.as_ptr())
-                .collect()
-        } else {
-            Vec::new()
-        };
+        let layer_names: Vec<*const i8> = raw_layer_names
+            .iter()
+            .map(|layer_name| layer_name.as_ptr())
+            .collect();
 
         let mut instance_desc = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_extension_names(&instance_extensions)
-            .enabled_layer_names(&validation_layer_names);
+            .enabled_layer_names(&layer_names);
 
-        if has_validation_layer_support {
-            instance_desc.p_next = &debug_utils_messenger_info
+        let debug_utils_messenger_info = debug_state.map(debug::messenger_create_info);
+        if let Some(debug_utils_messenger_info) = debug_utils_messenger_info.as_ref() {
+            instance_desc.p_next = debug_utils_messenger_info
                 as *const vk::DebugUtilsMessengerCreateInfoEXT
                 as *const c_void;
         }
 
         unsafe {
-            let instance = entry.create_instance(&instance_desc, None)?;
-
-            Ok(instance)
+            entry
+                .create_instance(&instance_desc, None)
+                .map_err(|err| match err {
+                    ash::InstanceError::VkError(result) => {
+                        VreError::instance("Could not create VK Instance.")(result)
+                    }
+                    ash::InstanceError::LoadError(symbols) => {
+                        VreError::Loader(format!("missing symbols {}", symbols.join(", ")))
+                    }
+                })
         }
     }
 
@@ -240,9 +1250,12 @@ impl VulkanBackend {
         entry: &Entry,
         instance: &Instance,
         window: &Window,
-    ) -> Result<SurfaceBundle, Box<dyn Error>> {
+    ) -> Result<SurfaceBundle, VreError> {
         // Create a surface from winit window.
-        let surface = unsafe { ash_window::create_surface(entry, instance, window, None)? };
+        let surface = unsafe {
+            ash_window::create_surface(entry, instance, window, None)
+                .map_err(VreError::surface("Could not create SurfaceBundle."))?
+        };
         let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
 
         Ok(SurfaceBundle {
@@ -251,72 +1264,27 @@ impl VulkanBackend {
         })
     }
 
-    fn check_validation_layer_support(entry: &Entry) -> bool {
-        let layer_properties = entry
-            .enumerate_instance_layer_properties()
-            .expect("Failed to enumerate Instance Layer Properties");
+    fn check_validation_layer_support(entry: &Entry) -> Result<bool, VreError> {
+        let layer_properties =
+            entry
+                .enumerate_instance_layer_properties()
+                .map_err(VreError::instance(
+                    "Failed to enumerate Instance Layer Properties",
+                ))?;
 
-        if layer_properties.len() <= 0 {
-            eprintln!("No available validation layers.");
-            return false;
+        if layer_properties.is_empty() {
+            log::info!("No available validation layers.");
+            return Ok(false);
         }
 
-        for required_layer_name in VALIDATION_LAYERS.iter() {
-            let mut is_layer_found = false;
-
-            for layer_property in layer_properties.iter() {
-                let test_layer_name = crate::utils::vk_to_string(&layer_property.layer_name);
-
-                if (*required_layer_name) == test_layer_name {
-                    is_layer_found = true;
-                    break;
-                }
-            }
-
-            if is_layer_found == false {
-                return false;
-            }
-        }
-
-        true
-    }
-
-    fn setup_debug_utils(
-        entry: &ash::Entry,
-        instance: &ash::Instance,
-    ) -> (ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT) {
-        let has_validation_layer_support = VulkanBackend::check_validation_layer_support(entry);
-        let debug_utils_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
-
-        if has_validation_layer_support {
-            (debug_utils_loader, ash::vk::DebugUtilsMessengerEXT::null())
-        } else {
-            let messenger_ci = create_debug_utils_messenger_info();
-
-            let utils_messenger = unsafe {
-                debug_utils_loader
-                    .create_debug_utils_messenger(&messenger_ci, None)
-                    .expect("Debug Utils Callback")
-            };
-
-            (debug_utils_loader, utils_messenger)
-        }
+        Ok(layer_properties.iter().any(|layer_property| {
+            crate::utils::vk_to_string(&layer_property.layer_name) == debug::VALIDATION_LAYER
+        }))
     }
 }
 
-fn create_debug_utils_messenger_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
-    vk::DebugUtilsMessengerCreateInfoEXT {
-        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
-        p_next: ptr::null(),
-        flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-            | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-            | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-        pfn_user_callback: Some(vulkan_debug_utils_callback),
-        p_user_data: ptr::null_mut(),
-    }
+/// Converts a configured name for use in a create info, rejecting names with
+/// interior nul bytes.
+fn to_c_string(name: &str) -> Result<CString, VreError> {
+    CString::new(name).map_err(|_| VreError::Config(format!("invalid name {:?}", name)))
 }
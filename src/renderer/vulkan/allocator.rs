@@ -0,0 +1,768 @@
+use std::{
+    ptr,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Device, Instance,
+};
+
+use crate::renderer::VreError;
+
+use super::device;
+use super::owned::OwnedDevice;
+
+/// Size of the blocks resources are sub-allocated from on large heaps.
+const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Heaps up to this size, e.g. the host-visible part of VRAM on discrete
+/// GPUs, use blocks of an eighth of the heap instead so a single block
+/// doesn't exhaust them.
+const SMALL_HEAP_SIZE: vk::DeviceSize = 512 * 1024 * 1024;
+
+/// Images at least this large get their own `VkDeviceMemory`. Large render
+/// targets are recreated on resize and would otherwise fragment blocks.
+const DEDICATED_IMAGE_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+/// Where an allocation lives, which decides the memory type it is taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Device-local memory only accessed by the GPU, e.g. render targets and
+    /// static geometry.
+    GpuOnly,
+    /// Host-visible memory written by the CPU and read by the GPU, e.g.
+    /// staging and uniform buffers.
+    CpuToGpu,
+    /// Host-visible memory written by the GPU and read back by the CPU.
+    GpuToCpu,
+    /// Memory the device only backs when needed, for transient attachments
+    /// that can stay in on-chip tile memory.
+    Lazy,
+}
+
+impl MemoryLocation {
+    /// Property flags a memory type must have, and flags preferred on top of
+    /// them.
+    fn flags(self) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+        let host_visible =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+        match self {
+            MemoryLocation::GpuOnly => (
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::MemoryPropertyFlags::empty(),
+            ),
+            MemoryLocation::CpuToGpu => (host_visible, vk::MemoryPropertyFlags::empty()),
+            MemoryLocation::GpuToCpu => (host_visible, vk::MemoryPropertyFlags::HOST_CACHED),
+            MemoryLocation::Lazy => (
+                vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ),
+        }
+    }
+}
+
+/// How a resource is laid out in memory. Buffers and linearly tiled images
+/// must not share a `bufferImageGranularity` page with optimally tiled
+/// images, so the two are sub-allocated from separate blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+/// What to allocate memory for.
+#[derive(Clone, Copy, Debug)]
+pub struct AllocationDesc {
+    pub requirements: vk::MemoryRequirements,
+    pub location: MemoryLocation,
+    pub kind: ResourceKind,
+    /// Gives the resource its own `VkDeviceMemory` instead of a range in a
+    /// shared block. Allocations larger than half a block are always
+    /// dedicated.
+    pub dedicated: bool,
+}
+
+/// Memory usage of one memory heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Size of the heap reported by the driver.
+    pub size: u64,
+    pub device_local: bool,
+    /// Blocks resources are sub-allocated from.
+    pub block_count: usize,
+    /// Allocations with their own `VkDeviceMemory`.
+    pub dedicated_count: usize,
+    /// Live allocations, sub-allocated or dedicated.
+    pub allocation_count: usize,
+    /// Bytes allocated from the driver, including unused space in blocks.
+    pub reserved_bytes: u64,
+    /// Bytes handed out to resources.
+    pub used_bytes: u64,
+}
+
+/// A range of device memory owned by one resource. Must be returned with
+/// `Allocator::free` once the resource is destroyed.
+#[derive(Debug)]
+pub struct Allocation {
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    /// Block list and index the range was taken from, or `None` for a
+    /// dedicated allocation.
+    block: Option<(ResourceKind, usize)>,
+    mapped: Option<NonNull<u8>>,
+}
+
+// The mapped pointer refers to device memory that stays mapped for the
+// lifetime of the allocation, not to thread-local data.
+unsafe impl Send for Allocation {}
+
+#[allow(dead_code)]
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Start of the allocation in host memory, if it lives in a host-visible
+    /// memory type. Host-visible memory is persistently mapped and coherent.
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        self.mapped
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FreeRange {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+impl FreeRange {
+    fn end(&self) -> vk::DeviceSize {
+        self.offset + self.size
+    }
+}
+
+/// One `VkDeviceMemory` that resources are sub-allocated from.
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    /// Start of the block in host memory, or null if the memory type is not
+    /// host-visible.
+    mapped: *mut u8,
+    /// Unused ranges sorted by offset. Adjacent ranges are always merged.
+    free_ranges: Vec<FreeRange>,
+    allocation_count: usize,
+    used_bytes: vk::DeviceSize,
+}
+
+// See `Allocation`.
+unsafe impl Send for MemoryBlock {}
+
+impl MemoryBlock {
+    fn new(
+        device: &Device,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+        host_visible: bool,
+    ) -> Result<Self, VreError> {
+        let (memory, mapped) =
+            allocate_device_memory(device, memory_type_index, size, host_visible)?;
+
+        Ok(Self {
+            memory,
+            size,
+            mapped,
+            free_ranges: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+            used_bytes: 0,
+        })
+    }
+
+    /// Takes the smallest free range that fits `size` bytes at `alignment`
+    /// and returns the aligned offset. Padding in front of the allocation
+    /// stays free.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let (index, offset) = self
+            .free_ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(index, range)| {
+                let offset = align_up(range.offset, alignment);
+                let end = offset.checked_add(size)?;
+
+                (end <= range.end()).then(|| (index, offset, range.end() - end))
+            })
+            .min_by_key(|&(_, _, leftover)| leftover)
+            .map(|(index, offset, _)| (index, offset))?;
+
+        let range = self.free_ranges.remove(index);
+        let mut insert_at = index;
+        if offset > range.offset {
+            self.free_ranges.insert(
+                insert_at,
+                FreeRange {
+                    offset: range.offset,
+                    size: offset - range.offset,
+                },
+            );
+            insert_at += 1;
+        }
+        if range.end() > offset + size {
+            self.free_ranges.insert(
+                insert_at,
+                FreeRange {
+                    offset: offset + size,
+                    size: range.end() - (offset + size),
+                },
+            );
+        }
+
+        self.allocation_count += 1;
+        self.used_bytes += size;
+
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let index = self
+            .free_ranges
+            .partition_point(|range| range.offset < offset);
+        self.free_ranges.insert(index, FreeRange { offset, size });
+
+        if index + 1 < self.free_ranges.len()
+            && self.free_ranges[index].end() == self.free_ranges[index + 1].offset
+        {
+            self.free_ranges[index].size += self.free_ranges[index + 1].size;
+            self.free_ranges.remove(index + 1);
+        }
+        if index > 0 && self.free_ranges[index - 1].end() == self.free_ranges[index].offset {
+            self.free_ranges[index - 1].size += self.free_ranges[index].size;
+            self.free_ranges.remove(index);
+        }
+
+        self.allocation_count -= 1;
+        self.used_bytes -= size;
+    }
+}
+
+/// Blocks and dedicated allocations of one memory type.
+struct MemoryPool {
+    block_size: vk::DeviceSize,
+    host_visible: bool,
+    /// Released blocks leave a `None` behind so indices stored in live
+    /// allocations stay valid.
+    linear_blocks: Vec<Option<MemoryBlock>>,
+    optimal_blocks: Vec<Option<MemoryBlock>>,
+    dedicated_count: usize,
+    dedicated_bytes: vk::DeviceSize,
+}
+
+impl MemoryPool {
+    fn blocks_mut(&mut self, kind: ResourceKind) -> &mut Vec<Option<MemoryBlock>> {
+        match kind {
+            ResourceKind::Linear => &mut self.linear_blocks,
+            ResourceKind::Optimal => &mut self.optimal_blocks,
+        }
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = &MemoryBlock> {
+        self.linear_blocks
+            .iter()
+            .chain(self.optimal_blocks.iter())
+            .flatten()
+    }
+}
+
+/// Sub-allocates buffer and image memory from large blocks to stay well
+/// below `maxMemoryAllocationCount`. Safe to share between threads; keeps
+/// the device alive until it is dropped, after every allocation was freed.
+pub struct Allocator {
+    device: Arc<OwnedDevice>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: vk::DeviceSize,
+    /// One pool per memory type, indexed like `memory_properties.memory_types`.
+    pools: Mutex<Vec<MemoryPool>>,
+}
+
+impl Allocator {
+    pub fn new(
+        instance: &Instance,
+        device: &Arc<OwnedDevice>,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+        let pools = memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+            .iter()
+            .map(|memory_type| {
+                let heap_size =
+                    memory_properties.memory_heaps[memory_type.heap_index as usize].size;
+
+                MemoryPool {
+                    block_size: if heap_size <= SMALL_HEAP_SIZE {
+                        heap_size / 8
+                    } else {
+                        DEFAULT_BLOCK_SIZE
+                    },
+                    host_visible: memory_type
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::HOST_VISIBLE),
+                    linear_blocks: Vec::new(),
+                    optimal_blocks: Vec::new(),
+                    dedicated_count: 0,
+                    dedicated_bytes: 0,
+                }
+            })
+            .collect();
+
+        Self {
+            device: Arc::clone(device),
+            memory_properties,
+            buffer_image_granularity: limits.buffer_image_granularity,
+            pools: Mutex::new(pools),
+        }
+    }
+
+    /// Allocates memory matching `desc`, either from a block of the chosen
+    /// memory type or as a dedicated allocation.
+    pub fn allocate(&self, desc: &AllocationDesc) -> Result<Allocation, VreError> {
+        let size = desc.requirements.size;
+        let memory_type_index = self
+            .find_memory_type(desc.requirements.memory_type_bits, desc.location)
+            .ok_or(VreError::NoSuitableMemoryType)?;
+
+        let mut pools = self.pools.lock().unwrap();
+        let pool = &mut pools[memory_type_index as usize];
+        let (block_size, host_visible) = (pool.block_size, pool.host_visible);
+
+        if desc.dedicated || size > block_size / 2 {
+            let (memory, mapped) =
+                allocate_device_memory(&self.device, memory_type_index, size, host_visible)?;
+            pool.dedicated_count += 1;
+            pool.dedicated_bytes += size;
+
+            return Ok(Allocation {
+                memory,
+                offset: 0,
+                size,
+                memory_type_index,
+                block: None,
+                mapped: NonNull::new(mapped),
+            });
+        }
+
+        let kind = self.block_kind(desc.kind);
+        let alignment = desc.requirements.alignment.max(1);
+        let blocks = pool.blocks_mut(kind);
+
+        let existing = blocks.iter_mut().enumerate().find_map(|(index, block)| {
+            let block = block.as_mut()?;
+
+            block
+                .allocate(size, alignment)
+                .map(|offset| (index, offset))
+        });
+        let (index, offset) = match existing {
+            Some(found) => found,
+            None => {
+                let mut block =
+                    MemoryBlock::new(&self.device, memory_type_index, block_size, host_visible)?;
+                let offset = block
+                    .allocate(size, alignment)
+                    .expect("A new block fits any non-dedicated allocation.");
+                let index = match blocks.iter().position(Option::is_none) {
+                    Some(index) => {
+                        blocks[index] = Some(block);
+                        index
+                    }
+                    None => {
+                        blocks.push(Some(block));
+                        blocks.len() - 1
+                    }
+                };
+
+                (index, offset)
+            }
+        };
+
+        let block = blocks[index].as_ref().unwrap();
+        let mapped = NonNull::new(block.mapped)
+            .map(|mapped| unsafe { NonNull::new_unchecked(mapped.as_ptr().add(offset as usize)) });
+
+        Ok(Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            memory_type_index,
+            block: Some((kind, index)),
+            mapped,
+        })
+    }
+
+    /// Allocates memory for `buffer` and binds it.
+    pub fn allocate_buffer(
+        &self,
+        buffer: vk::Buffer,
+        location: MemoryLocation,
+    ) -> Result<Allocation, VreError> {
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let allocation = self.allocate(&AllocationDesc {
+            requirements,
+            location,
+            kind: ResourceKind::Linear,
+            dedicated: false,
+        })?;
+
+        let result = unsafe {
+            self.device
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+        };
+
+        self.bound(allocation, result, "Could not bind buffer memory.")
+    }
+
+    /// Allocates memory for `image`, created with `tiling`, and binds it.
+    /// Large images get a dedicated allocation.
+    pub fn allocate_image(
+        &self,
+        image: vk::Image,
+        tiling: vk::ImageTiling,
+        location: MemoryLocation,
+    ) -> Result<Allocation, VreError> {
+        let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let kind = if tiling == vk::ImageTiling::LINEAR {
+            ResourceKind::Linear
+        } else {
+            ResourceKind::Optimal
+        };
+        let allocation = self.allocate(&AllocationDesc {
+            requirements,
+            location,
+            kind,
+            dedicated: requirements.size >= DEDICATED_IMAGE_SIZE,
+        })?;
+
+        let result = unsafe {
+            self.device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+        };
+
+        self.bound(allocation, result, "Could not bind image memory.")
+    }
+
+    /// Returns `allocation` if binding it succeeded, otherwise frees it.
+    fn bound(
+        &self,
+        allocation: Allocation,
+        result: ash::prelude::VkResult<()>,
+        context: &'static str,
+    ) -> Result<Allocation, VreError> {
+        match result {
+            Ok(()) => Ok(allocation),
+            Err(err) => {
+                self.free(allocation);
+                Err(VreError::device(context)(err))
+            }
+        }
+    }
+
+    /// Returns `allocation` to its block, or frees its memory if it is
+    /// dedicated. The resource using it must already be destroyed.
+    pub fn free(&self, allocation: Allocation) {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = &mut pools[allocation.memory_type_index as usize];
+
+        let (kind, index) = match allocation.block {
+            Some(block) => block,
+            None => {
+                unsafe {
+                    self.device.free_memory(allocation.memory, None);
+                }
+                pool.dedicated_count -= 1;
+                pool.dedicated_bytes -= allocation.size;
+                return;
+            }
+        };
+
+        let blocks = pool.blocks_mut(kind);
+        let block = blocks[index]
+            .as_mut()
+            .expect("Allocation was freed after its block was released.");
+        block.free(allocation.offset, allocation.size);
+
+        if let Some(block) = take_spare_block(blocks, index) {
+            unsafe {
+                self.device.free_memory(block.memory, None);
+            }
+        }
+    }
+
+    /// Current usage of every memory heap, indexed like the heaps in
+    /// `vkGetPhysicalDeviceMemoryProperties`.
+    pub fn heap_stats(&self) -> Vec<HeapStats> {
+        let properties = &self.memory_properties;
+        let mut stats: Vec<HeapStats> = properties.memory_heaps
+            [..properties.memory_heap_count as usize]
+            .iter()
+            .map(|heap| HeapStats {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                ..HeapStats::default()
+            })
+            .collect();
+
+        let pools = self.pools.lock().unwrap();
+        for (memory_type, pool) in properties.memory_types.iter().zip(pools.iter()) {
+            let heap = &mut stats[memory_type.heap_index as usize];
+
+            for block in pool.blocks() {
+                heap.block_count += 1;
+                heap.allocation_count += block.allocation_count;
+                heap.reserved_bytes += block.size;
+                heap.used_bytes += block.used_bytes;
+            }
+
+            heap.dedicated_count += pool.dedicated_count;
+            heap.allocation_count += pool.dedicated_count;
+            heap.reserved_bytes += pool.dedicated_bytes;
+            heap.used_bytes += pool.dedicated_bytes;
+        }
+
+        stats
+    }
+
+    /// Whether a memory type in `type_bits` is suitable for `location`.
+    pub fn supports(&self, type_bits: u32, location: MemoryLocation) -> bool {
+        self.find_memory_type(type_bits, location).is_some()
+    }
+
+    /// Picks the first memory type with the preferred flags for `location`,
+    /// falling back to one with only the required flags.
+    fn find_memory_type(&self, type_bits: u32, location: MemoryLocation) -> Option<u32> {
+        let (required, preferred) = location.flags();
+
+        device::find_memory_type(&self.memory_properties, type_bits, required | preferred)
+            .or_else(|| device::find_memory_type(&self.memory_properties, type_bits, required))
+    }
+
+    /// Block list a resource is sub-allocated from. Without a granularity
+    /// restriction both kinds can share blocks.
+    fn block_kind(&self, kind: ResourceKind) -> ResourceKind {
+        if self.buffer_image_granularity > 1 {
+            kind
+        } else {
+            ResourceKind::Linear
+        }
+    }
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        let pools = self.pools.get_mut().unwrap();
+
+        for pool in pools.iter() {
+            let leaked = pool
+                .blocks()
+                .map(|block| block.allocation_count)
+                .sum::<usize>()
+                + pool.dedicated_count;
+            if leaked > 0 {
+                log::warn!(
+                    "{} allocations were not freed before the allocator.",
+                    leaked
+                );
+            }
+
+            for block in pool.blocks() {
+                unsafe {
+                    self.device.free_memory(block.memory, None);
+                }
+            }
+        }
+    }
+}
+
+/// Allocates `size` bytes of `memory_type_index` and maps all of it when
+/// `map` is set. Returns a null pointer for unmapped memory.
+fn allocate_device_memory(
+    device: &Device,
+    memory_type_index: u32,
+    size: vk::DeviceSize,
+    map: bool,
+) -> Result<(vk::DeviceMemory, *mut u8), VreError> {
+    let allocate_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(size)
+        .memory_type_index(memory_type_index)
+        .build();
+
+    unsafe {
+        let memory = device
+            .allocate_memory(&allocate_info, None)
+            .map_err(VreError::device("Could not allocate device memory."))?;
+
+        if !map {
+            return Ok((memory, ptr::null_mut()));
+        }
+
+        match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+            Ok(mapped) => Ok((memory, mapped as *mut u8)),
+            Err(err) => {
+                device.free_memory(memory, None);
+                Err(VreError::device("Could not map device memory.")(err))
+            }
+        }
+    }
+}
+
+/// Takes the block at `index` out of `blocks` if it is empty and the list
+/// has another empty block. One empty block is kept per list so resources
+/// recreated every frame don't allocate from the driver each time.
+fn take_spare_block(blocks: &mut [Option<MemoryBlock>], index: usize) -> Option<MemoryBlock> {
+    let is_empty = |block: &MemoryBlock| block.allocation_count == 0;
+    let empty_blocks = blocks
+        .iter()
+        .flatten()
+        .filter(|block| is_empty(block))
+        .count();
+
+    match blocks[index].as_ref() {
+        Some(block) if is_empty(block) && empty_blocks > 1 => blocks[index].take(),
+        _ => None,
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> MemoryBlock {
+        MemoryBlock {
+            memory: vk::DeviceMemory::null(),
+            size,
+            mapped: ptr::null_mut(),
+            free_ranges: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+            used_bytes: 0,
+        }
+    }
+
+    fn ranges(block: &MemoryBlock) -> Vec<(vk::DeviceSize, vk::DeviceSize)> {
+        block
+            .free_ranges
+            .iter()
+            .map(|range| (range.offset, range.end()))
+            .collect()
+    }
+
+    #[test]
+    fn aligns_allocations_inside_padded_ranges() {
+        let mut block = block(1024);
+
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(100, 256), Some(256));
+        // The padding in front of the aligned allocation stays free.
+        assert_eq!(ranges(&block), vec![(10, 256), (356, 1024)]);
+        assert_eq!(block.allocate(64, 64), Some(64));
+        assert_eq!(ranges(&block), vec![(10, 64), (128, 256), (356, 1024)]);
+        assert_eq!(block.allocation_count, 3);
+        assert_eq!(block.used_bytes, 174);
+    }
+
+    #[test]
+    fn picks_the_best_fitting_range() {
+        let mut block = block(1024);
+        let offsets: Vec<_> = (0..6).map(|_| block.allocate(100, 1).unwrap()).collect();
+        block.free(offsets[1], 100);
+        block.free(offsets[3], 100);
+        block.free(offsets[5], 100);
+        assert_eq!(ranges(&block), vec![(100, 200), (300, 400), (500, 1024)]);
+
+        assert_eq!(block.allocate(300, 1), Some(500));
+        assert_eq!(block.allocate(90, 1), Some(100));
+    }
+
+    #[test]
+    fn merges_both_neighbours_on_free() {
+        let mut block = block(1024);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 1).unwrap();
+        let c = block.allocate(100, 1).unwrap();
+
+        block.free(a, 100);
+        block.free(c, 100);
+        assert_eq!(ranges(&block), vec![(0, 100), (200, 1024)]);
+
+        block.free(b, 100);
+        assert_eq!(ranges(&block), vec![(0, 1024)]);
+        assert_eq!(block.allocation_count, 0);
+        assert_eq!(block.used_bytes, 0);
+    }
+
+    #[test]
+    fn allocates_whole_blocks() {
+        let mut block = block(1024);
+
+        assert_eq!(block.allocate(1024, 256), Some(0));
+        assert!(block.free_ranges.is_empty());
+        assert_eq!(block.allocate(1, 1), None);
+
+        block.free(0, 1024);
+        assert_eq!(ranges(&block), vec![(0, 1024)]);
+    }
+
+    #[test]
+    fn leaves_ranges_untouched_on_failure() {
+        let mut block = block(1024);
+        block.allocate(10, 1).unwrap();
+        let before = block.free_ranges.clone();
+
+        // Too large, and only fitting without the alignment padding.
+        assert_eq!(block.allocate(2048, 1), None);
+        assert_eq!(block.allocate(1000, 256), None);
+        assert_eq!(block.allocate(vk::DeviceSize::MAX, 1), None);
+
+        assert_eq!(block.free_ranges, before);
+        assert_eq!(block.allocation_count, 1);
+        assert_eq!(block.used_bytes, 10);
+    }
+
+    #[test]
+    fn keeps_one_empty_block() {
+        let mut used = block(1024);
+        used.allocate(10, 1).unwrap();
+        let mut blocks = vec![Some(used), Some(block(1024)), None];
+
+        // The only empty block is kept even though another block exists.
+        assert!(take_spare_block(&mut blocks, 1).is_none());
+        assert!(blocks[1].is_some());
+        assert!(take_spare_block(&mut blocks, 0).is_none());
+        assert!(take_spare_block(&mut blocks, 2).is_none());
+
+        blocks[0].as_mut().unwrap().free(0, 10);
+        assert!(take_spare_block(&mut blocks, 0).is_some());
+        assert!(blocks[0].is_none());
+        assert!(take_spare_block(&mut blocks, 1).is_none());
+    }
+}
@@ -0,0 +1,237 @@
+use std::{marker::PhantomData, mem, ptr, sync::Arc};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+use super::allocator::{Allocation, Allocator, MemoryLocation};
+use super::commands::submit_one_time;
+
+/// What a `Buffer` is bound as when drawing or dispatching.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    Vertex,
+    Index,
+    Uniform,
+    Storage,
+}
+
+impl BufferUsage {
+    fn to_vk(self) -> vk::BufferUsageFlags {
+        match self {
+            BufferUsage::Vertex => vk::BufferUsageFlags::VERTEX_BUFFER,
+            BufferUsage::Index => vk::BufferUsageFlags::INDEX_BUFFER,
+            BufferUsage::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            BufferUsage::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+        }
+    }
+}
+
+/// A GPU buffer holding `len()` elements of `T`, destroyed and freed when
+/// dropped. May outlive the `Renderer` it was created with.
+pub struct Buffer<T> {
+    device: Device,
+    /// Keeps the device alive until the buffer is destroyed.
+    allocator: Arc<Allocator>,
+    buffer: vk::Buffer,
+    /// `None` until the buffer memory is bound.
+    allocation: Option<Allocation>,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> Buffer<T> {
+    /// Creates a device-local buffer and fills it with `data` through a
+    /// staging buffer. Blocks until the copy recorded from `command_pool` has
+    /// finished on `queue`. With more than one entry in `queue_families` the
+    /// buffer is shared between them without ownership transfers.
+    pub fn with_data(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        usage: BufferUsage,
+        data: &[T],
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        queue_families: &[u32],
+    ) -> Result<Self, VreError> {
+        let staging = Buffer::staging(device, allocator, data)?;
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            data.len(),
+            usage.to_vk() | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+            queue_families,
+        )?;
+
+        let region = vk::BufferCopy::builder().size(buffer.size()).build();
+        submit_one_time(device, command_pool, queue, |command_buffer| unsafe {
+            device.cmd_copy_buffer(command_buffer, staging.buffer, buffer.buffer, &[region]);
+        })?;
+
+        Ok(buffer)
+    }
+
+    /// Creates a host-visible buffer of `len` zeroed elements that stays
+    /// mapped until it is dropped, e.g. for per-frame uniforms. Writes are
+    /// visible to the GPU without flushing.
+    pub fn mapped(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        usage: BufferUsage,
+        len: usize,
+    ) -> Result<Self, VreError> {
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            len,
+            usage.to_vk(),
+            MemoryLocation::CpuToGpu,
+            &[],
+        )?;
+
+        unsafe {
+            ptr::write_bytes(buffer.mapped_ptr().unwrap(), 0, buffer.size() as usize);
+        }
+
+        Ok(buffer)
+    }
+
+    /// Creates a host-visible buffer holding `data` to copy from.
+    pub fn staging(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        data: &[T],
+    ) -> Result<Self, VreError> {
+        let mut staging = Buffer::new(
+            device,
+            allocator,
+            data.len(),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+            &[],
+        )?;
+        staging.write(0, data);
+
+        Ok(staging)
+    }
+
+    fn new(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+        queue_families: &[u32],
+    ) -> Result<Self, VreError> {
+        let size = (len * mem::size_of::<T>()) as vk::DeviceSize;
+        if size == 0 {
+            return Err(VreError::InvalidResource(
+                "buffers must not be empty".to_string(),
+            ));
+        }
+
+        let (sharing_mode, queue_families) = if queue_families.len() > 1 {
+            (vk::SharingMode::CONCURRENT, queue_families)
+        } else {
+            (vk::SharingMode::EXCLUSIVE, &[][..])
+        };
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families)
+            .build();
+
+        // The allocation is filled in afterwards so `Drop` destroys the
+        // buffer if allocating fails.
+        let mut buffer = Self {
+            device: device.clone(),
+            allocator: allocator.clone(),
+            buffer: unsafe {
+                device
+                    .create_buffer(&buffer_create_info, None)
+                    .map_err(VreError::device("Could not create buffer."))?
+            },
+            allocation: None,
+            len,
+            _marker: PhantomData,
+        };
+        buffer.allocation = Some(allocator.allocate_buffer(buffer.buffer, location)?);
+
+        Ok(buffer)
+    }
+
+    /// Copies `data` into the buffer, starting at element `start`.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer is not host-visible or `data` does not fit.
+    pub fn write(&mut self, start: usize, data: &[T]) {
+        let mapped = self
+            .mapped_ptr()
+            .expect("Only mapped buffers can be written from the CPU.");
+        assert!(
+            start + data.len() <= self.len,
+            "Write of {} elements at {} exceeds buffer length {}.",
+            data.len(),
+            start,
+            self.len
+        );
+
+        // Copied bytewise, the mapping is only aligned to the buffer's memory
+        // requirements, not necessarily to `T`.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                mapped.add(start * mem::size_of::<T>()),
+                mem::size_of_val(data),
+            );
+        }
+    }
+}
+
+impl<T> Buffer<T> {
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size in bytes.
+    pub fn size(&self) -> vk::DeviceSize {
+        (self.len * mem::size_of::<T>()) as vk::DeviceSize
+    }
+
+    /// Whether the buffer is persistently mapped and can be written with
+    /// `write`.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped_ptr().is_some()
+    }
+
+    fn mapped_ptr(&self) -> Option<*mut u8> {
+        self.allocation
+            .as_ref()
+            .and_then(Allocation::mapped_ptr)
+            .map(|mapped| mapped.as_ptr())
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+/// Records a command buffer with `record`, submits it to `queue` and waits
+/// for it to complete.
+pub fn submit_one_time<F: FnOnce(vk::CommandBuffer)>(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    record: F,
+) -> Result<(), VreError> {
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .build();
+
+    unsafe {
+        let command_buffers = device
+            .allocate_command_buffers(&allocate_info)
+            .map_err(VreError::device("Could not allocate command buffer."))?;
+        let command_buffer = command_buffers[0];
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+
+        let result = device
+            .begin_command_buffer(command_buffer, &begin_info)
+            .map_err(VreError::device("Could not begin command buffer."))
+            .and_then(|_| {
+                record(command_buffer);
+                device
+                    .end_command_buffer(command_buffer)
+                    .map_err(VreError::device("Could not end command buffer."))
+            })
+            .and_then(|_| {
+                let submit_info = vk::SubmitInfo::builder()
+                    .command_buffers(&command_buffers)
+                    .build();
+
+                device
+                    .queue_submit(queue, &[submit_info], vk::Fence::null())
+                    .map_err(VreError::device("Could not submit command buffer."))
+            })
+            .and_then(|_| {
+                device
+                    .queue_wait_idle(queue)
+                    .map_err(VreError::device("Could not wait for queue to become idle."))
+            });
+
+        device.free_command_buffers(command_pool, &command_buffers);
+
+        result
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ffi::CStr,
+    os::raw::{c_char, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ash::{extensions::ext::DebugUtils, vk, Entry, Instance};
+
+use crate::renderer::VreError;
+
+/// Layer enabled when validation is requested in the `RendererConfig`.
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Target used for validation messages forwarded to `log`, so they can be
+/// filtered separately, e.g. with `RUST_LOG=vulkan=debug`.
+pub const LOG_TARGET: &str = "vulkan";
+
+/// Number of validation warnings and errors reported since the renderer was
+/// created. Suppressed messages are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationCounters {
+    pub warnings: usize,
+    pub errors: usize,
+}
+
+/// Filter and counters shared with the debug messenger callback. Must outlive
+/// both the messenger and the instance, whose creation and destruction are
+/// reported through it as well.
+pub struct DebugState {
+    /// Least severe `log::Level` still forwarded, stored as `usize`.
+    min_level: AtomicUsize,
+    suppressed_ids: Mutex<HashSet<i32>>,
+    warnings: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+impl DebugState {
+    pub fn new(min_level: log::Level) -> Arc<Self> {
+        Arc::new(Self {
+            min_level: AtomicUsize::new(min_level as usize),
+            suppressed_ids: Mutex::new(HashSet::new()),
+            warnings: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+        })
+    }
+
+    /// Only forwards messages at least as severe as `min_level`. Warnings and
+    /// errors are counted regardless.
+    pub fn set_min_level(&self, min_level: log::Level) {
+        self.min_level.store(min_level as usize, Ordering::Relaxed);
+    }
+
+    /// Drops messages with the given `messageIdNumber` entirely, e.g. known
+    /// false positives. The number is printed with every forwarded message.
+    pub fn suppress_message_id(&self, message_id: i32) {
+        self.suppressed_ids.lock().unwrap().insert(message_id);
+    }
+
+    pub fn counters(&self) -> ValidationCounters {
+        ValidationCounters {
+            warnings: self.warnings.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    fn is_suppressed(&self, message_id: i32) -> bool {
+        self.suppressed_ids.lock().unwrap().contains(&message_id)
+    }
+
+    /// Counts the message and returns the level to forward it at, or `None`
+    /// if it is suppressed or less severe than the minimum level.
+    fn filter(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_id: i32,
+    ) -> Option<log::Level> {
+        if self.is_suppressed(message_id) {
+            return None;
+        }
+
+        let level = if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            log::Level::Error
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+            log::Level::Warn
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            log::Level::Info
+        } else {
+            log::Level::Trace
+        };
+
+        if level as usize > self.min_level.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(level)
+    }
+
+    fn handle(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+        callback_data: &vk::DebugUtilsMessengerCallbackDataEXT,
+    ) {
+        let level = match self.filter(severity, callback_data.message_id_number) {
+            Some(level) => level,
+            None => return,
+        };
+
+        let kind = if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            "validation"
+        } else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+            "performance"
+        } else {
+            "general"
+        };
+        let id_name = unsafe { c_str_or_empty(callback_data.p_message_id_name) };
+        let message = unsafe { c_str_or_empty(callback_data.p_message) };
+
+        log::log!(
+            target: LOG_TARGET,
+            level,
+            "[{}] {} ({:#x}): {}",
+            kind,
+            id_name,
+            callback_data.message_id_number,
+            message
+        );
+    }
+}
+
+unsafe fn c_str_or_empty<'a>(ptr: *const c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        "".into()
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy()
+    }
+}
+
+unsafe extern "system" fn vulkan_debug_utils_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() || p_user_data.is_null() {
+        return vk::FALSE;
+    }
+
+    let state = &*(p_user_data as *const DebugState);
+    state.handle(message_severity, message_type, &*p_callback_data);
+
+    vk::FALSE
+}
+
+/// Create info routing every message to `state`. Filtering happens in the
+/// callback so the minimum severity can change at runtime.
+pub fn messenger_create_info(state: &Arc<DebugState>) -> vk::DebugUtilsMessengerCreateInfoEXT {
+    vk::DebugUtilsMessengerCreateInfoEXT {
+        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+        p_next: ptr::null(),
+        flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
+        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+            | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+            | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        pfn_user_callback: Some(vulkan_debug_utils_callback),
+        p_user_data: Arc::as_ptr(state) as *mut c_void,
+    }
+}
+
+/// Debug utils messenger that is destroyed when dropped. Only created when
+/// validation layers are enabled.
+pub struct DebugMessenger {
+    debug_utils_loader: DebugUtils,
+    debug_messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    pub fn new(
+        entry: &Entry,
+        instance: &Instance,
+        state: &Arc<DebugState>,
+    ) -> Result<Self, VreError> {
+        let debug_utils_loader = DebugUtils::new(entry, instance);
+        let messenger_create_info = messenger_create_info(state);
+
+        let debug_messenger = unsafe {
+            debug_utils_loader
+                .create_debug_utils_messenger(&messenger_create_info, None)
+                .map_err(VreError::instance(
+                    "Could not create debug utils messenger.",
+                ))?
+        };
+
+        Ok(Self {
+            debug_utils_loader,
+            debug_messenger,
+        })
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe {
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.debug_messenger, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
+
+    const ERROR: Severity = Severity::ERROR;
+    const WARNING: Severity = Severity::WARNING;
+    const INFO: Severity = Severity::INFO;
+    const VERBOSE: Severity = Severity::VERBOSE;
+
+    #[test]
+    fn counts_warnings_and_errors() {
+        let state = DebugState::new(log::Level::Warn);
+        let callback_data = vk::DebugUtilsMessengerCallbackDataEXT::default();
+
+        for severity in [ERROR, WARNING, WARNING, INFO, VERBOSE].iter() {
+            state.handle(
+                *severity,
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                &callback_data,
+            );
+        }
+
+        assert_eq!(
+            state.counters(),
+            ValidationCounters {
+                warnings: 2,
+                errors: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn filters_by_min_level() {
+        let state = DebugState::new(log::Level::Warn);
+        assert_eq!(state.filter(ERROR, 0), Some(log::Level::Error));
+        assert_eq!(state.filter(WARNING, 0), Some(log::Level::Warn));
+        assert_eq!(state.filter(INFO, 0), None);
+        assert_eq!(state.filter(VERBOSE, 0), None);
+
+        state.set_min_level(log::Level::Error);
+        assert_eq!(state.filter(WARNING, 0), None);
+
+        state.set_min_level(log::Level::Trace);
+        assert_eq!(state.filter(INFO, 0), Some(log::Level::Info));
+        assert_eq!(state.filter(VERBOSE, 0), Some(log::Level::Trace));
+
+        // Filtered messages are still counted.
+        assert_eq!(
+            state.counters(),
+            ValidationCounters {
+                warnings: 2,
+                errors: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn drops_suppressed_message_ids() {
+        let state = DebugState::new(log::Level::Trace);
+        state.suppress_message_id(0x1234);
+
+        assert_eq!(state.filter(ERROR, 0x1234), None);
+        assert_eq!(state.filter(WARNING, 0x1234), None);
+        assert_eq!(state.counters(), ValidationCounters::default());
+
+        assert_eq!(state.filter(ERROR, 0x1235), Some(log::Level::Error));
+        assert_eq!(state.counters().errors, 1);
+    }
+}
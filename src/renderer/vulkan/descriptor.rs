@@ -0,0 +1,436 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+use super::buffer::Buffer;
+use super::texture::Texture;
+
+/// Sets the first pool of a `DescriptorAllocator` holds. Every further pool
+/// holds twice as many, up to `MAX_SETS_PER_POOL`.
+const INITIAL_SETS_PER_POOL: u32 = 64;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// Descriptors of each type a pool holds per set it was sized for.
+const POOL_RATIOS: &[(vk::DescriptorType, u32)] = &[
+    (vk::DescriptorType::UNIFORM_BUFFER, 2),
+    (vk::DescriptorType::STORAGE_BUFFER, 2),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+    (vk::DescriptorType::STORAGE_IMAGE, 1),
+];
+
+/// One binding of a descriptor set layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DescriptorBinding {
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// Descriptor set layouts created on demand and shared between every set
+/// with the same bindings. Layouts live until the cache is dropped, which
+/// must happen before the device.
+pub struct DescriptorLayoutCache {
+    device: Device,
+    layouts: Mutex<HashMap<Vec<DescriptorBinding>, vk::DescriptorSetLayout>>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            layouts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the layout for `bindings`, creating it on first use. The
+    /// order bindings are listed in does not matter.
+    pub fn get(&self, bindings: &[DescriptorBinding]) -> Result<vk::DescriptorSetLayout, VreError> {
+        let mut key = bindings.to_vec();
+        key.sort_by_key(|binding| binding.binding);
+
+        let mut layouts = self.layouts.lock().unwrap();
+        match layouts.entry(key) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => {
+                let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = entry
+                    .key()
+                    .iter()
+                    .map(|binding| {
+                        vk::DescriptorSetLayoutBinding::builder()
+                            .binding(binding.binding)
+                            .descriptor_type(binding.descriptor_type)
+                            .descriptor_count(binding.count)
+                            .stage_flags(binding.stages)
+                            .build()
+                    })
+                    .collect();
+                let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                    .bindings(&layout_bindings)
+                    .build();
+
+                let layout = unsafe {
+                    self.device
+                        .create_descriptor_set_layout(&layout_create_info, None)
+                        .map_err(VreError::device("Could not create descriptor set layout."))?
+                };
+
+                Ok(*entry.insert(layout))
+            }
+        }
+    }
+}
+
+impl Drop for DescriptorLayoutCache {
+    fn drop(&mut self) {
+        for layout in self.layouts.get_mut().unwrap().values() {
+            unsafe {
+                self.device.destroy_descriptor_set_layout(*layout, None);
+            }
+        }
+    }
+}
+
+/// Allocates descriptor sets from a list of pools, creating a larger pool
+/// whenever the current one runs out. Sets are never freed individually,
+/// only all at once with `reset`.
+pub struct DescriptorAllocator {
+    device: Device,
+    /// Pool sets are allocated from, or null before the first allocation.
+    current: vk::DescriptorPool,
+    /// Pools that ran out, including `current`.
+    used_pools: Vec<vk::DescriptorPool>,
+    /// Pools emptied by `reset`, reused before new ones are created.
+    free_pools: Vec<vk::DescriptorPool>,
+    next_pool_size: u32,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            current: vk::DescriptorPool::null(),
+            used_pools: Vec::new(),
+            free_pools: Vec::new(),
+            next_pool_size: INITIAL_SETS_PER_POOL,
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, VreError> {
+        if self.current == vk::DescriptorPool::null() {
+            self.next_pool()?;
+        }
+
+        match self.try_allocate(layout) {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.next_pool()?;
+                self.try_allocate(layout)
+            }
+            result => result,
+        }
+        .map_err(VreError::device("Could not allocate descriptor set."))
+    }
+
+    /// Frees every set allocated so far. None of them may still be in use
+    /// by the GPU.
+    pub fn reset(&mut self) -> Result<(), VreError> {
+        for pool in self.used_pools.iter() {
+            unsafe {
+                self.device
+                    .reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty())
+                    .map_err(VreError::device("Could not reset descriptor pool."))?;
+            }
+        }
+        self.free_pools.append(&mut self.used_pools);
+        self.current = vk::DescriptorPool::null();
+
+        Ok(())
+    }
+
+    fn try_allocate(
+        &self,
+        layout: vk::DescriptorSetLayout,
+    ) -> ash::prelude::VkResult<vk::DescriptorSet> {
+        let layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.current)
+            .set_layouts(&layouts)
+            .build();
+
+        unsafe {
+            self.device
+                .allocate_descriptor_sets(&allocate_info)
+                .map(|sets| sets[0])
+        }
+    }
+
+    /// Makes a reset pool or a new, larger one current.
+    fn next_pool(&mut self) -> Result<(), VreError> {
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let max_sets = self.next_pool_size;
+                self.next_pool_size = (max_sets * 2).min(MAX_SETS_PER_POOL);
+
+                let pool_sizes: Vec<vk::DescriptorPoolSize> = POOL_RATIOS
+                    .iter()
+                    .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+                        ty,
+                        descriptor_count: ratio * max_sets,
+                    })
+                    .collect();
+                let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(max_sets)
+                    .pool_sizes(&pool_sizes)
+                    .build();
+
+                unsafe {
+                    self.device
+                        .create_descriptor_pool(&pool_create_info, None)
+                        .map_err(VreError::device("Could not create descriptor pool."))?
+                }
+            }
+        };
+
+        self.used_pools.push(pool);
+        self.current = pool;
+
+        Ok(())
+    }
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        for pool in self.used_pools.iter().chain(self.free_pools.iter()) {
+            unsafe {
+                self.device.destroy_descriptor_pool(*pool, None);
+            }
+        }
+    }
+}
+
+/// A descriptor set and the layout it was allocated with, e.g. to build a
+/// pipeline layout from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DescriptorSet {
+    pub set: vk::DescriptorSet,
+    pub layout: vk::DescriptorSetLayout,
+}
+
+enum DescriptorResource {
+    Buffer(vk::DescriptorBufferInfo),
+    Image(vk::DescriptorImageInfo),
+}
+
+/// Lists the resources of a descriptor set. The layout is derived from the
+/// bindings and the set is allocated and written in one go by
+/// `Renderer::create_descriptor_set` or `Renderer::frame_descriptor_set`.
+#[derive(Default)]
+pub struct DescriptorSetBuilder {
+    bindings: Vec<DescriptorBinding>,
+    resources: Vec<DescriptorResource>,
+    /// Misuse recorded while chaining, reported by `build`.
+    errors: Vec<String>,
+}
+
+impl DescriptorSetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn uniform_buffer<T>(
+        self,
+        binding: u32,
+        buffer: &Buffer<T>,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.buffer(binding, vk::DescriptorType::UNIFORM_BUFFER, buffer, stages)
+    }
+
+    pub fn storage_buffer<T>(
+        self,
+        binding: u32,
+        buffer: &Buffer<T>,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.buffer(binding, vk::DescriptorType::STORAGE_BUFFER, buffer, stages)
+    }
+
+    /// Binds `texture` with `sampler`, sampled in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn texture(
+        self,
+        binding: u32,
+        texture: &Texture,
+        sampler: vk::Sampler,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.image(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler,
+                image_view: texture.view(),
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+            stages,
+        )
+    }
+
+    /// Binds `texture` for loads and stores in `GENERAL` layout.
+    pub fn storage_image(
+        self,
+        binding: u32,
+        texture: &Texture,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.image(
+            binding,
+            vk::DescriptorType::STORAGE_IMAGE,
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: texture.view(),
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+            stages,
+        )
+    }
+
+    fn buffer<T>(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: &Buffer<T>,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        let info = vk::DescriptorBufferInfo {
+            buffer: buffer.handle(),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+
+        self.push(
+            binding,
+            descriptor_type,
+            DescriptorResource::Buffer(info),
+            stages,
+        )
+    }
+
+    fn image(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        info: vk::DescriptorImageInfo,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.push(
+            binding,
+            descriptor_type,
+            DescriptorResource::Image(info),
+            stages,
+        )
+    }
+
+    fn push(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        resource: DescriptorResource,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        if self
+            .bindings
+            .iter()
+            .any(|existing| existing.binding == binding)
+        {
+            self.errors
+                .push(format!("binding {} is bound twice", binding));
+            return self;
+        }
+
+        self.bindings.push(DescriptorBinding {
+            binding,
+            descriptor_type,
+            count: 1,
+            stages,
+        });
+        self.resources.push(resource);
+        self
+    }
+
+    /// Allocates a set with the cached layout for the bindings and writes
+    /// every resource into it. Fails if a binding was bound twice.
+    pub fn build(
+        &self,
+        device: &Device,
+        layout_cache: &DescriptorLayoutCache,
+        allocator: &mut DescriptorAllocator,
+    ) -> Result<DescriptorSet, VreError> {
+        if let Some(error) = self.errors.first() {
+            return Err(VreError::InvalidResource(error.clone()));
+        }
+
+        let layout = layout_cache.get(&self.bindings)?;
+        let set = allocator.allocate(layout)?;
+
+        let writes: Vec<vk::WriteDescriptorSet> = self
+            .bindings
+            .iter()
+            .zip(self.resources.iter())
+            .map(|(binding, resource)| {
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type);
+
+                match resource {
+                    DescriptorResource::Buffer(info) => {
+                        write.buffer_info(std::slice::from_ref(info))
+                    }
+                    DescriptorResource::Image(info) => write.image_info(std::slice::from_ref(info)),
+                }
+                .build()
+            })
+            .collect();
+
+        unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+        }
+
+        Ok(DescriptorSet { set, layout })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_duplicate_bindings() {
+        let image = || DescriptorResource::Image(vk::DescriptorImageInfo::default());
+        let builder = DescriptorSetBuilder::new()
+            .push(
+                0,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                image(),
+                vk::ShaderStageFlags::FRAGMENT,
+            )
+            .push(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                image(),
+                vk::ShaderStageFlags::COMPUTE,
+            );
+
+        assert_eq!(builder.bindings.len(), 1);
+        assert_eq!(builder.errors, vec!["binding 0 is bound twice".to_string()]);
+    }
+}
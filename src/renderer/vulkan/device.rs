@@ -1,19 +1,44 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, ffi::CString};
 
 use ash::{extensions::khr::Swapchain, version::InstanceV1_0, vk, Device, Instance};
 
+use crate::renderer::{RendererConfig, VreError};
 use crate::utils;
 
+use super::query::QuerySupport;
 use super::{QueueFamilyIndices, SurfaceBundle};
 
+/// Device extensions to enable: the swapchain extension when presenting,
+/// plus any extensions requested in `config`.
+pub fn required_extensions(config: &RendererConfig, presenting: bool) -> Vec<CString> {
+    let mut extensions = Vec::new();
+
+    if presenting {
+        extensions.push(Swapchain::name().to_owned());
+    }
+
+    for extension in config.device_extensions.iter() {
+        match CString::new(extension.as_str()) {
+            Ok(extension) if !extensions.contains(&extension) => extensions.push(extension),
+            Ok(_) => {}
+            Err(_) => log::warn!("Ignoring invalid device extension name {:?}.", extension),
+        }
+    }
+
+    extensions
+}
+
 pub fn check_device_extension_support(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
-) -> bool {
+    required: &[CString],
+) -> Result<bool, VreError> {
     let available_extensions = unsafe {
         instance
             .enumerate_device_extension_properties(physical_device)
-            .expect("Failed to get device extension properties.")
+            .map_err(VreError::instance(
+                "Failed to get device extension properties.",
+            ))?
     };
 
     let mut available_extension_names = vec![];
@@ -24,81 +49,154 @@ pub fn check_device_extension_support(
     }
 
     let mut required_extensions = HashSet::new();
-    for extension in super::REQUIRED_DEVICE_EXTENSIONS.iter() {
-        required_extensions.insert(extension.to_string());
+    for extension in required.iter() {
+        required_extensions.insert(extension.to_string_lossy().into_owned());
     }
 
     for extension_name in available_extension_names.iter() {
         required_extensions.remove(extension_name);
     }
 
-    return required_extensions.is_empty();
+    Ok(required_extensions.is_empty())
 }
 
+/// Creates the logical device with `extensions` enabled and one queue from
+/// every distinct queue family in use. When `surface_bundle` is `None` the
+/// device is created for headless rendering.
 pub fn create_logical_device(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
-    surface_bundle: &SurfaceBundle,
-) -> (Device, QueueFamilyIndices) {
-    let indices = find_queue_family(instance, physical_device, surface_bundle);
+    surface_bundle: Option<&SurfaceBundle>,
+    extensions: &[CString],
+) -> Result<(Device, QueueFamilyIndices, QuerySupport), VreError> {
+    let indices = find_queue_family(instance, physical_device, surface_bundle)?;
     let priorities = [1.0];
-    let enabled_extension_names = [Swapchain::name().as_ptr()];
-    let queue_info = vk::DeviceQueueCreateInfo::builder()
-        .queue_family_index(indices.graphics_family.unwrap())
-        .queue_priorities(&priorities)
+    let enabled_extension_names: Vec<*const i8> = extensions
+        .iter()
+        .map(|extension| extension.as_ptr())
+        .collect();
+    let queue_infos: Vec<vk::DeviceQueueCreateInfo> = indices
+        .unique_families()
+        .into_iter()
+        .map(|family| {
+            vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(family)
+                .queue_priorities(&priorities)
+                .build()
+        })
+        .collect();
+
+    // Optional query features are enabled whenever the device has them.
+    let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+    let features = vk::PhysicalDeviceFeatures::builder()
+        .pipeline_statistics_query(supported_features.pipeline_statistics_query == vk::TRUE)
+        .occlusion_query_precise(supported_features.occlusion_query_precise == vk::TRUE)
         .build();
     let device_create_info = vk::DeviceCreateInfo::builder()
-        .queue_create_infos(&[queue_info])
+        .queue_create_infos(&queue_infos)
         .enabled_extension_names(&enabled_extension_names)
+        .enabled_features(&features)
         .build();
 
     let device = unsafe {
         instance
             .create_device(physical_device, &device_create_info, None)
-            .expect("Could not create Vulkan Device.")
+            .map_err(VreError::device("Could not create Vulkan Device."))?
     };
 
-    (device, indices)
+    Ok((device, indices, QuerySupport::new(&features)))
 }
 
+/// Finds the graphics and present queue families, preferring a single family
+/// that can do both, plus dedicated transfer and compute families if the
+/// device has them. Present support is only queried when a surface is
+/// available.
 pub fn find_queue_family(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
-    surface_bundle: &SurfaceBundle,
-) -> QueueFamilyIndices {
+    surface_bundle: Option<&SurfaceBundle>,
+) -> Result<QueueFamilyIndices, VreError> {
     let queue_families =
         unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
 
     let mut indices = QueueFamilyIndices {
         graphics_family: None,
         present_family: None,
+        dedicated_transfer_family: None,
+        dedicated_compute_family: None,
     };
 
-    let mut index = 0;
-    for queue_family in queue_families.iter() {
-        if queue_family.queue_count > 0
-            && queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-        {
-            indices.graphics_family = Some(index);
+    for (index, queue_family) in (0u32..).zip(queue_families.iter()) {
+        if queue_family.queue_count == 0 {
+            continue;
         }
 
-        let has_present_support = unsafe {
-            surface_bundle
-                .surface_loader
-                .get_physical_device_surface_support(physical_device, index, surface_bundle.surface)
-                .expect("Could not check physical device for surface support.")
+        let flags = queue_family.queue_flags;
+        let has_graphics = flags.contains(vk::QueueFlags::GRAPHICS);
+        let has_compute = flags.contains(vk::QueueFlags::COMPUTE);
+        let has_present_support = match surface_bundle {
+            Some(surface_bundle) => unsafe {
+                surface_bundle
+                    .surface_loader
+                    .get_physical_device_surface_support(
+                        physical_device,
+                        index,
+                        surface_bundle.surface,
+                    )
+                    .map_err(VreError::surface(
+                        "Could not check physical device for surface support.",
+                    ))?
+            },
+            None => false,
         };
 
-        if queue_family.queue_count > 0 && has_present_support {
+        if has_graphics {
+            // Switch to a family that can also present, so frames don't have
+            // to be shared between families.
+            let graphics_can_present = indices.graphics_family.is_some()
+                && indices.graphics_family == indices.present_family;
+
+            if indices.graphics_family.is_none() || (has_present_support && !graphics_can_present) {
+                indices.graphics_family = Some(index);
+            }
+        }
+
+        if has_present_support
+            && (indices.present_family.is_none() || indices.graphics_family == Some(index))
+        {
             indices.present_family = Some(index);
         }
 
-        if indices.is_complete() {
-            break;
+        if has_compute && !has_graphics && indices.dedicated_compute_family.is_none() {
+            indices.dedicated_compute_family = Some(index);
         }
 
-        index += 1;
+        // Graphics and compute families support transfers implicitly, so
+        // only families without either are dedicated to transfers.
+        if flags.contains(vk::QueueFlags::TRANSFER)
+            && !has_graphics
+            && !has_compute
+            && indices.dedicated_transfer_family.is_none()
+        {
+            indices.dedicated_transfer_family = Some(index);
+        }
     }
 
-    indices
+    Ok(indices)
+}
+
+/// Finds a memory type index allowed by `type_bits` that has all of the
+/// requested `flags`.
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    flags: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+        })
+        .map(|(index, _)| index as u32)
 }
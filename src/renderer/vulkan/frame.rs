@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::{config, VreError};
+
+use super::allocator::Allocator;
+use super::descriptor::DescriptorAllocator;
+use super::query::{FrameQueries, QuerySupport};
+use super::timestamps::{TimestampLimits, TimestampQueries};
+use super::transient::TransientPool;
+
+/// Command buffer and synchronization primitives used to record and submit
+/// one frame.
+pub struct FrameContext {
+    device: Device,
+    command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+    /// Signaled when the acquired swapchain image is ready to be written.
+    pub image_available: vk::Semaphore,
+    /// Signaled when rendering finished and the image can be presented.
+    pub render_finished: vk::Semaphore,
+    /// Signaled when the GPU finished executing `command_buffer`.
+    pub in_flight: vk::Fence,
+    /// Descriptor sets only used by this frame, freed when the frame context
+    /// is reused.
+    pub descriptors: DescriptorAllocator,
+    /// Render graph images, buffers and framebuffers used by this frame.
+    pub transients: TransientPool,
+    /// GPU timing scopes of this frame, read back when the context is
+    /// reused.
+    pub timestamps: TimestampQueries,
+    /// Pipeline statistics and occlusion queries of this frame, read back
+    /// with `timestamps`.
+    pub queries: FrameQueries,
+    /// Set once the frame context is reused, until `descriptors` and
+    /// `transients` have been reset and the queries read back.
+    stale: bool,
+}
+
+impl FrameContext {
+    /// Allocates the command buffer from `command_pool`, which must allow
+    /// resetting individual command buffers and outlive the frame. The fence
+    /// starts signaled so the first wait returns immediately.
+    pub fn new(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        command_pool: vk::CommandPool,
+        timestamp_limits: TimestampLimits,
+        query_support: QuerySupport,
+    ) -> Result<Self, VreError> {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+        let semaphore_create_info = vk::SemaphoreCreateInfo::builder().build();
+        let fence_create_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED)
+            .build();
+
+        // Handles are filled in one at a time so `Drop` releases whatever
+        // was created if a later step fails.
+        let mut frame = Self {
+            device: device.clone(),
+            command_pool,
+            command_buffer: vk::CommandBuffer::null(),
+            image_available: vk::Semaphore::null(),
+            render_finished: vk::Semaphore::null(),
+            in_flight: vk::Fence::null(),
+            descriptors: DescriptorAllocator::new(device),
+            transients: TransientPool::new(device, allocator),
+            timestamps: TimestampQueries::new(device, timestamp_limits)?,
+            queries: FrameQueries::new(device, query_support)?,
+            stale: false,
+        };
+
+        unsafe {
+            frame.command_buffer = device
+                .allocate_command_buffers(&allocate_info)
+                .map_err(VreError::device("Could not allocate frame command buffer."))?[0];
+            frame.image_available = device
+                .create_semaphore(&semaphore_create_info, None)
+                .map_err(VreError::device("Could not create semaphore."))?;
+            frame.render_finished = device
+                .create_semaphore(&semaphore_create_info, None)
+                .map_err(VreError::device("Could not create semaphore."))?;
+            frame.in_flight = device
+                .create_fence(&fence_create_info, None)
+                .map_err(VreError::device("Could not create fence."))?;
+        }
+
+        Ok(frame)
+    }
+
+    /// Blocks until the previous submission of this frame has finished.
+    pub fn wait(&self) -> Result<(), VreError> {
+        unsafe {
+            self.device
+                .wait_for_fences(&[self.in_flight], true, u64::MAX)
+                .map_err(VreError::device("Failed to wait for frame fence."))
+        }
+    }
+}
+
+impl Drop for FrameContext {
+    fn drop(&mut self) {
+        unsafe {
+            if self.command_buffer != vk::CommandBuffer::null() {
+                self.device
+                    .free_command_buffers(self.command_pool, &[self.command_buffer]);
+            }
+            self.device.destroy_semaphore(self.image_available, None);
+            self.device.destroy_semaphore(self.render_finished, None);
+            self.device.destroy_fence(self.in_flight, None);
+        }
+    }
+}
+
+/// Ring of frame contexts so the CPU can record up to `len()` frames ahead of
+/// the GPU without reusing resources that are still in use.
+pub struct FrameRing {
+    device: Device,
+    frames: Vec<FrameContext>,
+    current: usize,
+    /// Fence of the frame that last rendered into each swapchain image, or a
+    /// null handle if the image has not been used yet.
+    images_in_flight: Vec<vk::Fence>,
+}
+
+impl FrameRing {
+    pub fn new(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        command_pool: vk::CommandPool,
+        timestamp_limits: TimestampLimits,
+        query_support: QuerySupport,
+        frames_in_flight: usize,
+        image_count: usize,
+    ) -> Result<Self, VreError> {
+        config::validate_frames_in_flight(frames_in_flight).map_err(VreError::Config)?;
+
+        let frames = (0..frames_in_flight)
+            .map(|_| {
+                FrameContext::new(
+                    device,
+                    allocator,
+                    command_pool,
+                    timestamp_limits,
+                    query_support,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            device: device.clone(),
+            frames,
+            current: 0,
+            images_in_flight: vec![vk::Fence::null(); image_count],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn current(&self) -> &FrameContext {
+        &self.frames[self.current]
+    }
+
+    /// Moves on to the next frame context once the current one was submitted.
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.frames.len();
+        self.frames[self.current].stale = true;
+    }
+
+    /// Frees what the current frame context used during its previous
+    /// submission and reads back its queries, waiting for it first. Does
+    /// nothing if that already happened since the context was reused.
+    pub fn reclaim(&mut self) -> Result<(), VreError> {
+        let frame = &mut self.frames[self.current];
+
+        if frame.stale {
+            frame.wait()?;
+            frame.descriptors.reset()?;
+            frame.transients.reset();
+            frame.timestamps.resolve()?;
+            frame.queries.resolve()?;
+            frame.stale = false;
+        }
+
+        Ok(())
+    }
+
+    /// Descriptor allocator of the current frame. The first call after the
+    /// frame context was reused waits for its previous submission and frees
+    /// the sets it used.
+    pub fn current_descriptors(&mut self) -> Result<&mut DescriptorAllocator, VreError> {
+        self.reclaim()?;
+
+        Ok(&mut self.frames[self.current].descriptors)
+    }
+
+    /// The current frame context, reclaimed like `current_descriptors`.
+    pub fn current_mut(&mut self) -> Result<&mut FrameContext, VreError> {
+        self.reclaim()?;
+
+        Ok(&mut self.frames[self.current])
+    }
+
+    /// Waits until no other in-flight frame is still rendering into
+    /// `image_index`, then marks the image as used by the current frame.
+    /// Needed when the swapchain hands out images out of order or has fewer
+    /// images than there are frames in flight.
+    pub fn claim_image(&mut self, image_index: u32) -> Result<(), VreError> {
+        let image_index = image_index as usize;
+        let in_flight = self.frames[self.current].in_flight;
+        let image_fence = self.images_in_flight[image_index];
+
+        if image_fence != vk::Fence::null() && image_fence != in_flight {
+            unsafe {
+                self.device
+                    .wait_for_fences(&[image_fence], true, u64::MAX)
+                    .map_err(VreError::device(
+                        "Failed to wait for swapchain image fence.",
+                    ))?;
+            }
+        }
+
+        self.images_in_flight[image_index] = in_flight;
+
+        Ok(())
+    }
+
+    /// Forgets which frames used which images, e.g. after the swapchain was
+    /// recreated with `image_count` images.
+    pub fn reset_images(&mut self, image_count: usize) {
+        self.images_in_flight = vec![vk::Fence::null(); image_count];
+    }
+
+    /// Waits for every frame in flight and reads back their queries, e.g.
+    /// before the ring is replaced. Returns the frame contexts oldest
+    /// submission first, so their results can be taken in order.
+    pub fn resolve_all(&mut self) -> Result<Vec<&mut FrameContext>, VreError> {
+        self.wait_all()?;
+
+        let (newer, older) = self.frames.split_at_mut(self.current);
+        let mut frames: Vec<&mut FrameContext> = older.iter_mut().chain(newer).collect();
+        for frame in frames.iter_mut() {
+            frame.timestamps.resolve()?;
+            frame.queries.resolve()?;
+        }
+
+        Ok(frames)
+    }
+
+    /// Blocks until every frame in flight has finished on the GPU.
+    pub fn wait_all(&self) -> Result<(), VreError> {
+        let fences: Vec<vk::Fence> = self.frames.iter().map(|frame| frame.in_flight).collect();
+
+        unsafe {
+            self.device
+                .wait_for_fences(&fences, true, u64::MAX)
+                .map_err(VreError::device("Failed to wait for frame fences."))
+        }
+    }
+}
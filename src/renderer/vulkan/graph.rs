@@ -0,0 +1,2179 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+use super::buffer::Buffer;
+use super::graph_dump::{
+    flag_names, AccessDump, BarrierDump, PassDump, RenderGraphDump, ResourceDump, ResourceKind,
+    ResourceSource,
+};
+use super::pipeline::{PipelineId, PipelineManager};
+use super::query::FrameQueries;
+use super::render_pass::{AttachmentKey, RenderPassCache, RenderPassKey};
+use super::shader::PipelineLayout;
+use super::texture::{self, Texture};
+use super::timestamps::TimestampQueries;
+use super::transient::{
+    ImageKey, TransientDesc, TransientPool, TransientRequest, TransientResource,
+};
+
+/// Accesses that write memory. Only these need to be made available by a
+/// barrier; read accesses in a source mask have no effect.
+const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::SHADER_WRITE.as_raw()
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags::HOST_WRITE.as_raw()
+        | vk::AccessFlags::MEMORY_WRITE.as_raw(),
+);
+
+static NEXT_GRAPH_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a `RenderGraph`, so handles from other graphs are caught
+/// instead of silently naming another resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct GraphId(u64);
+
+impl Default for GraphId {
+    fn default() -> Self {
+        GraphId(NEXT_GRAPH_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// An image declared in a `RenderGraph`. Only valid for the graph that
+/// returned it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle {
+    graph: GraphId,
+    index: usize,
+}
+
+/// A buffer declared in a `RenderGraph`. Only valid for the graph that
+/// returned it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle {
+    graph: GraphId,
+    index: usize,
+}
+
+/// Size of an image created by the render graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageSize {
+    Absolute(vk::Extent2D),
+    /// Scale of the output image's size, e.g. `0.5` for half resolution.
+    OutputRelative(f32),
+}
+
+impl ImageSize {
+    fn resolve(self, output_extent: vk::Extent2D) -> vk::Extent2D {
+        match self {
+            ImageSize::Absolute(extent) => extent,
+            ImageSize::OutputRelative(scale) => vk::Extent2D {
+                width: ((output_extent.width as f32 * scale) as u32).max(1),
+                height: ((output_extent.height as f32 * scale) as u32).max(1),
+            },
+        }
+    }
+}
+
+/// Format and size of an image created by the render graph. Usage flags
+/// are derived from how passes access the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageDesc {
+    pub(crate) format: vk::Format,
+    pub(crate) size: ImageSize,
+}
+
+impl ImageDesc {
+    pub fn new(format: vk::Format, width: u32, height: u32) -> Self {
+        Self {
+            format,
+            size: ImageSize::Absolute(vk::Extent2D { width, height }),
+        }
+    }
+
+    /// An image `scale` times the size of the output image, so it follows
+    /// window resizes.
+    pub fn output_relative(format: vk::Format, scale: f32) -> Self {
+        Self {
+            format,
+            size: ImageSize::OutputRelative(scale),
+        }
+    }
+}
+
+/// What happens to an attachment's contents at the start of a pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp {
+    /// Keeps the contents, which makes the pass read the image.
+    Load,
+    /// Leaves the contents undefined.
+    DontCare,
+    ClearColor([f32; 4]),
+    /// Clears depth to the value and stencil to zero.
+    ClearDepth(f32),
+}
+
+impl LoadOp {
+    fn to_vk(self) -> vk::AttachmentLoadOp {
+        match self {
+            LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+            LoadOp::ClearColor(_) | LoadOp::ClearDepth(_) => vk::AttachmentLoadOp::CLEAR,
+        }
+    }
+
+    fn clear_value(self) -> vk::ClearValue {
+        match self {
+            LoadOp::ClearDepth(depth) => vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            },
+            LoadOp::ClearColor(color) => vk::ClearValue {
+                color: vk::ClearColorValue { float32: color },
+            },
+            LoadOp::Load | LoadOp::DontCare => vk::ClearValue::default(),
+        }
+    }
+}
+
+/// How a pass uses a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferAccess {
+    Vertex,
+    Index,
+    Indirect,
+    Uniform(vk::ShaderStageFlags),
+    Storage(vk::ShaderStageFlags),
+    /// Copy source when read, copy destination when written.
+    Transfer,
+}
+
+/// A resource of the graph, indexing its images or buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Resource {
+    Image(usize),
+    Buffer(usize),
+}
+
+enum ImageSource {
+    Transient(ImageDesc),
+    /// The image the frame is presented or read back from.
+    Output,
+    /// Returned to `layout` once the graph has run.
+    Imported {
+        image: vk::Image,
+        view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        layout: vk::ImageLayout,
+    },
+}
+
+struct ImageNode {
+    name: String,
+    source: ImageSource,
+}
+
+struct BufferNode {
+    name: String,
+    size: vk::DeviceSize,
+    /// `None` for buffers created by the graph.
+    imported: Option<vk::Buffer>,
+}
+
+/// One pass's use of a resource. Uses of the same resource by one pass are
+/// merged.
+#[derive(Clone, Copy, Debug)]
+struct Access {
+    resource: Resource,
+    stages: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    /// Layout images must be in, `UNDEFINED` for buffers.
+    layout: vk::ImageLayout,
+    /// Whether the pass depends on the previous contents.
+    reads: bool,
+    writes: bool,
+    image_usage: vk::ImageUsageFlags,
+    buffer_usage: vk::BufferUsageFlags,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Attachment {
+    image: ImageHandle,
+    load_op: LoadOp,
+}
+
+struct Pass<'a> {
+    name: String,
+    accesses: Vec<Access>,
+    colors: Vec<Attachment>,
+    depth: Option<Attachment>,
+    side_effect: bool,
+    execute: Box<dyn FnOnce(&PassContext) + 'a>,
+}
+
+/// Passes of one frame and the images and buffers they read and write.
+///
+/// Passes are declared in the order their accesses are meant to happen:
+/// a read sees the last write declared before it. When the graph is run,
+/// passes whose results never reach the output image, an imported resource
+/// or a pass marked as having side effects are culled, the rest are sorted
+/// by their dependencies and barriers and layout transitions are inserted
+/// between them. Images and buffers created by the graph only live for the
+/// frame; their contents are undefined until a pass writes them.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    id: GraphId,
+    images: Vec<ImageNode>,
+    buffers: Vec<BufferNode>,
+    passes: Vec<Pass<'a>>,
+    output: Option<ImageHandle>,
+    /// Declaration errors, reported when the graph is compiled.
+    errors: Vec<String>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The image the frame ends up in: the acquired swapchain image, or the
+    /// offscreen target when headless. Its contents are undefined at the
+    /// start of the frame. Passes may render into it and, where the surface
+    /// supports it, copy to or from it; other accesses fail to compile.
+    pub fn import_swapchain(&mut self) -> ImageHandle {
+        if let Some(output) = self.output {
+            return output;
+        }
+
+        let output = self.add_image("swapchain", ImageSource::Output);
+        self.output = Some(output);
+        output
+    }
+
+    /// Uses `texture` in the graph. It is returned to its current layout
+    /// after the last pass, so it must not be `UNDEFINED`.
+    pub fn import_texture(&mut self, name: &str, texture: &'a Texture) -> ImageHandle {
+        if texture.layout() == vk::ImageLayout::UNDEFINED {
+            self.errors.push(format!(
+                "texture `{}` is imported in UNDEFINED layout",
+                name
+            ));
+        }
+
+        let extent = texture.extent();
+        self.add_image(
+            name,
+            ImageSource::Imported {
+                image: texture.image(),
+                view: texture.view(),
+                format: texture.format(),
+                extent: vk::Extent2D {
+                    width: extent.width,
+                    height: extent.height,
+                },
+                layout: texture.layout(),
+            },
+        )
+    }
+
+    /// Creates an image that only lives for this frame.
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ImageHandle {
+        self.add_image(name, ImageSource::Transient(desc))
+    }
+
+    /// Uses `buffer` in the graph.
+    pub fn import_buffer<T>(&mut self, name: &str, buffer: &'a Buffer<T>) -> BufferHandle {
+        self.add_buffer(name, buffer.size(), Some(buffer.handle()))
+    }
+
+    /// Creates a device-local buffer of `size` bytes that only lives for
+    /// this frame.
+    pub fn create_buffer(&mut self, name: &str, size: vk::DeviceSize) -> BufferHandle {
+        self.add_buffer(name, size, None)
+    }
+
+    /// Starts declaring a pass. The pass is added once `execute` is called
+    /// on the returned builder.
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            name: name.to_string(),
+            accesses: Vec::new(),
+            colors: Vec::new(),
+            depth: None,
+            side_effect: false,
+        }
+    }
+
+    fn add_image(&mut self, name: &str, source: ImageSource) -> ImageHandle {
+        self.images.push(ImageNode {
+            name: name.to_string(),
+            source,
+        });
+        ImageHandle {
+            graph: self.id,
+            index: self.images.len() - 1,
+        }
+    }
+
+    fn add_buffer(
+        &mut self,
+        name: &str,
+        size: vk::DeviceSize,
+        imported: Option<vk::Buffer>,
+    ) -> BufferHandle {
+        self.buffers.push(BufferNode {
+            name: name.to_string(),
+            size,
+            imported,
+        });
+        BufferHandle {
+            graph: self.id,
+            index: self.buffers.len() - 1,
+        }
+    }
+
+    pub(crate) fn resource_name(&self, resource: Resource) -> &str {
+        match resource {
+            Resource::Image(index) => &self.images[index].name,
+            Resource::Buffer(index) => &self.buffers[index].name,
+        }
+    }
+
+    fn is_transient(&self, resource: Resource) -> bool {
+        match resource {
+            Resource::Image(index) => match self.images[index].source {
+                ImageSource::Transient(_) => true,
+                ImageSource::Output | ImageSource::Imported { .. } => false,
+            },
+            Resource::Buffer(index) => self.buffers[index].imported.is_none(),
+        }
+    }
+
+    fn image_format(&self, index: usize, output: &OutputTarget) -> vk::Format {
+        match self.images[index].source {
+            ImageSource::Transient(desc) => desc.format,
+            ImageSource::Output => output.format,
+            ImageSource::Imported { format, .. } => format,
+        }
+    }
+
+    fn image_extent(&self, index: usize, output: &OutputTarget) -> vk::Extent2D {
+        match self.images[index].source {
+            ImageSource::Transient(desc) => desc.size.resolve(output.extent),
+            ImageSource::Output => output.extent,
+            ImageSource::Imported { extent, .. } => extent,
+        }
+    }
+
+    /// State of a resource before the first pass.
+    fn initial_state(&self, resource: Resource, output: &OutputTarget) -> ResourceState {
+        let mut state = ResourceState::default();
+        let source = match resource {
+            Resource::Image(index) => &self.images[index].source,
+            Resource::Buffer(index) => {
+                if self.buffers[index].imported.is_some() {
+                    state.write_stages = vk::PipelineStageFlags::ALL_COMMANDS;
+                    state.write_access = vk::AccessFlags::MEMORY_WRITE;
+                }
+                return state;
+            }
+        };
+
+        match *source {
+            ImageSource::Transient(_) => {}
+            ImageSource::Output => state.write_stages = output.initial_stages,
+            ImageSource::Imported { layout, .. } => {
+                state.layout = layout;
+                state.write_stages = vk::PipelineStageFlags::ALL_COMMANDS;
+                state.write_access = vk::AccessFlags::MEMORY_WRITE;
+            }
+        }
+
+        state
+    }
+
+    /// Culls and orders the passes and works out which resources they use
+    /// when. Barriers are left to `synchronize`.
+    pub(crate) fn schedule(&self, output: &OutputTarget) -> Result<CompiledGraph, VreError> {
+        if let Some(error) = self.errors.first() {
+            return Err(VreError::RenderGraph(error.clone()));
+        }
+
+        for pass in self.passes.iter() {
+            let unsupported = pass
+                .accesses
+                .iter()
+                .filter(|access| {
+                    self.output.map(|output| Resource::Image(output.index)) == Some(access.resource)
+                })
+                .map(|access| access.image_usage & !output.usage)
+                .find(|usage| !usage.is_empty());
+            if let Some(usage) = unsupported {
+                return Err(VreError::RenderGraph(format!(
+                    "pass `{}` uses the output image as {:?}, which it doesn't support",
+                    pass.name, usage
+                )));
+            }
+
+            let mut extents = pass
+                .colors
+                .iter()
+                .chain(pass.depth.iter())
+                .map(|attachment| self.image_extent(attachment.image.index, output));
+            if let Some(first) = extents.next() {
+                if extents.any(|extent| extent != first) {
+                    return Err(VreError::RenderGraph(format!(
+                        "attachments of pass `{}` differ in size",
+                        pass.name
+                    )));
+                }
+            }
+        }
+
+        let pass_count = self.passes.len();
+        // Passes each pass reads results from, and passes that must run
+        // after each pass, which also covers write-after-read and
+        // write-after-write hazards.
+        let mut producers = vec![Vec::new(); pass_count];
+        let mut successors = vec![Vec::new(); pass_count];
+        let mut last_writers: HashMap<Resource, usize> = HashMap::new();
+        let mut readers: HashMap<Resource, Vec<usize>> = HashMap::new();
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for access in pass.accesses.iter() {
+                let resource = access.resource;
+                let last_writer = last_writers.get(&resource).copied();
+
+                if access.reads {
+                    match last_writer {
+                        Some(writer) => {
+                            producers[index].push(writer);
+                            successors[writer].push(index);
+                        }
+                        None if self.is_transient(resource) => {
+                            return Err(VreError::RenderGraph(format!(
+                                "pass `{}` reads `{}` before any pass writes it",
+                                pass.name,
+                                self.resource_name(resource)
+                            )));
+                        }
+                        None => {}
+                    }
+                }
+
+                if access.writes {
+                    if let Some(writer) = last_writer {
+                        successors[writer].push(index);
+                    }
+                    for reader in readers.remove(&resource).unwrap_or_default() {
+                        successors[reader].push(index);
+                    }
+                    last_writers.insert(resource, index);
+                } else {
+                    readers.entry(resource).or_default().push(index);
+                }
+            }
+        }
+
+        // Passes writing resources that outlive the graph are kept, along
+        // with every pass they read results from.
+        let mut alive = vec![false; pass_count];
+        let mut stack: Vec<usize> = (0..pass_count)
+            .filter(|&index| {
+                let pass = &self.passes[index];
+                pass.side_effect
+                    || pass
+                        .accesses
+                        .iter()
+                        .any(|access| access.writes && !self.is_transient(access.resource))
+            })
+            .collect();
+        for &index in stack.iter() {
+            alive[index] = true;
+        }
+        while let Some(index) = stack.pop() {
+            for &producer in producers[index].iter() {
+                if !alive[producer] {
+                    alive[producer] = true;
+                    stack.push(producer);
+                }
+            }
+        }
+
+        // Kahn's algorithm, taking ready passes in declaration order so the
+        // result is stable.
+        let mut in_degrees = vec![0; pass_count];
+        for index in (0..pass_count).filter(|&index| alive[index]) {
+            for &successor in successors[index].iter() {
+                if alive[successor] {
+                    in_degrees[successor] += 1;
+                }
+            }
+        }
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..pass_count)
+            .filter(|&index| alive[index] && in_degrees[index] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &successor in successors[index].iter() {
+                if alive[successor] {
+                    in_degrees[successor] -= 1;
+                    if in_degrees[successor] == 0 {
+                        ready.push(Reverse(successor));
+                    }
+                }
+            }
+        }
+
+        let mut image_usage = vec![vk::ImageUsageFlags::empty(); self.images.len()];
+        let mut buffer_usage = vec![vk::BufferUsageFlags::empty(); self.buffers.len()];
+        let mut lifetimes = HashMap::new();
+
+        for (position, &index) in order.iter().enumerate() {
+            for access in self.passes[index].accesses.iter() {
+                lifetimes
+                    .entry(access.resource)
+                    .or_insert((position, position))
+                    .1 = position;
+
+                match access.resource {
+                    Resource::Image(image) => image_usage[image] |= access.image_usage,
+                    Resource::Buffer(buffer) => buffer_usage[buffer] |= access.buffer_usage,
+                }
+            }
+        }
+
+        Ok(CompiledGraph {
+            culled: (0..pass_count).filter(|&index| !alive[index]).collect(),
+            order,
+            barriers: Vec::new(),
+            final_barriers: Vec::new(),
+            image_usage,
+            buffer_usage,
+            lifetimes,
+        })
+    }
+
+    /// Works out the barriers of a scheduled graph. `aliases` pairs
+    /// transient resources placed in the same memory with the resource that
+    /// used it last, whose accesses the first use has to wait for.
+    pub(crate) fn synchronize(
+        &self,
+        compiled: &mut CompiledGraph,
+        output: &OutputTarget,
+        aliases: &[(Resource, Resource)],
+    ) {
+        let mut states: HashMap<Resource, ResourceState> = HashMap::new();
+        let mut barriers = Vec::with_capacity(compiled.order.len());
+
+        for &index in compiled.order.iter() {
+            let mut pass_barriers = Vec::new();
+
+            for access in self.passes[index].accesses.iter() {
+                if !states.contains_key(&access.resource) {
+                    let mut state = self.initial_state(access.resource, output);
+                    let previous = aliases
+                        .iter()
+                        .find(|(_, next)| *next == access.resource)
+                        .and_then(|(previous, _)| states.get(previous));
+                    if let Some(previous) = previous {
+                        state.write_stages = previous.write_stages | previous.read_stages;
+                        state.write_access = previous.write_access;
+                    }
+                    states.insert(access.resource, state);
+                }
+
+                let state = states.get_mut(&access.resource).unwrap();
+                pass_barriers.extend(state.apply(access));
+            }
+
+            barriers.push(pass_barriers);
+        }
+
+        // The output is handed over in the layout presentation or readback
+        // expects, imported images go back to the layout they came in.
+        let mut final_barriers = Vec::new();
+        for (index, image) in self.images.iter().enumerate() {
+            let resource = Resource::Image(index);
+            let access = match image.source {
+                ImageSource::Output => Access {
+                    stages: output.final_stages,
+                    access: output.final_access,
+                    layout: output.final_layout,
+                    ..Access::new(resource)
+                },
+                ImageSource::Imported { layout, .. } => Access {
+                    stages: vk::PipelineStageFlags::ALL_COMMANDS,
+                    access: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                    layout,
+                    ..Access::new(resource)
+                },
+                ImageSource::Transient(_) => continue,
+            };
+
+            let state = states
+                .entry(resource)
+                .or_insert_with(|| self.initial_state(resource, output));
+            if state.layout != access.layout {
+                final_barriers.extend(state.apply(&access));
+            }
+        }
+
+        compiled.barriers = barriers;
+        compiled.final_barriers = final_barriers;
+    }
+
+    /// The compiled form of the graph, for `RenderGraphDump`.
+    pub(crate) fn dump(
+        &self,
+        compiled: &CompiledGraph,
+        output: &OutputTarget,
+        aliases: &[(Resource, Resource)],
+    ) -> RenderGraphDump {
+        // Images come first in the dump's resources, then buffers.
+        let resource_index = |resource| match resource {
+            Resource::Image(index) => index,
+            Resource::Buffer(index) => self.images.len() + index,
+        };
+        let barrier_dump = |barrier: &Barrier| {
+            let is_image = match barrier.resource {
+                Resource::Image(_) => true,
+                Resource::Buffer(_) => false,
+            };
+
+            BarrierDump {
+                resource: resource_index(barrier.resource),
+                src_stages: flag_names(barrier.src_stages),
+                src_access: flag_names(barrier.src_access),
+                dst_stages: flag_names(barrier.dst_stages),
+                dst_access: flag_names(barrier.dst_access),
+                old_layout: is_image.then(|| format!("{:?}", barrier.old_layout)),
+                new_layout: is_image.then(|| format!("{:?}", barrier.new_layout)),
+            }
+        };
+        let resource_dump = |resource: Resource, kind, source| ResourceDump {
+            name: self.resource_name(resource).to_string(),
+            kind,
+            source,
+            format: None,
+            extent: None,
+            size: None,
+            usage: Vec::new(),
+            lifetime: compiled
+                .lifetimes
+                .get(&resource)
+                .map(|&(first, last)| [first, last]),
+            aliases: aliases
+                .iter()
+                .find(|(_, next)| *next == resource)
+                .map(|&(previous, _)| resource_index(previous)),
+        };
+
+        let images = self.images.iter().enumerate().map(|(index, image)| {
+            let source = match image.source {
+                ImageSource::Transient(_) => ResourceSource::Transient,
+                ImageSource::Output => ResourceSource::Output,
+                ImageSource::Imported { .. } => ResourceSource::Imported,
+            };
+            let extent = self.image_extent(index, output);
+
+            ResourceDump {
+                format: Some(format!("{:?}", self.image_format(index, output))),
+                extent: Some([extent.width, extent.height]),
+                usage: flag_names(compiled.image_usage[index]),
+                ..resource_dump(Resource::Image(index), ResourceKind::Image, source)
+            }
+        });
+        let buffers = self.buffers.iter().enumerate().map(|(index, buffer)| {
+            let source = match buffer.imported {
+                Some(_) => ResourceSource::Imported,
+                None => ResourceSource::Transient,
+            };
+
+            ResourceDump {
+                size: Some(buffer.size),
+                usage: flag_names(compiled.buffer_usage[index]),
+                ..resource_dump(Resource::Buffer(index), ResourceKind::Buffer, source)
+            }
+        });
+
+        let passes = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                let position = compiled.order.iter().position(|&ordered| ordered == index);
+                let accesses = pass
+                    .accesses
+                    .iter()
+                    .map(|access| AccessDump {
+                        resource: resource_index(access.resource),
+                        reads: access.reads,
+                        writes: access.writes,
+                        stages: flag_names(access.stages),
+                        access: flag_names(access.access),
+                        layout: match access.resource {
+                            Resource::Image(_) => Some(format!("{:?}", access.layout)),
+                            Resource::Buffer(_) => None,
+                        },
+                    })
+                    .collect();
+                let barriers = position
+                    .map(|position| {
+                        compiled.barriers[position]
+                            .iter()
+                            .map(barrier_dump)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                PassDump {
+                    name: pass.name.clone(),
+                    position,
+                    side_effect: pass.side_effect,
+                    accesses,
+                    barriers,
+                }
+            })
+            .collect();
+
+        RenderGraphDump {
+            passes,
+            resources: images.chain(buffers).collect(),
+            final_barriers: compiled.final_barriers.iter().map(barrier_dump).collect(),
+        }
+    }
+
+    /// Schedules the graph, reporting every declaration error. Only the
+    /// size, format and usage of `output` are looked at, so this can run
+    /// before the output image is known.
+    pub(crate) fn compile(&mut self, output: &OutputTarget) -> Result<CompiledGraph, VreError> {
+        // The output has to reach its final layout even if no pass uses it.
+        self.import_swapchain();
+        let compiled = self.schedule(output)?;
+        for &index in compiled.culled.iter() {
+            log::trace!("Culled render graph pass `{}`.", self.passes[index].name);
+        }
+
+        Ok(compiled)
+    }
+
+    /// Records the graph `compile` returned `compiled` for into
+    /// `recorder.command_buffer`, which must be in the recording state.
+    /// Returns the compiled form if `recorder.dump` is set.
+    pub(crate) fn record(
+        self,
+        mut compiled: CompiledGraph,
+        output: &OutputTarget,
+        recorder: GraphRecorder,
+    ) -> Result<Option<RenderGraphDump>, VreError> {
+        let device = recorder.device;
+        let command_buffer = recorder.command_buffer;
+
+        let formats: Vec<vk::Format> = (0..self.images.len())
+            .map(|index| self.image_format(index, output))
+            .collect();
+        let extents: Vec<vk::Extent2D> = (0..self.images.len())
+            .map(|index| self.image_extent(index, output))
+            .collect();
+
+        // Transient resources no remaining pass uses are never created.
+        let mut transients: Vec<Resource> = compiled
+            .lifetimes
+            .keys()
+            .copied()
+            .filter(|&resource| self.is_transient(resource))
+            .collect();
+        transients.sort();
+        let requests: Vec<TransientRequest> = transients
+            .iter()
+            .map(|&resource| {
+                let desc = match resource {
+                    Resource::Image(index) => TransientDesc::Image(ImageKey {
+                        format: formats[index],
+                        extent: extents[index],
+                        usage: compiled.image_usage[index],
+                    }),
+                    Resource::Buffer(index) => TransientDesc::Buffer {
+                        size: self.buffers[index].size,
+                        usage: compiled.buffer_usage[index],
+                    },
+                };
+                let (first, last) = compiled.lifetimes[&resource];
+
+                TransientRequest { desc, first, last }
+            })
+            .collect();
+        recorder.transients.acquire(&requests)?;
+
+        let aliases: Vec<(Resource, Resource)> = recorder
+            .transients
+            .aliases()
+            .iter()
+            .map(|&(previous, next)| (transients[previous], transients[next]))
+            .collect();
+        self.synchronize(&mut compiled, output, &aliases);
+        let dump = recorder
+            .dump
+            .then(|| self.dump(&compiled, output, &aliases));
+
+        let mut images: Vec<(vk::Image, vk::ImageView)> = self
+            .images
+            .iter()
+            .map(|image| match image.source {
+                ImageSource::Output => (output.image, output.view),
+                ImageSource::Imported { image, view, .. } => (image, view),
+                ImageSource::Transient(_) => (vk::Image::null(), vk::ImageView::null()),
+            })
+            .collect();
+        let mut buffers: Vec<vk::Buffer> = self
+            .buffers
+            .iter()
+            .map(|buffer| buffer.imported.unwrap_or_else(vk::Buffer::null))
+            .collect();
+        for (request, &resource) in transients.iter().enumerate() {
+            match (resource, recorder.transients.resource(request)) {
+                (Resource::Image(index), TransientResource::Image(image, view)) => {
+                    images[index] = (image, view)
+                }
+                (Resource::Buffer(index), TransientResource::Buffer(buffer)) => {
+                    buffers[index] = buffer
+                }
+                _ => unreachable!("Transient resource of the wrong kind."),
+            }
+        }
+
+        // Transient attachments aren't stored after their last use, which lets
+        // tilers keep them in tile memory.
+        let last_uses: Vec<Option<usize>> = (0..self.images.len())
+            .map(|index| {
+                let resource = Resource::Image(index);
+                compiled
+                    .lifetimes
+                    .get(&resource)
+                    .filter(|_| self.is_transient(resource))
+                    .map(|&(_, last)| last)
+            })
+            .collect();
+
+        let mut passes: Vec<Option<Pass>> = self.passes.into_iter().map(Some).collect();
+        for (position, &index) in compiled.order.iter().enumerate() {
+            let pass = passes[index].take().unwrap();
+            // The pass's timing includes waiting on its barriers.
+            let scope = recorder.timestamps.begin_scope(command_buffer, &pass.name);
+            record_barriers(
+                device,
+                command_buffer,
+                &compiled.barriers[position],
+                &images,
+                &formats,
+                &buffers,
+            );
+
+            let attachments: Vec<Attachment> = pass
+                .colors
+                .iter()
+                .chain(pass.depth.iter())
+                .copied()
+                .collect();
+            let render_area = attachments
+                .first()
+                .map(|attachment| extents[attachment.image.index]);
+            let context = PassContext {
+                device,
+                graph: self.id,
+                command_buffer,
+                images: &images,
+                buffers: &buffers,
+                render_area,
+                pipelines: recorder.pipelines,
+                timestamps: recorder.timestamps,
+                queries: recorder.queries,
+                scope,
+            };
+
+            let render_area = match render_area {
+                Some(render_area) => render_area,
+                None => {
+                    (pass.execute)(&context);
+                    recorder.queries.end_active(command_buffer);
+                    recorder.timestamps.end_scopes(command_buffer, scope);
+                    continue;
+                }
+            };
+
+            let attachment_key = |attachment: &Attachment| AttachmentKey {
+                format: formats[attachment.image.index],
+                load_op: attachment.load_op.to_vk(),
+                store_op: if last_uses[attachment.image.index] == Some(position) {
+                    vk::AttachmentStoreOp::DONT_CARE
+                } else {
+                    vk::AttachmentStoreOp::STORE
+                },
+            };
+            let render_pass = recorder.render_passes.get(&RenderPassKey {
+                colors: pass.colors.iter().map(attachment_key).collect(),
+                depth: pass.depth.as_ref().map(attachment_key),
+            })?;
+            let views: Vec<vk::ImageView> = attachments
+                .iter()
+                .map(|attachment| images[attachment.image.index].1)
+                .collect();
+            let framebuffer = recorder
+                .transients
+                .framebuffer(render_pass, &views, render_area)?;
+            let clear_values: Vec<vk::ClearValue> = attachments
+                .iter()
+                .map(|attachment| attachment.load_op.clear_value())
+                .collect();
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: render_area,
+            };
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: render_area.width as f32,
+                height: render_area.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(framebuffer)
+                .render_area(scissor)
+                .clear_values(&clear_values)
+                .build();
+
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            }
+            (pass.execute)(&context);
+            // Queries must end in the subpass they began in.
+            recorder.queries.end_active(command_buffer);
+            unsafe {
+                device.cmd_end_render_pass(command_buffer);
+            }
+            recorder.timestamps.end_scopes(command_buffer, scope);
+        }
+
+        record_barriers(
+            device,
+            command_buffer,
+            &compiled.final_barriers,
+            &images,
+            &formats,
+            &buffers,
+        );
+
+        Ok(dump)
+    }
+}
+
+/// Declares the resources a pass accesses. Dropping the builder without
+/// calling `execute` discards the pass.
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: String,
+    accesses: Vec<Access>,
+    colors: Vec<Attachment>,
+    depth: Option<Attachment>,
+    side_effect: bool,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    /// Renders into `image` as the next color attachment.
+    pub fn color_attachment(mut self, image: ImageHandle, load_op: LoadOp) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        if let LoadOp::ClearDepth(_) = load_op {
+            self.error(format!(
+                "color attachment `{}` is cleared to a depth value",
+                self.graph.images[image.index].name
+            ));
+        }
+
+        let load = load_op == LoadOp::Load;
+        self.colors.push(Attachment { image, load_op });
+        self.access(Access {
+            stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: if load {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            } else {
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            },
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            reads: load,
+            writes: true,
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Tests against and writes `image` as the depth attachment.
+    pub fn depth_attachment(mut self, image: ImageHandle, load_op: LoadOp) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        if let LoadOp::ClearColor(_) = load_op {
+            self.error(format!(
+                "depth attachment `{}` is cleared to a color",
+                self.graph.images[image.index].name
+            ));
+        }
+        if self.depth.is_some() {
+            self.error("more than one depth attachment".to_string());
+        }
+
+        self.depth = Some(Attachment { image, load_op });
+        self.access(Access {
+            stages: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            reads: load_op == LoadOp::Load,
+            writes: true,
+            image_usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Samples `image` from shaders in `stages`.
+    pub fn sampled_image(mut self, image: ImageHandle, stages: vk::ShaderStageFlags) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        let stages = self.shader_stages(stages);
+        self.access(Access {
+            stages,
+            access: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            reads: true,
+            image_usage: vk::ImageUsageFlags::SAMPLED,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Loads from `image` as a storage image in shaders in `stages`.
+    pub fn read_storage_image(mut self, image: ImageHandle, stages: vk::ShaderStageFlags) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        let stages = self.shader_stages(stages);
+        self.access(Access {
+            stages,
+            access: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::GENERAL,
+            reads: true,
+            image_usage: vk::ImageUsageFlags::STORAGE,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Stores to `image` as a storage image in shaders in `stages`. Also
+    /// declare a read to keep the previous contents.
+    pub fn write_storage_image(mut self, image: ImageHandle, stages: vk::ShaderStageFlags) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        let stages = self.shader_stages(stages);
+        self.access(Access {
+            stages,
+            access: vk::AccessFlags::SHADER_WRITE,
+            layout: vk::ImageLayout::GENERAL,
+            writes: true,
+            image_usage: vk::ImageUsageFlags::STORAGE,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Copies or blits from `image`.
+    pub fn transfer_source(mut self, image: ImageHandle) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        self.access(Access {
+            stages: vk::PipelineStageFlags::TRANSFER,
+            access: vk::AccessFlags::TRANSFER_READ,
+            layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            reads: true,
+            image_usage: vk::ImageUsageFlags::TRANSFER_SRC,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    /// Copies, blits or clears into `image`.
+    pub fn transfer_destination(mut self, image: ImageHandle) -> Self {
+        if !self.owns_image(image) {
+            return self;
+        }
+        self.access(Access {
+            stages: vk::PipelineStageFlags::TRANSFER,
+            access: vk::AccessFlags::TRANSFER_WRITE,
+            layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            writes: true,
+            image_usage: vk::ImageUsageFlags::TRANSFER_DST,
+            ..Access::new(Resource::Image(image.index))
+        })
+    }
+
+    pub fn read_buffer(mut self, buffer: BufferHandle, access: BufferAccess) -> Self {
+        if !self.owns_buffer(buffer) {
+            return self;
+        }
+        let (stages, flags, usage) = match access {
+            BufferAccess::Vertex => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+            ),
+            BufferAccess::Index => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::INDEX_READ,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+            ),
+            BufferAccess::Indirect => (
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ,
+                vk::BufferUsageFlags::INDIRECT_BUFFER,
+            ),
+            BufferAccess::Uniform(stages) => (
+                self.shader_stages(stages),
+                vk::AccessFlags::UNIFORM_READ,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+            ),
+            BufferAccess::Storage(stages) => (
+                self.shader_stages(stages),
+                vk::AccessFlags::SHADER_READ,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            ),
+            BufferAccess::Transfer => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ),
+        };
+
+        self.access(Access {
+            stages,
+            access: flags,
+            reads: true,
+            buffer_usage: usage,
+            ..Access::new(Resource::Buffer(buffer.index))
+        })
+    }
+
+    /// Writes `buffer`, which is only possible as a storage buffer or copy
+    /// destination. Also declare a read to keep the previous contents.
+    pub fn write_buffer(mut self, buffer: BufferHandle, access: BufferAccess) -> Self {
+        if !self.owns_buffer(buffer) {
+            return self;
+        }
+        let (stages, flags, usage) = match access {
+            BufferAccess::Storage(stages) => (
+                self.shader_stages(stages),
+                vk::AccessFlags::SHADER_WRITE,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            ),
+            BufferAccess::Transfer => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::BufferUsageFlags::TRANSFER_DST,
+            ),
+            BufferAccess::Vertex
+            | BufferAccess::Index
+            | BufferAccess::Indirect
+            | BufferAccess::Uniform(_) => {
+                self.error(format!(
+                    "buffer `{}` is written as {:?}",
+                    self.graph.buffers[buffer.index].name, access
+                ));
+                return self;
+            }
+        };
+
+        self.access(Access {
+            stages,
+            access: flags,
+            writes: true,
+            buffer_usage: usage,
+            ..Access::new(Resource::Buffer(buffer.index))
+        })
+    }
+
+    /// Keeps the pass even if nothing reads what it writes, e.g. because it
+    /// writes through handles the graph doesn't know about.
+    pub fn side_effect(mut self) -> Self {
+        self.side_effect = true;
+        self
+    }
+
+    /// Adds the pass with the function recording its commands. Passes with
+    /// attachments are recorded inside a render pass, with the viewport and
+    /// scissor covering the attachments.
+    pub fn execute<F>(self, execute: F)
+    where
+        F: FnOnce(&PassContext) + 'a,
+    {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            accesses: self.accesses,
+            colors: self.colors,
+            depth: self.depth,
+            side_effect: self.side_effect,
+            execute: Box::new(execute),
+        });
+    }
+
+    /// Whether `image` was created by this graph. Records an error if not,
+    /// since it can't be looked up.
+    fn owns_image(&mut self, image: ImageHandle) -> bool {
+        let owned = image.graph == self.graph.id;
+        if !owned {
+            self.error("image handle from another render graph".to_string());
+        }
+        owned
+    }
+
+    fn owns_buffer(&mut self, buffer: BufferHandle) -> bool {
+        let owned = buffer.graph == self.graph.id;
+        if !owned {
+            self.error("buffer handle from another render graph".to_string());
+        }
+        owned
+    }
+
+    /// Merges `access` into the pass's earlier use of the same resource.
+    fn access(mut self, access: Access) -> Self {
+        let existing = self
+            .accesses
+            .iter_mut()
+            .find(|existing| existing.resource == access.resource);
+
+        match existing {
+            Some(existing) if existing.layout != access.layout => {
+                let message = format!(
+                    "`{}` is used in both {:?} and {:?}",
+                    self.graph.resource_name(access.resource),
+                    existing.layout,
+                    access.layout
+                );
+                self.error(message);
+            }
+            Some(existing) => {
+                existing.stages |= access.stages;
+                existing.access |= access.access;
+                existing.reads |= access.reads;
+                existing.writes |= access.writes;
+                existing.image_usage |= access.image_usage;
+                existing.buffer_usage |= access.buffer_usage;
+            }
+            None => self.accesses.push(access),
+        }
+
+        self
+    }
+
+    fn shader_stages(&self, stages: vk::ShaderStageFlags) -> vk::PipelineStageFlags {
+        let mapping = [
+            (
+                vk::ShaderStageFlags::VERTEX,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+            ),
+            (
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                vk::PipelineStageFlags::TESSELLATION_CONTROL_SHADER,
+            ),
+            (
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER,
+            ),
+            (
+                vk::ShaderStageFlags::GEOMETRY,
+                vk::PipelineStageFlags::GEOMETRY_SHADER,
+            ),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+            (
+                vk::ShaderStageFlags::COMPUTE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+        ];
+
+        let pipeline_stages = mapping
+            .iter()
+            .filter(|(shader_stage, _)| stages.contains(*shader_stage))
+            .fold(vk::PipelineStageFlags::empty(), |acc, (_, stage)| {
+                acc | *stage
+            });
+
+        // An empty mask can't be waited on; fall back to every stage.
+        if pipeline_stages.is_empty() {
+            vk::PipelineStageFlags::ALL_COMMANDS
+        } else {
+            pipeline_stages
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.graph
+            .errors
+            .push(format!("pass `{}`: {}", self.name, message));
+    }
+}
+
+impl Access {
+    fn new(resource: Resource) -> Self {
+        Self {
+            resource,
+            stages: vk::PipelineStageFlags::empty(),
+            access: vk::AccessFlags::empty(),
+            layout: vk::ImageLayout::UNDEFINED,
+            reads: false,
+            writes: false,
+            image_usage: vk::ImageUsageFlags::empty(),
+            buffer_usage: vk::BufferUsageFlags::empty(),
+        }
+    }
+}
+
+/// Handles and device objects available while a pass records its commands.
+pub struct PassContext<'c> {
+    device: &'c Device,
+    graph: GraphId,
+    command_buffer: vk::CommandBuffer,
+    images: &'c [(vk::Image, vk::ImageView)],
+    buffers: &'c [vk::Buffer],
+    render_area: Option<vk::Extent2D>,
+    pipelines: &'c PipelineManager,
+    timestamps: &'c TimestampQueries,
+    queries: &'c FrameQueries,
+    /// Depth of the pass's own timing scope.
+    scope: usize,
+}
+
+impl<'c> PassContext<'c> {
+    pub fn device(&self) -> &Device {
+        self.device
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// The image behind `image`. Only images the pass declared are in the
+    /// layout it asked for. Panics if `image` belongs to another graph.
+    pub fn image(&self, image: ImageHandle) -> vk::Image {
+        self.images[self.image_index(image)].0
+    }
+
+    pub fn image_view(&self, image: ImageHandle) -> vk::ImageView {
+        self.images[self.image_index(image)].1
+    }
+
+    /// The buffer behind `buffer`. Panics if `buffer` belongs to another
+    /// graph.
+    pub fn buffer(&self, buffer: BufferHandle) -> vk::Buffer {
+        assert!(
+            buffer.graph == self.graph,
+            "buffer handle from another render graph"
+        );
+        self.buffers[buffer.index]
+    }
+
+    fn image_index(&self, image: ImageHandle) -> usize {
+        assert!(
+            image.graph == self.graph,
+            "image handle from another render graph"
+        );
+        image.index
+    }
+
+    /// Size of the pass's attachments, `None` if it has none.
+    pub fn render_area(&self) -> Option<vk::Extent2D> {
+        self.render_area
+    }
+
+    pub fn pipeline(&self, id: PipelineId) -> vk::Pipeline {
+        self.pipelines.pipeline(id)
+    }
+
+    pub fn pipeline_layout(&self, id: PipelineId) -> &PipelineLayout {
+        self.pipelines.layout(id)
+    }
+
+    /// Starts timing the commands recorded from here on as a GPU scope
+    /// called `name`, nested in the pass's own scope and any scope still
+    /// open. Scopes left open are closed when the pass returns.
+    pub fn begin_gpu_scope(&self, name: &str) {
+        self.timestamps.begin_scope(self.command_buffer, name);
+    }
+
+    /// Ends the innermost scope started with `begin_gpu_scope`.
+    pub fn end_gpu_scope(&self) {
+        self.timestamps
+            .end_scope(self.command_buffer, self.scope + 1);
+    }
+
+    /// Starts counting the invocations and primitives of the commands
+    /// recorded from here on, read back with
+    /// `Renderer::pipeline_statistics(name)`. Queries with the same name are
+    /// summed per frame. Ignored if the device doesn't support pipeline
+    /// statistics or another query of this type is active; queries left
+    /// active end when the pass returns.
+    pub fn begin_pipeline_statistics(&self, name: &str) {
+        self.queries.begin_statistics(self.command_buffer, name);
+    }
+
+    pub fn end_pipeline_statistics(&self) {
+        self.queries.end_statistics(self.command_buffer);
+    }
+
+    /// Starts counting the samples passing the depth and stencil tests in
+    /// the draws recorded from here on, read back with
+    /// `Renderer::occlusion_samples(key)`. Only valid inside the pass's
+    /// render area and ended like pipeline statistics.
+    pub fn begin_occlusion_query(&self, key: u64) {
+        self.queries.begin_occlusion(self.command_buffer, key);
+    }
+
+    pub fn end_occlusion_query(&self) {
+        self.queries.end_occlusion(self.command_buffer);
+    }
+}
+
+/// The image a frame is rendered into and how it is handed over afterwards.
+pub(crate) struct OutputTarget {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// Usage the image was created with. Passes may not access it in other
+    /// ways.
+    pub usage: vk::ImageUsageFlags,
+    /// Stages that must finish before the image may be written, e.g. the
+    /// stage waiting on the image acquisition.
+    pub initial_stages: vk::PipelineStageFlags,
+    pub final_layout: vk::ImageLayout,
+    pub final_stages: vk::PipelineStageFlags,
+    pub final_access: vk::AccessFlags,
+}
+
+/// Device objects a graph is recorded with.
+pub(crate) struct GraphRecorder<'r> {
+    pub device: &'r Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub render_passes: &'r RenderPassCache,
+    pub transients: &'r mut TransientPool,
+    pub pipelines: &'r PipelineManager,
+    /// Each pass is timed in a scope named after it.
+    pub timestamps: &'r TimestampQueries,
+    /// Queries passes leave active are ended after them.
+    pub queries: &'r FrameQueries,
+    /// Whether to return the compiled form of the graph.
+    pub dump: bool,
+}
+
+/// An execution and memory dependency on one resource, and for images a
+/// layout transition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Barrier {
+    pub resource: Resource,
+    pub src_stages: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_stages: vk::PipelineStageFlags,
+    pub dst_access: vk::AccessFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+/// The passes of a graph in execution order, with the barriers recorded
+/// around them.
+pub(crate) struct CompiledGraph {
+    /// Indices of the remaining passes in execution order.
+    pub order: Vec<usize>,
+    /// Indices of the passes whose results are never used.
+    pub culled: Vec<usize>,
+    /// Barriers recorded before each pass in `order`.
+    pub barriers: Vec<Vec<Barrier>>,
+    /// Barriers recorded after the last pass.
+    pub final_barriers: Vec<Barrier>,
+    /// Usage of every image by the remaining passes, empty for unused ones.
+    pub image_usage: Vec<vk::ImageUsageFlags>,
+    pub buffer_usage: Vec<vk::BufferUsageFlags>,
+    /// Positions in `order` of the first and last pass using each resource.
+    pub lifetimes: HashMap<Resource, (usize, usize)>,
+}
+
+/// What the passes recorded so far did to a resource.
+#[derive(Clone, Copy, Debug)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    /// Stages and accesses of the last write, including layout transitions.
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    /// Stages that read the resource since the last write.
+    read_stages: vk::PipelineStageFlags,
+    /// Stages and accesses the last write has been made visible to.
+    visible_stages: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+}
+
+impl Default for ResourceState {
+    fn default() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            write_stages: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            read_stages: vk::PipelineStageFlags::empty(),
+            visible_stages: vk::PipelineStageFlags::empty(),
+            visible_access: vk::AccessFlags::empty(),
+        }
+    }
+}
+
+impl ResourceState {
+    /// Records `access` and returns the barrier it needs, if any. Reads
+    /// wait for the last write unless it is already visible to them; writes
+    /// and layout transitions also wait for the reads since.
+    fn apply(&mut self, access: &Access) -> Option<Barrier> {
+        let is_image = match access.resource {
+            Resource::Image(_) => true,
+            Resource::Buffer(_) => false,
+        };
+        let transition = is_image && self.layout != access.layout;
+        let overwrites = access.writes || transition;
+        let mut src_stages = vk::PipelineStageFlags::empty();
+        let mut src_access = vk::AccessFlags::empty();
+        let mut needed = transition;
+
+        if !self.write_stages.is_empty() {
+            let visible = self.visible_stages.contains(access.stages)
+                && self.visible_access.contains(access.access);
+            if overwrites || !visible {
+                src_stages |= self.write_stages;
+                src_access |= self.write_access;
+                needed = true;
+            }
+        }
+        if overwrites && !self.read_stages.is_empty() {
+            src_stages |= self.read_stages;
+            needed = true;
+        }
+
+        let barrier = Barrier {
+            resource: access.resource,
+            src_stages,
+            src_access,
+            dst_stages: access.stages,
+            dst_access: access.access,
+            old_layout: self.layout,
+            new_layout: access.layout,
+        };
+
+        if access.writes {
+            self.write_stages = access.stages;
+            self.write_access = access.access & WRITE_ACCESS;
+            self.read_stages = vk::PipelineStageFlags::empty();
+            self.visible_stages = vk::PipelineStageFlags::empty();
+            self.visible_access = vk::AccessFlags::empty();
+        } else if transition {
+            // The transition is a write that the barrier already made
+            // visible to this access.
+            self.write_stages = access.stages;
+            self.write_access = vk::AccessFlags::empty();
+            self.read_stages = access.stages;
+            self.visible_stages = access.stages;
+            self.visible_access = access.access;
+        } else {
+            self.read_stages |= access.stages;
+            if needed {
+                self.visible_stages |= access.stages;
+                self.visible_access |= access.access;
+            }
+        }
+        self.layout = access.layout;
+
+        needed.then_some(barrier)
+    }
+}
+
+/// Records `barriers` as a single pipeline barrier.
+fn record_barriers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    barriers: &[Barrier],
+    images: &[(vk::Image, vk::ImageView)],
+    formats: &[vk::Format],
+    buffers: &[vk::Buffer],
+) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    let mut src_stages = vk::PipelineStageFlags::empty();
+    let mut dst_stages = vk::PipelineStageFlags::empty();
+    let mut image_barriers = Vec::new();
+    let mut buffer_barriers = Vec::new();
+
+    for barrier in barriers {
+        src_stages |= barrier.src_stages;
+        dst_stages |= barrier.dst_stages;
+
+        match barrier.resource {
+            Resource::Image(index) => image_barriers.push(
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)
+                    .old_layout(barrier.old_layout)
+                    .new_layout(barrier.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(images[index].0)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: texture::aspect_mask(formats[index]),
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    })
+                    .build(),
+            ),
+            Resource::Buffer(index) => buffer_barriers.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffers[index])
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            ),
+        }
+    }
+
+    // Nothing to wait for before the first use of a resource.
+    if src_stages.is_empty() {
+        src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output() -> OutputTarget {
+        OutputTarget {
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            format: vk::Format::B8G8R8A8_UNORM,
+            extent: vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            initial_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            final_access: vk::AccessFlags::empty(),
+        }
+    }
+
+    fn schedule_error(graph: &RenderGraph) -> String {
+        match graph.schedule(&output()) {
+            Err(VreError::RenderGraph(error)) => error,
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("graph was scheduled"),
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_handles() {
+        let mut other = RenderGraph::new();
+        let first_foreign_image =
+            other.create_image("a", ImageDesc::new(vk::Format::R8G8B8A8_UNORM, 4, 4));
+        let foreign_image =
+            other.create_image("b", ImageDesc::new(vk::Format::R8G8B8A8_UNORM, 4, 4));
+        let foreign_buffer = other.create_buffer("c", 16);
+
+        let mut graph = RenderGraph::new();
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("blit")
+            .transfer_source(foreign_image)
+            .transfer_destination(output_image)
+            .execute(|_| {});
+        assert_eq!(
+            schedule_error(&graph),
+            "pass `blit`: image handle from another render graph"
+        );
+
+        let mut graph = RenderGraph::new();
+        graph
+            .add_pass("fill")
+            .write_buffer(foreign_buffer, BufferAccess::Transfer)
+            .side_effect()
+            .execute(|_| {});
+        assert_eq!(
+            schedule_error(&graph),
+            "pass `fill`: buffer handle from another render graph"
+        );
+
+        // Handles are rejected even if the index exists in this graph.
+        let mut graph = RenderGraph::new();
+        graph.create_image("a", ImageDesc::new(vk::Format::R8G8B8A8_UNORM, 4, 4));
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("blit")
+            .transfer_source(first_foreign_image)
+            .transfer_destination(output_image)
+            .execute(|_| {});
+        assert_eq!(
+            schedule_error(&graph),
+            "pass `blit`: image handle from another render graph"
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_output_usage() {
+        let mut graph = RenderGraph::new();
+        let source =
+            graph.create_image("source", ImageDesc::new(vk::Format::B8G8R8A8_UNORM, 64, 64));
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("clear")
+            .color_attachment(source, LoadOp::ClearColor([0.0; 4]))
+            .execute(|_| {});
+        graph
+            .add_pass("blit")
+            .transfer_source(source)
+            .transfer_destination(output_image)
+            .execute(|_| {});
+        assert_eq!(
+            schedule_error(&graph),
+            "pass `blit` uses the output image as TRANSFER_DST, which it doesn't support"
+        );
+
+        let mut output = output();
+        output.usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        graph.schedule(&output).unwrap();
+    }
+
+    fn buffer_access(
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+        writes: bool,
+    ) -> Access {
+        Access {
+            stages,
+            access,
+            reads: !writes,
+            writes,
+            ..Access::new(Resource::Buffer(0))
+        }
+    }
+
+    fn image_access(
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+        layout: vk::ImageLayout,
+        writes: bool,
+    ) -> Access {
+        Access {
+            stages,
+            access,
+            layout,
+            reads: !writes,
+            writes,
+            ..Access::new(Resource::Image(0))
+        }
+    }
+
+    fn barrier(
+        resource: Resource,
+        src: (vk::PipelineStageFlags, vk::AccessFlags),
+        dst: (vk::PipelineStageFlags, vk::AccessFlags),
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+    ) -> Barrier {
+        Barrier {
+            resource,
+            src_stages: src.0,
+            src_access: src.1,
+            dst_stages: dst.0,
+            dst_access: dst.1,
+            old_layout: layouts.0,
+            new_layout: layouts.1,
+        }
+    }
+
+    const NO_LAYOUT: (vk::ImageLayout, vk::ImageLayout) =
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::UNDEFINED);
+
+    #[test]
+    fn waits_for_writes_before_reads() {
+        let compute_write = (
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+        let vertex_read = (
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        );
+        let fragment_read = (
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let mut state = ResourceState::default();
+
+        assert_eq!(
+            state.apply(&buffer_access(compute_write.0, compute_write.1, true)),
+            None
+        );
+        assert_eq!(
+            state.apply(&buffer_access(vertex_read.0, vertex_read.1, false)),
+            Some(barrier(
+                Resource::Buffer(0),
+                compute_write,
+                vertex_read,
+                NO_LAYOUT
+            ))
+        );
+        // The write is already visible to the vertex input stage.
+        assert_eq!(
+            state.apply(&buffer_access(vertex_read.0, vertex_read.1, false)),
+            None
+        );
+        // But not to fragment shaders.
+        assert_eq!(
+            state.apply(&buffer_access(fragment_read.0, fragment_read.1, false)),
+            Some(barrier(
+                Resource::Buffer(0),
+                compute_write,
+                fragment_read,
+                NO_LAYOUT
+            ))
+        );
+        assert_eq!(
+            state.apply(&buffer_access(fragment_read.0, fragment_read.1, false)),
+            None
+        );
+    }
+
+    #[test]
+    fn waits_for_reads_before_writes() {
+        let transfer_write = (
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+        let mut state = ResourceState::default();
+
+        // Nothing was written yet, so reads need no barrier.
+        assert_eq!(
+            state.apply(&buffer_access(
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                false
+            )),
+            None
+        );
+        assert_eq!(
+            state.apply(&buffer_access(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false
+            )),
+            None
+        );
+        // Overwriting only needs an execution dependency on the reads.
+        assert_eq!(
+            state.apply(&buffer_access(transfer_write.0, transfer_write.1, true)),
+            Some(barrier(
+                Resource::Buffer(0),
+                (
+                    vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::empty()
+                ),
+                transfer_write,
+                NO_LAYOUT
+            ))
+        );
+        // A second write waits for the first.
+        assert_eq!(
+            state.apply(&buffer_access(transfer_write.0, transfer_write.1, true)),
+            Some(barrier(
+                Resource::Buffer(0),
+                transfer_write,
+                transfer_write,
+                NO_LAYOUT
+            ))
+        );
+    }
+
+    #[test]
+    fn transitions_image_layouts() {
+        let color_write = (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        let sampled_read = (
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let storage_read = (
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let color = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        let sampled = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let general = vk::ImageLayout::GENERAL;
+        let mut state = ResourceState::default();
+
+        assert_eq!(
+            state.apply(&image_access(color_write.0, color_write.1, color, true)),
+            Some(barrier(
+                Resource::Image(0),
+                (vk::PipelineStageFlags::empty(), vk::AccessFlags::empty()),
+                color_write,
+                (vk::ImageLayout::UNDEFINED, color)
+            ))
+        );
+        assert_eq!(
+            state.apply(&image_access(
+                sampled_read.0,
+                sampled_read.1,
+                sampled,
+                false
+            )),
+            Some(barrier(
+                Resource::Image(0),
+                color_write,
+                sampled_read,
+                (color, sampled)
+            ))
+        );
+        assert_eq!(
+            state.apply(&image_access(
+                sampled_read.0,
+                sampled_read.1,
+                sampled,
+                false
+            )),
+            None
+        );
+        // Moving to another layout for a read still waits for earlier reads.
+        assert_eq!(
+            state.apply(&image_access(
+                storage_read.0,
+                storage_read.1,
+                general,
+                false
+            )),
+            Some(barrier(
+                Resource::Image(0),
+                (sampled_read.0, vk::AccessFlags::empty()),
+                storage_read,
+                (sampled, general)
+            ))
+        );
+        assert_eq!(
+            state.apply(&image_access(
+                storage_read.0,
+                storage_read.1,
+                general,
+                false
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn culls_unused_passes() {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let mut graph = RenderGraph::new();
+        let shadow_map = graph.create_image("shadow map", ImageDesc::new(format, 64, 64));
+        let debug_view = graph.create_image("debug view", ImageDesc::new(format, 64, 64));
+        let scratch = graph.create_buffer("scratch", 256);
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("shadows")
+            .color_attachment(shadow_map, LoadOp::ClearColor([1.0; 4]))
+            .execute(|_| {});
+        graph
+            .add_pass("debug")
+            .sampled_image(shadow_map, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(debug_view, LoadOp::DontCare)
+            .execute(|_| {});
+        graph
+            .add_pass("scratch")
+            .write_buffer(
+                scratch,
+                BufferAccess::Storage(vk::ShaderStageFlags::COMPUTE),
+            )
+            .execute(|_| {});
+        graph
+            .add_pass("lighting")
+            .sampled_image(shadow_map, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(output_image, LoadOp::DontCare)
+            .execute(|_| {});
+        graph.add_pass("marker").side_effect().execute(|_| {});
+
+        let compiled = graph.schedule(&output()).unwrap();
+        assert_eq!(compiled.order, vec![0, 3, 4]);
+        assert_eq!(compiled.culled, vec![1, 2]);
+        assert_eq!(
+            compiled.image_usage,
+            vec![
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageUsageFlags::empty(),
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ]
+        );
+        assert_eq!(compiled.buffer_usage, vec![vk::BufferUsageFlags::empty()]);
+        assert_eq!(compiled.lifetimes[&Resource::Image(0)], (0, 1));
+        assert!(!compiled.lifetimes.contains_key(&Resource::Image(1)));
+    }
+
+    #[test]
+    fn keeps_passes_in_declaration_order() {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let mut graph = RenderGraph::new();
+        let history = graph.create_image("history", ImageDesc::new(format, 64, 64));
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("seed")
+            .color_attachment(history, LoadOp::ClearColor([0.0; 4]))
+            .execute(|_| {});
+        graph
+            .add_pass("resolve")
+            .sampled_image(history, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(output_image, LoadOp::DontCare)
+            .execute(|_| {});
+        graph
+            .add_pass("accumulate")
+            .color_attachment(history, LoadOp::Load)
+            .execute(|_| {});
+        graph
+            .add_pass("composite")
+            .sampled_image(history, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(output_image, LoadOp::Load)
+            .execute(|_| {});
+
+        let compiled = graph.schedule(&output()).unwrap();
+        assert_eq!(compiled.order, vec![0, 1, 2, 3]);
+        assert!(compiled.culled.is_empty());
+    }
+
+    #[test]
+    fn synchronizes_passes() {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let mut graph = RenderGraph::new();
+        let shadow_map = graph.create_image("shadow map", ImageDesc::new(format, 64, 64));
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("shadows")
+            .color_attachment(shadow_map, LoadOp::ClearColor([1.0; 4]))
+            .execute(|_| {});
+        graph
+            .add_pass("lighting")
+            .sampled_image(shadow_map, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(output_image, LoadOp::ClearColor([0.0; 4]))
+            .execute(|_| {});
+        graph
+            .add_pass("overlay")
+            .sampled_image(shadow_map, vk::ShaderStageFlags::FRAGMENT)
+            .color_attachment(output_image, LoadOp::Load)
+            .execute(|_| {});
+
+        let output = output();
+        let mut compiled = graph.schedule(&output).unwrap();
+        graph.synchronize(&mut compiled, &output, &[]);
+
+        let color = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        let color_write = (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        let color_load = (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        let sampled_read = (
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let shadows = Resource::Image(shadow_map.index);
+        let swapchain = Resource::Image(output_image.index);
+
+        assert_eq!(
+            compiled.barriers,
+            vec![
+                vec![barrier(
+                    shadows,
+                    (vk::PipelineStageFlags::empty(), vk::AccessFlags::empty()),
+                    color_write,
+                    (vk::ImageLayout::UNDEFINED, color)
+                )],
+                vec![
+                    barrier(
+                        shadows,
+                        color_write,
+                        sampled_read,
+                        (color, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    ),
+                    // Waits for the acquire semaphore's stage.
+                    barrier(
+                        swapchain,
+                        (output.initial_stages, vk::AccessFlags::empty()),
+                        color_write,
+                        (vk::ImageLayout::UNDEFINED, color)
+                    ),
+                ],
+                // The shadow map is already visible to fragment shaders.
+                vec![barrier(swapchain, color_write, color_load, (color, color))],
+            ]
+        );
+        assert_eq!(
+            compiled.final_barriers,
+            vec![barrier(
+                swapchain,
+                color_write,
+                (output.final_stages, output.final_access),
+                (color, vk::ImageLayout::PRESENT_SRC_KHR)
+            )]
+        );
+    }
+}
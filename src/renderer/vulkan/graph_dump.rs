@@ -0,0 +1,367 @@
+use std::{fmt::Debug, fs, io, path::Path};
+
+use serde::Serialize;
+
+/// The compiled form of a rendered `RenderGraph`: its passes in declaration
+/// order, the resources they access, the barriers recorded before each pass
+/// and the passes that were culled. Written as Graphviz DOT for viewing and
+/// as JSON for tools.
+#[derive(Clone, Debug, Serialize)]
+pub struct RenderGraphDump {
+    pub(crate) passes: Vec<PassDump>,
+    pub(crate) resources: Vec<ResourceDump>,
+    /// Barriers recorded after the last pass, handing the output and
+    /// imported images over.
+    pub(crate) final_barriers: Vec<BarrierDump>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct PassDump {
+    pub name: String,
+    /// Position in execution order, `None` if the pass was culled.
+    pub position: Option<usize>,
+    pub side_effect: bool,
+    pub accesses: Vec<AccessDump>,
+    /// Barriers recorded right before the pass.
+    pub barriers: Vec<BarrierDump>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ResourceKind {
+    Image,
+    Buffer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ResourceSource {
+    Transient,
+    Output,
+    Imported,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ResourceDump {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub source: ResourceSource,
+    /// Format and `[width, height]` of images.
+    pub format: Option<String>,
+    pub extent: Option<[u32; 2]>,
+    /// Size of buffers in bytes.
+    pub size: Option<u64>,
+    pub usage: Vec<String>,
+    /// Positions in execution order of the first and last pass using the
+    /// resource, `None` if no remaining pass does.
+    pub lifetime: Option<[usize; 2]>,
+    /// Resource whose memory a transient resource reuses.
+    pub aliases: Option<usize>,
+}
+
+/// An edge between a pass and a resource, indexing
+/// `RenderGraphDump::resources`.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct AccessDump {
+    pub resource: usize,
+    pub reads: bool,
+    pub writes: bool,
+    pub stages: Vec<String>,
+    pub access: Vec<String>,
+    /// Layout images are used in, `None` for buffers.
+    pub layout: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct BarrierDump {
+    pub resource: usize,
+    pub src_stages: Vec<String>,
+    pub src_access: Vec<String>,
+    pub dst_stages: Vec<String>,
+    pub dst_access: Vec<String>,
+    /// Layout transition of images, `None` for buffers.
+    pub old_layout: Option<String>,
+    pub new_layout: Option<String>,
+}
+
+impl RenderGraphDump {
+    /// The graph as JSON, with flags and formats spelled like their Vulkan
+    /// names and resources referred to by their index in `resources`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Render graph dumps are always serializable.")
+    }
+
+    /// The graph in Graphviz DOT format, e.g. for `dot -Tsvg`. Passes are
+    /// boxes labelled with their position in execution order, culled ones
+    /// dashed. Edges run from resources to the passes reading them and from
+    /// passes to the resources they write, labelled with the barrier
+    /// recorded before the pass.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph render_graph {\n    rankdir=LR;\n");
+
+        for (index, resource) in self.resources.iter().enumerate() {
+            let mut label = escape(&resource.name);
+            if let (Some(format), Some([width, height])) = (&resource.format, resource.extent) {
+                label += &format!("\\n{} {}x{}", format, width, height);
+            }
+            if let Some(size) = resource.size {
+                label += &format!("\\n{} bytes", size);
+            }
+            let style = match resource.source {
+                ResourceSource::Transient if resource.lifetime.is_none() => "dashed",
+                ResourceSource::Transient => "solid",
+                ResourceSource::Output | ResourceSource::Imported => "bold",
+            };
+            let shape = match resource.kind {
+                ResourceKind::Image => "ellipse",
+                ResourceKind::Buffer => "cylinder",
+            };
+            dot += &format!(
+                "    r{} [shape={}, style={}, label=\"{}\"];\n",
+                index, shape, style, label
+            );
+            if let Some(previous) = resource.aliases {
+                dot += &format!(
+                    "    r{} -> r{} [style=dotted, arrowhead=none, label=\"aliases\"];\n",
+                    previous, index
+                );
+            }
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let (label, style) = match pass.position {
+                Some(position) => (format!("#{} {}", position, escape(&pass.name)), "solid"),
+                None => (format!("{}\\n(culled)", escape(&pass.name)), "dashed"),
+            };
+            dot += &format!(
+                "    p{} [shape=box, style={}, label=\"{}\"];\n",
+                index, style, label
+            );
+
+            for access in pass.accesses.iter() {
+                let barrier = pass
+                    .barriers
+                    .iter()
+                    .find(|barrier| barrier.resource == access.resource)
+                    .map(barrier_label)
+                    .unwrap_or_default();
+                if access.reads {
+                    dot += &format!(
+                        "    r{} -> p{} [label=\"{}\"];\n",
+                        access.resource, index, barrier
+                    );
+                }
+                if access.writes {
+                    // A barrier already labels the read edge.
+                    let barrier = if access.reads { "" } else { &barrier };
+                    dot += &format!(
+                        "    p{} -> r{} [color=red, label=\"{}\"];\n",
+                        index, access.resource, barrier
+                    );
+                }
+            }
+        }
+
+        if !self.final_barriers.is_empty() {
+            dot += "    end [shape=doublecircle, label=\"end\"];\n";
+            for barrier in self.final_barriers.iter() {
+                dot += &format!(
+                    "    r{} -> end [style=dashed, label=\"{}\"];\n",
+                    barrier.resource,
+                    barrier_label(barrier)
+                );
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn save_dot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_dot())
+    }
+}
+
+/// The names of the bits set in a Vulkan flags value.
+pub(crate) fn flag_names<T: Debug>(flags: T) -> Vec<String> {
+    format!("{:?}", flags)
+        .split(" | ")
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn barrier_label(barrier: &BarrierDump) -> String {
+    let stages = |stages: &[String]| {
+        if stages.is_empty() {
+            "TOP_OF_PIPE".to_string()
+        } else {
+            stages.join("|")
+        }
+    };
+
+    let mut label = format!(
+        "{} -> {}",
+        stages(&barrier.src_stages),
+        stages(&barrier.dst_stages)
+    );
+    if let (Some(old_layout), Some(new_layout)) = (&barrier.old_layout, &barrier.new_layout) {
+        if old_layout != new_layout {
+            label += &format!("\\n{} -> {}", old_layout, new_layout);
+        }
+    }
+    label
+}
+
+/// Escapes `text` for a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::super::graph::{ImageDesc, LoadOp, OutputTarget, RenderGraph, Resource};
+    use super::*;
+
+    fn output() -> OutputTarget {
+        OutputTarget {
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            format: vk::Format::B8G8R8A8_UNORM,
+            extent: vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            initial_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            final_access: vk::AccessFlags::empty(),
+        }
+    }
+
+    /// A chain of passes whose first and third image share memory, plus a
+    /// pass that is culled.
+    fn dump() -> RenderGraphDump {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let clear = LoadOp::ClearColor([0.0; 4]);
+
+        let mut graph = RenderGraph::new();
+        let depth = graph.create_image("depth", ImageDesc::new(format, 64, 64));
+        let blur = graph.create_image("blur \"h\"\\v", ImageDesc::new(format, 64, 64));
+        let bloom = graph.create_image("bloom", ImageDesc::new(format, 64, 64));
+        let scratch = graph.create_image("scratch", ImageDesc::new(format, 64, 64));
+        let output_image = graph.import_swapchain();
+        graph
+            .add_pass("shadows")
+            .color_attachment(depth, clear)
+            .execute(|_| {});
+        graph
+            .add_pass("blur")
+            .sampled_image(depth, fragment)
+            .color_attachment(blur, clear)
+            .execute(|_| {});
+        graph
+            .add_pass("bloom")
+            .sampled_image(blur, fragment)
+            .color_attachment(bloom, clear)
+            .execute(|_| {});
+        graph
+            .add_pass("debug")
+            .color_attachment(scratch, clear)
+            .execute(|_| {});
+        graph
+            .add_pass("composite")
+            .sampled_image(bloom, fragment)
+            .color_attachment(output_image, clear)
+            .execute(|_| {});
+
+        let output = output();
+        let aliases = [(Resource::Image(0), Resource::Image(2))];
+        let mut compiled = graph.schedule(&output).unwrap();
+        graph.synchronize(&mut compiled, &output, &aliases);
+        graph.dump(&compiled, &output, &aliases)
+    }
+
+    #[test]
+    fn writes_json() {
+        let json: serde_json::Value = serde_json::from_str(&dump().to_json()).unwrap();
+
+        let passes = json["passes"].as_array().unwrap();
+        assert_eq!(passes.len(), 5);
+        assert_eq!(passes[0]["name"], "shadows");
+        assert_eq!(passes[3]["position"], serde_json::Value::Null);
+        assert_eq!(passes[3]["barriers"], serde_json::json!([]));
+        assert_eq!(passes[4]["position"], 3);
+
+        let access = &passes[1]["accesses"][0];
+        assert_eq!(access["resource"], 0);
+        assert_eq!(access["reads"], true);
+        assert_eq!(access["writes"], false);
+        assert_eq!(access["stages"], serde_json::json!(["FRAGMENT_SHADER"]));
+        assert_eq!(access["layout"], "SHADER_READ_ONLY_OPTIMAL");
+
+        let resources = json["resources"].as_array().unwrap();
+        assert_eq!(resources.len(), 5);
+        assert_eq!(resources[0]["kind"], "image");
+        assert_eq!(resources[0]["source"], "transient");
+        assert_eq!(resources[0]["format"], "R8G8B8A8_UNORM");
+        assert_eq!(resources[0]["extent"], serde_json::json!([64, 64]));
+        assert_eq!(resources[0]["lifetime"], serde_json::json!([0, 1]));
+        assert_eq!(resources[2]["aliases"], 0);
+        assert_eq!(resources[3]["lifetime"], serde_json::Value::Null);
+        assert_eq!(resources[4]["source"], "output");
+
+        let final_barriers = json["final_barriers"].as_array().unwrap();
+        assert_eq!(final_barriers.len(), 1);
+        assert_eq!(final_barriers[0]["resource"], 4);
+        assert_eq!(final_barriers[0]["dst_access"], serde_json::json!([]));
+        assert_eq!(final_barriers[0]["new_layout"], "PRESENT_SRC_KHR");
+    }
+
+    #[test]
+    fn writes_dot() {
+        let dot = dump().to_dot();
+        let lines: Vec<&str> = dot.lines().map(str::trim).collect();
+        let expected = [
+            r#"r0 [shape=ellipse, style=solid, label="depth\nR8G8B8A8_UNORM 64x64"];"#,
+            r#"r1 [shape=ellipse, style=solid, label="blur \"h\"\\v\nR8G8B8A8_UNORM 64x64"];"#,
+            r#"r0 -> r2 [style=dotted, arrowhead=none, label="aliases"];"#,
+            r#"r3 [shape=ellipse, style=dashed, label="scratch\nR8G8B8A8_UNORM 64x64"];"#,
+            r#"r4 [shape=ellipse, style=bold, label="swapchain\nB8G8R8A8_UNORM 64x64"];"#,
+            r##"p0 [shape=box, style=solid, label="#0 shadows"];"##,
+            r#"p0 -> r0 [color=red, label="TOP_OF_PIPE -> COLOR_ATTACHMENT_OUTPUT\nUNDEFINED -> COLOR_ATTACHMENT_OPTIMAL"];"#,
+            r#"r0 -> p1 [label="COLOR_ATTACHMENT_OUTPUT -> FRAGMENT_SHADER\nCOLOR_ATTACHMENT_OPTIMAL -> SHADER_READ_ONLY_OPTIMAL"];"#,
+            r#"p3 [shape=box, style=dashed, label="debug\n(culled)"];"#,
+            r#"p3 -> r3 [color=red, label=""];"#,
+            r#"end [shape=doublecircle, label="end"];"#,
+            r#"r4 -> end [style=dashed, label="COLOR_ATTACHMENT_OUTPUT -> BOTTOM_OF_PIPE\nCOLOR_ATTACHMENT_OPTIMAL -> PRESENT_SRC_KHR"];"#,
+        ];
+
+        assert_eq!(lines.first(), Some(&"digraph render_graph {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        for line in expected.iter() {
+            assert!(lines.contains(line), "missing `{}` in\n{}", line, dot);
+        }
+    }
+
+    #[test]
+    fn names_flags() {
+        assert_eq!(
+            flag_names(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+            vec!["SHADER_READ", "SHADER_WRITE"]
+        );
+        assert_eq!(
+            flag_names(vk::PipelineStageFlags::FRAGMENT_SHADER),
+            vec!["FRAGMENT_SHADER"]
+        );
+        assert!(flag_names(vk::AccessFlags::empty()).is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path, sync::Arc};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+use super::allocator::{Allocation, Allocator, MemoryLocation};
+use super::commands::submit_one_time;
+use super::texture;
+
+/// Format of the offscreen color image. RGBA8 keeps the readback layout
+/// identical to what the PNG encoder expects.
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Usage of the offscreen color image. Besides rendering and the readback,
+/// passes may copy into it.
+pub const OFFSCREEN_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw()
+        | vk::ImageUsageFlags::TRANSFER_SRC.as_raw()
+        | vk::ImageUsageFlags::TRANSFER_DST.as_raw(),
+);
+
+/// A color image rendered into instead of a swapchain image when running
+/// without a window.
+pub struct OffscreenTarget {
+    device: Device,
+    allocator: Arc<Allocator>,
+    pub image: vk::Image,
+    /// `None` until the image memory is bound.
+    allocation: Option<Allocation>,
+    pub image_view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+/// Pixels copied back from an `OffscreenTarget`, tightly packed RGBA8 rows.
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Host-visible buffer the offscreen image is copied into.
+struct ReadbackBuffer<'a> {
+    device: &'a Device,
+    allocator: &'a Allocator,
+    buffer: vk::Buffer,
+    allocation: Option<Allocation>,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &Device,
+        allocator: &Arc<Allocator>,
+        extent: vk::Extent2D,
+    ) -> Result<Self, VreError> {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(OFFSCREEN_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(OFFSCREEN_USAGE)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe {
+            device
+                .create_image(&image_create_info, None)
+                .map_err(VreError::device("Could not create offscreen image."))?
+        };
+
+        // Handles are filled in one by one so `Drop` releases whatever was
+        // created if a later step fails.
+        let mut target = Self {
+            device: device.clone(),
+            allocator: allocator.clone(),
+            image,
+            allocation: None,
+            image_view: vk::ImageView::null(),
+            format: OFFSCREEN_FORMAT,
+            extent,
+        };
+
+        target.allocation = Some(allocator.allocate_image(
+            image,
+            vk::ImageTiling::OPTIMAL,
+            MemoryLocation::GpuOnly,
+        )?);
+
+        target.image_view = texture::create_image_view(
+            device,
+            image,
+            vk::ImageViewType::TYPE_2D,
+            OFFSCREEN_FORMAT,
+            color_subresource_range(),
+        )?;
+
+        Ok(target)
+    }
+
+    /// Copies the target back into host memory. Expects the image to be in
+    /// `TRANSFER_SRC_OPTIMAL` and blocks until the copy has finished.
+    pub fn read_back(
+        &self,
+        device: &Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) -> Result<FrameCapture, VreError> {
+        let size = (self.extent.width * self.extent.height * 4) as vk::DeviceSize;
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        let mut readback = ReadbackBuffer {
+            device,
+            allocator: &self.allocator,
+            buffer: unsafe {
+                device
+                    .create_buffer(&buffer_create_info, None)
+                    .map_err(VreError::device("Could not create readback buffer."))?
+            },
+            allocation: None,
+        };
+        let allocation = readback.allocation.insert(
+            self.allocator
+                .allocate_buffer(readback.buffer, MemoryLocation::GpuToCpu)?,
+        );
+        let data = allocation
+            .mapped_ptr()
+            .expect("Readback memory is host-visible.");
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .build();
+
+        submit_one_time(device, command_pool, queue, |command_buffer| unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback.buffer,
+                &[region],
+            );
+        })?;
+
+        // The memory is coherent, so the copy is visible once the queue is
+        // idle.
+        let pixels = unsafe { std::slice::from_raw_parts(data.as_ptr(), size as usize).to_vec() };
+
+        Ok(FrameCapture {
+            width: self.extent.width,
+            height: self.extent.height,
+            pixels,
+        })
+    }
+}
+
+impl Drop for OffscreenTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.image_view, None);
+            self.device.destroy_image(self.image, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
+
+impl Drop for ReadbackBuffer<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
+
+impl FrameCapture {
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+
+        Ok(())
+    }
+}
+
+fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
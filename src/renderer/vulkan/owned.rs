@@ -0,0 +1,121 @@
+use std::{ops::Deref, sync::Arc};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Device, Entry, Instance,
+};
+
+use crate::renderer::VreError;
+
+use super::debug::DebugState;
+
+/// An `Instance` that is destroyed when dropped. Keeps the loader and the
+/// state its debug callback reports to alive until then. Shared with the
+/// device, so it outlives everything created from either.
+pub struct OwnedInstance {
+    instance: Instance,
+    _debug_state: Arc<DebugState>,
+    _entry: Entry,
+}
+
+impl OwnedInstance {
+    pub fn new(entry: &Entry, instance: Instance, debug_state: &Arc<DebugState>) -> Self {
+        OwnedInstance {
+            instance,
+            _debug_state: Arc::clone(debug_state),
+            _entry: entry.clone(),
+        }
+    }
+}
+
+impl Deref for OwnedInstance {
+    type Target = Instance;
+
+    fn deref(&self) -> &Instance {
+        &self.instance
+    }
+}
+
+impl Drop for OwnedInstance {
+    fn drop(&mut self) {
+        unsafe {
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// A logical `Device` that waits for all queued work to finish and is then
+/// destroyed when dropped. Shared by the backend, the allocator and every
+/// resource handed out to users, so it is only destroyed once the last of
+/// them is dropped, whichever order they are dropped in.
+pub struct OwnedDevice {
+    device: Device,
+    _instance: Arc<OwnedInstance>,
+}
+
+impl OwnedDevice {
+    pub fn new(device: Device, instance: &Arc<OwnedInstance>) -> Self {
+        OwnedDevice {
+            device,
+            _instance: Arc::clone(instance),
+        }
+    }
+}
+
+impl Deref for OwnedDevice {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        &self.device
+    }
+}
+
+impl Drop for OwnedDevice {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(err) = self.device.device_wait_idle() {
+                log::error!("Failed to wait for device idle during teardown: {}", err);
+            }
+
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+/// A command pool that is destroyed, freeing all of its command buffers,
+/// when dropped.
+pub struct OwnedCommandPool {
+    device: Device,
+    command_pool: vk::CommandPool,
+}
+
+impl OwnedCommandPool {
+    pub fn new(device: &Device, create_info: &vk::CommandPoolCreateInfo) -> Result<Self, VreError> {
+        let command_pool = unsafe {
+            device
+                .create_command_pool(create_info, None)
+                .map_err(VreError::device("Could not create command pool."))?
+        };
+
+        Ok(OwnedCommandPool {
+            device: device.clone(),
+            command_pool,
+        })
+    }
+}
+
+impl Deref for OwnedCommandPool {
+    type Target = vk::CommandPool;
+
+    fn deref(&self) -> &vk::CommandPool {
+        &self.command_pool
+    }
+}
+
+impl Drop for OwnedCommandPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::compiler::{self, ShaderStage};
+use crate::renderer::VreError;
+
+use super::descriptor::DescriptorLayoutCache;
+use super::owned::OwnedDevice;
+use super::render_pass::{RenderPassCache, RenderPassKey};
+use super::shader::{PipelineLayout, ShaderModule};
+
+/// How often shader files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Identifies a pipeline owned by the `Renderer`. Stays valid when the
+/// pipeline is rebuilt after its shaders changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(usize);
+
+/// Shader files, fixed-function state and attachment formats of a graphics
+/// pipeline. Viewport and scissor are dynamic.
+///
+/// By default the pipeline draws into a single attachment in the format of
+/// the renderer's output image, the swapchain image when windowed, and is
+/// rebuilt if that format changes.
+#[derive(Clone, Debug)]
+pub struct GraphicsPipelineDesc {
+    pub(crate) vertex_shader: PathBuf,
+    pub(crate) fragment_shader: PathBuf,
+    pub(crate) topology: vk::PrimitiveTopology,
+    pub(crate) cull_mode: vk::CullModeFlags,
+    pub(crate) front_face: vk::FrontFace,
+    pub(crate) alpha_blending: bool,
+    /// Formats of the color attachments, the output format if empty.
+    pub(crate) color_formats: Vec<vk::Format>,
+    pub(crate) depth_format: Option<vk::Format>,
+}
+
+impl GraphicsPipelineDesc {
+    /// A pipeline drawing triangle lists without culling or blending. The
+    /// shaders are loaded like `Renderer::load_shader_module` does.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(vertex_shader: P, fragment_shader: Q) -> Self {
+        Self {
+            vertex_shader: vertex_shader.as_ref().to_path_buf(),
+            fragment_shader: fragment_shader.as_ref().to_path_buf(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            alpha_blending: false,
+            color_formats: Vec::new(),
+            depth_format: None,
+        }
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    /// Blends with `src * src_alpha + dst * (1 - src_alpha)`.
+    pub fn alpha_blending(mut self, alpha_blending: bool) -> Self {
+        self.alpha_blending = alpha_blending;
+        self
+    }
+
+    /// Draws into color attachments of these formats, in the order a
+    /// render graph pass declares them, instead of the output image.
+    pub fn color_formats(mut self, color_formats: &[vk::Format]) -> Self {
+        self.color_formats = color_formats.to_vec();
+        self
+    }
+
+    /// Tests against and writes a depth attachment of `format`, keeping
+    /// fragments with less depth.
+    pub fn depth_test(mut self, format: vk::Format) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    /// Whether the pipeline draws into the output image and must be rebuilt
+    /// when its format changes.
+    fn uses_output_format(&self) -> bool {
+        self.color_formats.is_empty()
+    }
+}
+
+/// Shader file of a compute pipeline.
+#[derive(Clone, Debug)]
+pub struct ComputePipelineDesc {
+    pub(crate) shader: PathBuf,
+}
+
+impl ComputePipelineDesc {
+    pub fn new<P: AsRef<Path>>(shader: P) -> Self {
+        Self {
+            shader: shader.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PipelineDesc {
+    Graphics(GraphicsPipelineDesc),
+    Compute(ComputePipelineDesc),
+}
+
+impl PipelineDesc {
+    fn shaders(&self) -> Vec<(&Path, ShaderStage)> {
+        match self {
+            PipelineDesc::Graphics(desc) => vec![
+                (desc.vertex_shader.as_path(), ShaderStage::Vertex),
+                (desc.fragment_shader.as_path(), ShaderStage::Fragment),
+            ],
+            PipelineDesc::Compute(desc) => vec![(desc.shader.as_path(), ShaderStage::Compute)],
+        }
+    }
+}
+
+/// Device objects pipelines are built with.
+pub struct PipelineContext<'a> {
+    pub device: &'a Arc<OwnedDevice>,
+    pub layout_cache: &'a DescriptorLayoutCache,
+    pub cache: vk::PipelineCache,
+    pub render_passes: &'a RenderPassCache,
+    /// Format of the image frames are rendered into.
+    pub output_format: vk::Format,
+}
+
+/// A built pipeline and its layout, destroyed together.
+struct Pipeline {
+    device: Device,
+    pipeline: vk::Pipeline,
+    layout: PipelineLayout,
+}
+
+impl Pipeline {
+    fn build(context: &PipelineContext, desc: &PipelineDesc) -> Result<Self, VreError> {
+        let modules = desc
+            .shaders()
+            .into_iter()
+            .map(|(path, stage)| {
+                let spirv =
+                    compiler::load_shader_file(path, stage).map_err(VreError::ShaderCompile)?;
+                ShaderModule::new(context.device, &spirv)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let module_refs: Vec<&ShaderModule> = modules.iter().collect();
+        let layout = PipelineLayout::new(context.device, context.layout_cache, &module_refs)?;
+
+        let entry_points = modules
+            .iter()
+            .map(|module| {
+                CString::new(module.entry_point())
+                    .map_err(|_| VreError::Shader("entry point name contains nul".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let stages: Vec<vk::PipelineShaderStageCreateInfo> = modules
+            .iter()
+            .zip(entry_points.iter())
+            .map(|(module, entry_point)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(module.stage())
+                    .module(module.handle())
+                    .name(entry_point)
+                    .build()
+            })
+            .collect();
+
+        let pipeline = match desc {
+            PipelineDesc::Graphics(desc) => build_graphics(context, desc, &stages, &layout)?,
+            PipelineDesc::Compute(_) => {
+                let create_info = vk::ComputePipelineCreateInfo::builder()
+                    .stage(stages[0])
+                    .layout(layout.handle())
+                    .build();
+
+                unsafe {
+                    context
+                        .device
+                        .create_compute_pipelines(context.cache, &[create_info], None)
+                        .map_err(|(_, result)| {
+                            VreError::device("Could not create compute pipeline.")(result)
+                        })?[0]
+                }
+            }
+        };
+
+        Ok(Self {
+            device: Device::clone(context.device),
+            pipeline,
+            layout,
+        })
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+fn build_graphics(
+    context: &PipelineContext,
+    desc: &GraphicsPipelineDesc,
+    stages: &[vk::PipelineShaderStageCreateInfo],
+    layout: &PipelineLayout,
+) -> Result<vk::Pipeline, VreError> {
+    let vertex_bindings: Vec<vk::VertexInputBindingDescription> =
+        layout.vertex_binding().into_iter().collect();
+    let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&vertex_bindings)
+        .vertex_attribute_descriptions(layout.vertex_attributes())
+        .build();
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(desc.topology)
+        .build();
+    let viewport = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1)
+        .build();
+    let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(desc.cull_mode)
+        .front_face(desc.front_face)
+        .line_width(1.0)
+        .build();
+    let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+        .build();
+    let color_formats = if desc.uses_output_format() {
+        vec![context.output_format]
+    } else {
+        desc.color_formats.clone()
+    };
+    let render_pass = context.render_passes.get(&RenderPassKey::compatible(
+        &color_formats,
+        desc.depth_format,
+    ))?;
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .blend_enable(desc.alpha_blending)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .build();
+    let color_blend_attachments = vec![color_blend_attachment; color_formats.len()];
+    let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .build();
+    let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachments)
+        .build();
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(&dynamic_states)
+        .build();
+
+    let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport)
+        .rasterization_state(&rasterization)
+        .multisample_state(&multisample)
+        .color_blend_state(&color_blend)
+        .dynamic_state(&dynamic_state)
+        .layout(layout.handle())
+        .render_pass(render_pass)
+        .subpass(0);
+    if desc.depth_format.is_some() {
+        create_info = create_info.depth_stencil_state(&depth_stencil);
+    }
+    let create_info = create_info.build();
+
+    unsafe {
+        context
+            .device
+            .create_graphics_pipelines(context.cache, &[create_info], None)
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, result)| VreError::device("Could not create graphics pipeline.")(result))
+    }
+}
+
+struct Entry {
+    desc: PipelineDesc,
+    pipeline: Pipeline,
+    /// Why the last rebuild failed, cleared by the next successful one.
+    error: Option<VreError>,
+}
+
+/// Owns every pipeline created through the renderer and rebuilds the ones
+/// whose shader files changed on disk.
+pub struct PipelineManager {
+    entries: Vec<Entry>,
+    /// Modification time of every shader file in use when it was last
+    /// compiled, `None` if it could not be read.
+    shader_times: HashMap<PathBuf, Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl PipelineManager {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            shader_times: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn create(
+        &mut self,
+        context: &PipelineContext,
+        desc: PipelineDesc,
+    ) -> Result<PipelineId, VreError> {
+        let pipeline = Pipeline::build(context, &desc)?;
+
+        for (path, _) in desc.shaders() {
+            self.shader_times
+                .entry(path.to_path_buf())
+                .or_insert_with(|| modified_time(path));
+        }
+        self.entries.push(Entry {
+            desc,
+            pipeline,
+            error: None,
+        });
+
+        Ok(PipelineId(self.entries.len() - 1))
+    }
+
+    pub fn pipeline(&self, id: PipelineId) -> vk::Pipeline {
+        self.entries[id.0].pipeline.pipeline
+    }
+
+    pub fn layout(&self, id: PipelineId) -> &PipelineLayout {
+        &self.entries[id.0].pipeline.layout
+    }
+
+    pub fn error(&self, id: PipelineId) -> Option<&VreError> {
+        self.entries[id.0].error.as_ref()
+    }
+
+    /// Shader files modified since they were last compiled. Checks at most
+    /// once per `POLL_INTERVAL`.
+    pub fn changed_shaders(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = Vec::new();
+        for (path, time) in self.shader_times.iter_mut() {
+            let modified = modified_time(path);
+            if modified != *time {
+                *time = modified;
+                changed.push(path.clone());
+            }
+        }
+
+        changed
+    }
+
+    /// Rebuilds every pipeline using one of the `changed` shader files.
+    /// Pipelines that fail to build keep their previous version and record
+    /// the error. The new pipelines are built while earlier frames still
+    /// run; `wait_idle` is called before the old ones are destroyed.
+    pub fn reload<F>(
+        &mut self,
+        context: &PipelineContext,
+        changed: &[PathBuf],
+        wait_idle: F,
+    ) -> Result<(), VreError>
+    where
+        F: FnOnce() -> Result<(), VreError>,
+    {
+        let mut rebuilt = Vec::new();
+
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            let uses_changed = entry
+                .desc
+                .shaders()
+                .iter()
+                .any(|(path, _)| changed.iter().any(|changed| changed == path));
+            if !uses_changed {
+                continue;
+            }
+
+            match Pipeline::build(context, &entry.desc) {
+                Ok(pipeline) => rebuilt.push((index, pipeline)),
+                Err(err) => {
+                    log::error!(
+                        "Could not rebuild pipeline {}, keeping the previous one: {}",
+                        index,
+                        err
+                    );
+                    entry.error = Some(err);
+                }
+            }
+        }
+
+        if rebuilt.is_empty() {
+            return Ok(());
+        }
+
+        wait_idle()?;
+        log::info!("Reloaded {} pipeline(s).", rebuilt.len());
+        for (index, pipeline) in rebuilt {
+            let entry = &mut self.entries[index];
+            entry.pipeline = pipeline;
+            entry.error = None;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the graphics pipelines drawing into the output image after
+    /// its format changed. None of them may still be in use by the GPU.
+    pub fn rebuild_output_pipelines(&mut self, context: &PipelineContext) -> Result<(), VreError> {
+        for entry in self.entries.iter_mut() {
+            if let PipelineDesc::Graphics(desc) = &entry.desc {
+                if desc.uses_output_format() {
+                    entry.pipeline = Pipeline::build(context, &entry.desc)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,355 @@
+use std::{
+    convert::TryInto,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Device, Instance,
+};
+
+use crate::renderer::VreError;
+
+/// Identifies vre pipeline cache files.
+const MAGIC: &[u8; 4] = b"VRPC";
+
+/// Bumped whenever the file layout changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Magic, format version, vendor ID, device ID, driver version, pipeline
+/// cache UUID, data length and checksum.
+const HEADER_SIZE: usize = 4 + 4 * 4 + vk::UUID_SIZE + 8 + 8;
+
+/// Distinguishes the temporary files of saves within this process.
+static SAVE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The device a cache file was written for. The driver data is discarded
+/// unless all of it matches the current device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CacheKey {
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl CacheKey {
+    fn new(properties: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+}
+
+/// A `VkPipelineCache` that is loaded from a file when created and written
+/// back when dropped. Without a path it only lives in memory. Must be
+/// dropped before the device.
+pub struct PipelineCache {
+    device: Device,
+    cache: vk::PipelineCache,
+    path: Option<PathBuf>,
+    key: CacheKey,
+}
+
+impl PipelineCache {
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        path: Option<&Path>,
+    ) -> Result<Self, VreError> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let key = CacheKey::new(&properties);
+
+        let initial_data = match path {
+            Some(path) => load(path, &key),
+            None => Vec::new(),
+        };
+
+        let cache = match create_cache(device, &initial_data) {
+            Ok(cache) => cache,
+            // Drivers may still reject data that passed our checks.
+            Err(err) if !initial_data.is_empty() => {
+                log::warn!(
+                    "Driver rejected the pipeline cache ({}), starting empty.",
+                    err
+                );
+                create_cache(device, &[])?
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            device: device.clone(),
+            cache,
+            path: path.map(Path::to_path_buf),
+            key,
+        })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    /// Writes the cache to its file, replacing the previous one atomically
+    /// so an interrupted save never leaves a truncated file behind.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let data = unsafe {
+            self.device
+                .get_pipeline_cache_data(self.cache)
+                .map_err(io::Error::other)?
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = temp_path(path);
+        fs::write(&temp_path, serialize(&self.key, &data))?;
+        if let Err(err) = fs::rename(&temp_path, path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+
+        log::info!(
+            "Saved {} bytes of pipeline cache to {}.",
+            data.len(),
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            log::warn!("Failed to save pipeline cache: {}", err);
+        }
+
+        unsafe {
+            self.device.destroy_pipeline_cache(self.cache, None);
+        }
+    }
+}
+
+fn create_cache(device: &Device, initial_data: &[u8]) -> Result<vk::PipelineCache, VreError> {
+    let create_info = vk::PipelineCacheCreateInfo::builder()
+        .initial_data(initial_data)
+        .build();
+
+    unsafe {
+        device
+            .create_pipeline_cache(&create_info, None)
+            .map_err(VreError::device("Could not create pipeline cache."))
+    }
+}
+
+/// Reads the driver data from the cache file at `path`. Returns nothing if
+/// the file is missing, written for another device or driver, or corrupt.
+fn load(path: &Path, key: &CacheKey) -> Vec<u8> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => {
+            log::warn!("Could not read pipeline cache {}: {}", path.display(), err);
+            return Vec::new();
+        }
+    };
+
+    match parse(&contents, key) {
+        Ok(data) => {
+            log::info!(
+                "Loaded {} bytes of pipeline cache from {}.",
+                data.len(),
+                path.display()
+            );
+            data.to_vec()
+        }
+        Err(reason) => {
+            log::info!("Discarding pipeline cache {}: {}.", path.display(), reason);
+            Vec::new()
+        }
+    }
+}
+
+/// The driver data prefixed with the header `parse` checks.
+fn serialize(key: &CacheKey, data: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(HEADER_SIZE + data.len());
+    contents.extend_from_slice(MAGIC);
+    contents.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    contents.extend_from_slice(&key.vendor_id.to_le_bytes());
+    contents.extend_from_slice(&key.device_id.to_le_bytes());
+    contents.extend_from_slice(&key.driver_version.to_le_bytes());
+    contents.extend_from_slice(&key.pipeline_cache_uuid);
+    contents.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    contents.extend_from_slice(&checksum(data).to_le_bytes());
+    contents.extend_from_slice(data);
+    contents
+}
+
+/// A file next to `path` to write to before renaming, unique to this
+/// process and save so concurrent saves of the same cache don't clobber
+/// each other's partial files.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        SAVE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(file_name)
+}
+
+/// Validates the header and returns the driver data following it.
+fn parse<'a>(contents: &'a [u8], key: &CacheKey) -> Result<&'a [u8], &'static str> {
+    if contents.len() < HEADER_SIZE {
+        return Err("file is truncated");
+    }
+
+    let (header, data) = contents.split_at(HEADER_SIZE);
+    let u32_at = |offset: usize| u32::from_le_bytes(field(header, offset));
+    let u64_at = |offset: usize| u64::from_le_bytes(field(header, offset));
+    let uuid_offset = 20;
+    let length_offset = uuid_offset + vk::UUID_SIZE;
+
+    if &header[..4] != MAGIC {
+        return Err("not a pipeline cache file");
+    }
+    if u32_at(4) != FORMAT_VERSION {
+        return Err("unsupported format version");
+    }
+
+    let file_key = CacheKey {
+        vendor_id: u32_at(8),
+        device_id: u32_at(12),
+        driver_version: u32_at(16),
+        pipeline_cache_uuid: field(header, uuid_offset),
+    };
+    if file_key != *key {
+        return Err("written for a different device or driver");
+    }
+
+    if u64_at(length_offset) != data.len() as u64 || u64_at(length_offset + 8) != checksum(data) {
+        return Err("data is corrupt");
+    }
+
+    Ok(data)
+}
+
+/// The `N` bytes of `header` starting at `offset`.
+fn field<const N: usize>(header: &[u8], offset: usize) -> [u8; N] {
+    header[offset..offset + N].try_into().unwrap()
+}
+
+/// 64-bit FNV-1a hash of `data`.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: CacheKey = CacheKey {
+        vendor_id: 0x10de,
+        device_id: 0x2484,
+        driver_version: 7,
+        pipeline_cache_uuid: [3; vk::UUID_SIZE],
+    };
+
+    #[test]
+    fn accepts_matching_headers() {
+        let data = b"driver data";
+        let contents = serialize(&KEY, data);
+
+        assert_eq!(contents.len(), HEADER_SIZE + data.len());
+        assert_eq!(parse(&contents, &KEY), Ok(&data[..]));
+        assert_eq!(parse(&serialize(&KEY, &[]), &KEY), Ok(&[][..]));
+    }
+
+    #[test]
+    fn rejects_mismatched_headers() {
+        let contents = serialize(&KEY, b"driver data");
+        let other_keys = [
+            CacheKey {
+                vendor_id: 0x1002,
+                ..KEY
+            },
+            CacheKey {
+                device_id: 0x2485,
+                ..KEY
+            },
+            CacheKey {
+                driver_version: 8,
+                ..KEY
+            },
+            CacheKey {
+                pipeline_cache_uuid: [4; vk::UUID_SIZE],
+                ..KEY
+            },
+        ];
+        for key in other_keys.iter() {
+            assert_eq!(
+                parse(&contents, key),
+                Err("written for a different device or driver")
+            );
+        }
+
+        let mut wrong_magic = contents.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(parse(&wrong_magic, &KEY), Err("not a pipeline cache file"));
+
+        let mut wrong_version = contents.clone();
+        wrong_version[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            parse(&wrong_version, &KEY),
+            Err("unsupported format version")
+        );
+
+        let mut corrupt = contents;
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(parse(&corrupt, &KEY), Err("data is corrupt"));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let contents = serialize(&KEY, b"driver data");
+
+        assert_eq!(
+            parse(&contents[..HEADER_SIZE - 1], &KEY),
+            Err("file is truncated")
+        );
+        assert_eq!(parse(&[], &KEY), Err("file is truncated"));
+        assert_eq!(
+            parse(&contents[..contents.len() - 1], &KEY),
+            Err("data is corrupt")
+        );
+    }
+
+    #[test]
+    fn uses_unique_temp_paths() {
+        let path = Path::new("cache/pipelines.bin");
+        let first = temp_path(path);
+        let second = temp_path(path);
+
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), path.parent());
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(&format!("pipelines.bin.{}.", process::id())));
+        assert!(name.ends_with(".tmp"));
+    }
+}
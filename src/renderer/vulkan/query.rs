@@ -0,0 +1,403 @@
+use std::{cell::RefCell, collections::HashMap, mem};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+/// Queries of each type one frame can record; further queries are dropped.
+const MAX_QUERIES: u32 = 256;
+/// Counters collected by pipeline statistics queries. Results are written
+/// in the order of the bits.
+const STATISTICS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+);
+
+/// Which query types the device supports, from the features enabled on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuerySupport {
+    /// `pipelineStatisticsQuery`. Without it pipeline statistics queries
+    /// are ignored.
+    pub pipeline_statistics: bool,
+    /// `occlusionQueryPrecise`. Without it occlusion queries only tell
+    /// whether any sample passed: the count is zero or some non-zero value.
+    pub precise_occlusion: bool,
+}
+
+impl QuerySupport {
+    pub(crate) fn new(features: &vk::PhysicalDeviceFeatures) -> Self {
+        let support = Self {
+            pipeline_statistics: features.pipeline_statistics_query == vk::TRUE,
+            precise_occlusion: features.occlusion_query_precise == vk::TRUE,
+        };
+
+        if !support.pipeline_statistics {
+            log::info!("Pipeline statistics queries are not supported by the device.");
+        }
+
+        support
+    }
+}
+
+/// Pipeline statistics counted between `PassContext::begin_pipeline_statistics`
+/// and `end_pipeline_statistics`, summed over every query with the same name
+/// in a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub vertex_invocations: u64,
+    /// Primitives that reached the clipping stage.
+    pub clipping_invocations: u64,
+    /// Primitives that came out of clipping.
+    pub clipping_primitives: u64,
+    pub fragment_invocations: u64,
+    pub compute_invocations: u64,
+}
+
+impl PipelineStatistics {
+    fn from_counters(counters: [u64; 5]) -> Self {
+        Self {
+            vertex_invocations: counters[0],
+            clipping_invocations: counters[1],
+            clipping_primitives: counters[2],
+            fragment_invocations: counters[3],
+            compute_invocations: counters[4],
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.vertex_invocations += other.vertex_invocations;
+        self.clipping_invocations += other.clipping_invocations;
+        self.clipping_primitives += other.clipping_primitives;
+        self.fragment_invocations += other.fragment_invocations;
+        self.compute_invocations += other.compute_invocations;
+    }
+}
+
+/// The result of a query and the frame it was recorded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryResult<T> {
+    pub value: T,
+    /// Number of the frame, counting from the first frame rendered.
+    pub frame: u64,
+}
+
+/// The latest result of every pipeline statistics and occlusion query.
+#[derive(Default)]
+pub(crate) struct QueryResults {
+    statistics: HashMap<String, QueryResult<PipelineStatistics>>,
+    occlusion: HashMap<u64, QueryResult<u64>>,
+}
+
+impl QueryResults {
+    /// Replaces the results of the queries `frame` recorded.
+    pub fn update(&mut self, frame: FrameQueryResults) {
+        for (name, value) in frame.statistics {
+            self.statistics.insert(
+                name,
+                QueryResult {
+                    value,
+                    frame: frame.frame,
+                },
+            );
+        }
+        for (key, value) in frame.occlusion {
+            self.occlusion.insert(
+                key,
+                QueryResult {
+                    value,
+                    frame: frame.frame,
+                },
+            );
+        }
+    }
+
+    pub fn pipeline_statistics(&self, name: &str) -> Option<QueryResult<PipelineStatistics>> {
+        self.statistics.get(name).copied()
+    }
+
+    pub fn occlusion(&self, key: u64) -> Option<QueryResult<u64>> {
+        self.occlusion.get(&key).copied()
+    }
+}
+
+/// Results read back from one frame.
+pub(crate) struct FrameQueryResults {
+    frame: u64,
+    statistics: HashMap<String, PipelineStatistics>,
+    occlusion: HashMap<u64, u64>,
+}
+
+#[derive(Default)]
+struct QueryState {
+    frame: u64,
+    /// Names of the pipeline statistics queries in pool order.
+    statistics: Vec<String>,
+    /// Keys of the occlusion queries in pool order.
+    occlusion: Vec<u64>,
+    /// Whether a query of each type is active, and whether it was actually
+    /// begun rather than dropped.
+    active_statistics: Option<bool>,
+    active_occlusion: Option<bool>,
+    submitted: bool,
+}
+
+/// Pipeline statistics and occlusion query pools of one frame context,
+/// read back once the frame's fence has signaled like its timestamps.
+pub(crate) struct FrameQueries {
+    device: Device,
+    support: QuerySupport,
+    /// Null when pipeline statistics aren't supported.
+    statistics_pool: vk::QueryPool,
+    occlusion_pool: vk::QueryPool,
+    /// Recorded through shared references from render graph passes.
+    state: RefCell<QueryState>,
+    results: Option<FrameQueryResults>,
+}
+
+impl FrameQueries {
+    pub fn new(device: &Device, support: QuerySupport) -> Result<Self, VreError> {
+        let mut queries = Self {
+            device: device.clone(),
+            support,
+            statistics_pool: vk::QueryPool::null(),
+            occlusion_pool: vk::QueryPool::null(),
+            state: RefCell::new(QueryState::default()),
+            results: None,
+        };
+
+        let occlusion_create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(MAX_QUERIES)
+            .build();
+        queries.occlusion_pool = unsafe {
+            device
+                .create_query_pool(&occlusion_create_info, None)
+                .map_err(VreError::device("Could not create occlusion query pool."))?
+        };
+
+        if support.pipeline_statistics {
+            let statistics_create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .query_count(MAX_QUERIES)
+                .pipeline_statistics(STATISTICS)
+                .build();
+            queries.statistics_pool = unsafe {
+                device
+                    .create_query_pool(&statistics_create_info, None)
+                    .map_err(VreError::device(
+                        "Could not create pipeline statistics query pool.",
+                    ))?
+            };
+        }
+
+        Ok(queries)
+    }
+
+    /// Resets the pools for frame `frame`. Must be recorded outside a
+    /// render pass, before any query.
+    pub fn begin_frame(&self, command_buffer: vk::CommandBuffer, frame: u64) {
+        *self.state.borrow_mut() = QueryState {
+            frame,
+            ..QueryState::default()
+        };
+
+        for &pool in [self.statistics_pool, self.occlusion_pool].iter() {
+            if pool != vk::QueryPool::null() {
+                unsafe {
+                    self.device
+                        .cmd_reset_query_pool(command_buffer, pool, 0, MAX_QUERIES);
+                }
+            }
+        }
+    }
+
+    /// Marks the frame's queries as submitted, to be read back by
+    /// `resolve` once the frame has finished.
+    pub fn end_frame(&self) {
+        self.state.borrow_mut().submitted = true;
+    }
+
+    pub fn begin_statistics(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        let mut state = self.state.borrow_mut();
+        if state.active_statistics.is_some() {
+            log::warn!(
+                "Pipeline statistics query `{}` begun while another one is active.",
+                name
+            );
+            return;
+        }
+
+        let index = state.statistics.len() as u32;
+        let begun = self.statistics_pool != vk::QueryPool::null() && index < MAX_QUERIES;
+        state.active_statistics = Some(begun);
+        if begun {
+            state.statistics.push(name.to_string());
+            unsafe {
+                self.device.cmd_begin_query(
+                    command_buffer,
+                    self.statistics_pool,
+                    index,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+        }
+    }
+
+    pub fn end_statistics(&self, command_buffer: vk::CommandBuffer) {
+        let mut state = self.state.borrow_mut();
+        match state.active_statistics.take() {
+            Some(true) => unsafe {
+                self.device.cmd_end_query(
+                    command_buffer,
+                    self.statistics_pool,
+                    state.statistics.len() as u32 - 1,
+                );
+            },
+            Some(false) => {}
+            None => log::warn!("Pipeline statistics query ended without being begun."),
+        }
+    }
+
+    pub fn begin_occlusion(&self, command_buffer: vk::CommandBuffer, key: u64) {
+        let mut state = self.state.borrow_mut();
+        if state.active_occlusion.is_some() {
+            log::warn!("Occlusion query {} begun while another one is active.", key);
+            return;
+        }
+
+        let index = state.occlusion.len() as u32;
+        let begun = index < MAX_QUERIES;
+        state.active_occlusion = Some(begun);
+        if begun {
+            state.occlusion.push(key);
+            let flags = if self.support.precise_occlusion {
+                vk::QueryControlFlags::PRECISE
+            } else {
+                vk::QueryControlFlags::empty()
+            };
+            unsafe {
+                self.device
+                    .cmd_begin_query(command_buffer, self.occlusion_pool, index, flags);
+            }
+        }
+    }
+
+    pub fn end_occlusion(&self, command_buffer: vk::CommandBuffer) {
+        let mut state = self.state.borrow_mut();
+        match state.active_occlusion.take() {
+            Some(true) => unsafe {
+                self.device.cmd_end_query(
+                    command_buffer,
+                    self.occlusion_pool,
+                    state.occlusion.len() as u32 - 1,
+                );
+            },
+            Some(false) => {}
+            None => log::warn!("Occlusion query ended without being begun."),
+        }
+    }
+
+    /// Ends the queries a pass left active, which have to end in the
+    /// subpass they began in.
+    pub fn end_active(&self, command_buffer: vk::CommandBuffer) {
+        let (statistics, occlusion) = {
+            let state = self.state.borrow();
+            (state.active_statistics, state.active_occlusion)
+        };
+
+        if statistics.is_some() {
+            self.end_statistics(command_buffer);
+        }
+        if occlusion.is_some() {
+            self.end_occlusion(command_buffer);
+        }
+    }
+
+    /// Reads back the queries of the last submission. Must only be called
+    /// once it has finished on the GPU.
+    pub fn resolve(&mut self) -> Result<(), VreError> {
+        let state = mem::take(self.state.get_mut());
+        if !state.submitted || (state.statistics.is_empty() && state.occlusion.is_empty()) {
+            return Ok(());
+        }
+
+        let counters: Vec<[u64; 5]> =
+            match query_results(&self.device, self.statistics_pool, state.statistics.len())? {
+                Some(counters) => counters,
+                None => return Ok(()),
+            };
+        let samples: Vec<u64> =
+            match query_results(&self.device, self.occlusion_pool, state.occlusion.len())? {
+                Some(samples) => samples,
+                None => return Ok(()),
+            };
+
+        let mut statistics: HashMap<String, PipelineStatistics> = HashMap::new();
+        for (name, counters) in state.statistics.into_iter().zip(counters) {
+            statistics
+                .entry(name)
+                .or_default()
+                .add(&PipelineStatistics::from_counters(counters));
+        }
+
+        self.results = Some(FrameQueryResults {
+            frame: state.frame,
+            statistics,
+            occlusion: state.occlusion.into_iter().zip(samples).collect(),
+        });
+
+        Ok(())
+    }
+
+    /// The results `resolve` read back, if they haven't been taken yet.
+    pub fn take_results(&mut self) -> Option<FrameQueryResults> {
+        self.results.take()
+    }
+}
+
+impl Drop for FrameQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.statistics_pool, None);
+            self.device.destroy_query_pool(self.occlusion_pool, None);
+        }
+    }
+}
+
+/// Reads the 64-bit results of the first `count` queries of `pool` without
+/// waiting. Returns `None` if they aren't available, which only happens
+/// when their submission failed.
+fn query_results<T: Copy + Default>(
+    device: &Device,
+    pool: vk::QueryPool,
+    count: usize,
+) -> Result<Option<Vec<T>>, VreError> {
+    if count == 0 {
+        return Ok(Some(Vec::new()));
+    }
+
+    // Called directly since ash's wrapper limits results to one value per
+    // query.
+    let mut results = vec![T::default(); count];
+    let result = unsafe {
+        device.fp_v1_0().get_query_pool_results(
+            device.handle(),
+            pool,
+            0,
+            count as u32,
+            mem::size_of_val(results.as_slice()),
+            results.as_mut_ptr() as *mut _,
+            mem::size_of::<T>() as vk::DeviceSize,
+            vk::QueryResultFlags::TYPE_64,
+        )
+    };
+
+    match result {
+        vk::Result::SUCCESS => Ok(Some(results)),
+        vk::Result::NOT_READY => Ok(None),
+        err => Err(VreError::device("Failed to read query results.")(err)),
+    }
+}
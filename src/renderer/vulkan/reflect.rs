@@ -0,0 +1,770 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::descriptor::DescriptorBinding;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// A descriptor binding used by a shader, in descriptor set `set`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: DescriptorBinding,
+}
+
+/// A vertex shader input at `location`, in the format it is read as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+    /// Size of one element of `format` in bytes.
+    pub size: u32,
+}
+
+/// Interface of the first entry point of a SPIR-V module.
+#[derive(Clone, Debug)]
+pub struct Reflection {
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+    /// Every resource variable in the module, sorted by set and binding.
+    pub bindings: Vec<ReflectedBinding>,
+    /// Bytes of push constants used, or zero.
+    pub push_constant_size: u32,
+    /// Only filled for vertex shaders, sorted by location.
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScalarKind {
+    Bool,
+    Sint,
+    Uint,
+    Float,
+}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Scalar { kind: ScalarKind, width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, columns: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    array_stride: Option<u32>,
+    built_in: bool,
+    buffer_block: bool,
+}
+
+struct Variable {
+    id: u32,
+    pointer_type: u32,
+    storage_class: u32,
+}
+
+struct EntryPoint {
+    execution_model: u32,
+    name: String,
+    interface: Vec<u32>,
+}
+
+/// Types, decorations and variables of a module, indexed by result id.
+#[derive(Default)]
+struct Module {
+    entry_point: Option<EntryPoint>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    member_matrix_strides: HashMap<(u32, u32), u32>,
+    variables: Vec<Variable>,
+}
+
+/// Reflects the interface of the first entry point in `words`. Resources
+/// are collected module-wide, which matches the entry point for modules
+/// with a single one.
+pub fn reflect(words: &[u32]) -> Result<Reflection, String> {
+    let module = Module::parse(words)?;
+    let entry_point = module
+        .entry_point
+        .as_ref()
+        .ok_or_else(|| "module has no entry point".to_string())?;
+    let stage = stage_flags(entry_point.execution_model)?;
+
+    let mut bindings = Vec::new();
+    let mut push_constant_size = 0;
+    for variable in module.variables.iter() {
+        let pointee = match module.types.get(&variable.pointer_type) {
+            Some(Type::Pointer { pointee }) => *pointee,
+            _ => return Err(format!("variable %{} is not a pointer", variable.id)),
+        };
+
+        match variable.storage_class {
+            STORAGE_CLASS_UNIFORM_CONSTANT
+            | STORAGE_CLASS_UNIFORM
+            | STORAGE_CLASS_STORAGE_BUFFER => {
+                bindings.push(module.binding(variable, pointee, stage)?);
+            }
+            STORAGE_CLASS_PUSH_CONSTANT => {
+                push_constant_size = push_constant_size.max(module.size_of(pointee, None)?);
+            }
+            _ => {}
+        }
+    }
+    bindings.sort_by_key(|reflected| (reflected.set, reflected.binding.binding));
+
+    let vertex_inputs = if stage == vk::ShaderStageFlags::VERTEX {
+        module.vertex_inputs(&entry_point.interface)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Reflection {
+        stage,
+        entry_point: entry_point.name.clone(),
+        bindings,
+        push_constant_size,
+        vertex_inputs,
+    })
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Result<Self, String> {
+        if words.len() < HEADER_WORDS || words[0] != MAGIC {
+            return Err("not a SPIR-V module".to_string());
+        }
+
+        let mut module = Module::default();
+        let mut rest = &words[HEADER_WORDS..];
+        while !rest.is_empty() {
+            let word_count = (rest[0] >> 16) as usize;
+            let opcode = rest[0] & 0xffff;
+            if word_count == 0 || word_count > rest.len() {
+                return Err("truncated instruction".to_string());
+            }
+
+            module.parse_instruction(opcode, &rest[1..word_count])?;
+            rest = &rest[word_count..];
+        }
+
+        Ok(module)
+    }
+
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) -> Result<(), String> {
+        let operand = |index: usize| {
+            operands
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("instruction {} is missing operands", opcode))
+        };
+
+        match opcode {
+            OP_ENTRY_POINT if self.entry_point.is_none() => {
+                let (name, name_words) = parse_string(&operands[2.min(operands.len())..]);
+                self.entry_point = Some(EntryPoint {
+                    execution_model: operand(0)?,
+                    name,
+                    interface: operands[(2 + name_words).min(operands.len())..].to_vec(),
+                });
+            }
+            OP_TYPE_BOOL => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Scalar {
+                        kind: ScalarKind::Bool,
+                        width: 32,
+                    },
+                );
+            }
+            OP_TYPE_INT => {
+                let kind = if operand(2)? == 0 {
+                    ScalarKind::Uint
+                } else {
+                    ScalarKind::Sint
+                };
+                self.add_type(
+                    operand(0)?,
+                    Type::Scalar {
+                        kind,
+                        width: operand(1)?,
+                    },
+                );
+            }
+            OP_TYPE_FLOAT => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Scalar {
+                        kind: ScalarKind::Float,
+                        width: operand(1)?,
+                    },
+                );
+            }
+            OP_TYPE_VECTOR => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Vector {
+                        component: operand(1)?,
+                        count: operand(2)?,
+                    },
+                );
+            }
+            OP_TYPE_MATRIX => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Matrix {
+                        column: operand(1)?,
+                        columns: operand(2)?,
+                    },
+                );
+            }
+            OP_TYPE_IMAGE => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Image {
+                        dim: operand(2)?,
+                        sampled: operand(6)?,
+                    },
+                );
+            }
+            OP_TYPE_SAMPLER => self.add_type(operand(0)?, Type::Sampler),
+            OP_TYPE_SAMPLED_IMAGE => self.add_type(operand(0)?, Type::SampledImage),
+            OP_TYPE_ARRAY => {
+                let length = *self
+                    .constants
+                    .get(&operand(2)?)
+                    .ok_or("array length is not a constant")?;
+                self.add_type(
+                    operand(0)?,
+                    Type::Array {
+                        element: operand(1)?,
+                        length,
+                    },
+                );
+            }
+            OP_TYPE_RUNTIME_ARRAY => self.add_type(operand(0)?, Type::RuntimeArray),
+            OP_TYPE_STRUCT => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Struct {
+                        members: operands[1.min(operands.len())..].to_vec(),
+                    },
+                );
+            }
+            OP_TYPE_POINTER => {
+                self.add_type(
+                    operand(0)?,
+                    Type::Pointer {
+                        pointee: operand(2)?,
+                    },
+                );
+            }
+            OP_CONSTANT => {
+                // Only the low word matters for array lengths.
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => self.variables.push(Variable {
+                id: operand(1)?,
+                pointer_type: operand(0)?,
+                storage_class: operand(2)?,
+            }),
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_default();
+                match operand(1)? {
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    DECORATION_BUILT_IN => decorations.built_in = true,
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                    DECORATION_LOCATION => decorations.location = Some(operand(2)?),
+                    DECORATION_BINDING => decorations.binding = Some(operand(2)?),
+                    DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                    _ => {}
+                }
+            }
+            OP_MEMBER_DECORATE => {
+                let member = (operand(0)?, operand(1)?);
+                match operand(2)? {
+                    DECORATION_OFFSET => {
+                        self.member_offsets.insert(member, operand(3)?);
+                    }
+                    DECORATION_MATRIX_STRIDE => {
+                        self.member_matrix_strides.insert(member, operand(3)?);
+                    }
+                    DECORATION_BUILT_IN => {
+                        self.decorations.entry(member.0).or_default().built_in = true;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn add_type(&mut self, id: u32, ty: Type) {
+        self.types.insert(id, ty);
+    }
+
+    fn get_type(&self, id: u32) -> Result<&Type, String> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| format!("unknown type %{}", id))
+    }
+
+    fn decorations(&self, id: u32) -> Decorations {
+        self.decorations.get(&id).copied().unwrap_or_default()
+    }
+
+    /// The descriptor binding of a resource variable pointing to `pointee`.
+    fn binding(
+        &self,
+        variable: &Variable,
+        pointee: u32,
+        stage: vk::ShaderStageFlags,
+    ) -> Result<ReflectedBinding, String> {
+        let decorations = self.decorations(variable.id);
+        let (set, binding) = match (decorations.set, decorations.binding) {
+            (Some(set), Some(binding)) => (set, binding),
+            _ => {
+                return Err(format!(
+                    "resource %{} has no descriptor set or binding",
+                    variable.id
+                ))
+            }
+        };
+
+        let (resource, count) = match self.get_type(pointee)? {
+            Type::Array { element, length } => (*element, *length),
+            Type::RuntimeArray => {
+                return Err(format!(
+                    "runtime array at set {} binding {} is not supported",
+                    set, binding
+                ))
+            }
+            _ => (pointee, 1),
+        };
+
+        let descriptor_type = match (variable.storage_class, self.get_type(resource)?) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_CLASS_UNIFORM, _) if self.decorations(resource).buffer_block => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (STORAGE_CLASS_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::Image { dim, sampled }) => match (*dim, *sampled) {
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            _ => {
+                return Err(format!(
+                    "unsupported resource type at set {} binding {}",
+                    set, binding
+                ))
+            }
+        };
+
+        Ok(ReflectedBinding {
+            set,
+            binding: DescriptorBinding {
+                binding,
+                descriptor_type,
+                count,
+                stages: stage,
+            },
+        })
+    }
+
+    /// Size of a value of type `id` in bytes, following the explicit layout
+    /// decorations of buffer blocks.
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32, String> {
+        Ok(match self.get_type(id)? {
+            Type::Scalar { width, .. } => width / 8,
+            Type::Vector { component, count } => count * self.size_of(*component, None)?,
+            Type::Matrix { column, columns } => {
+                columns * matrix_stride.map_or_else(|| self.size_of(*column, None), Ok)?
+            }
+            Type::Array { element, length } => {
+                let stride = self.decorations(id).array_stride;
+                length * stride.map_or_else(|| self.size_of(*element, matrix_stride), Ok)?
+            }
+            Type::RuntimeArray => 0,
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, member) in members.iter().enumerate() {
+                    let key = (id, index as u32);
+                    let offset = self.member_offsets.get(&key).copied().unwrap_or(0);
+                    let member_size =
+                        self.size_of(*member, self.member_matrix_strides.get(&key).copied())?;
+                    size = size.max(offset + member_size);
+                }
+                size
+            }
+            _ => return Err(format!("type %{} has no size", id)),
+        })
+    }
+
+    /// User-defined inputs among `interface`. Matrices take one location per
+    /// column.
+    fn vertex_inputs(&self, interface: &[u32]) -> Result<Vec<VertexInput>, String> {
+        let mut inputs = Vec::new();
+
+        for variable in self.variables.iter() {
+            let decorations = self.decorations(variable.id);
+            if variable.storage_class != STORAGE_CLASS_INPUT
+                || !interface.contains(&variable.id)
+                || decorations.built_in
+            {
+                continue;
+            }
+
+            let location = decorations
+                .location
+                .ok_or_else(|| format!("vertex input %{} has no location", variable.id))?;
+            let ty = match self.get_type(variable.pointer_type)? {
+                Type::Pointer { pointee } => *pointee,
+                _ => return Err(format!("variable %{} is not a pointer", variable.id)),
+            };
+            let (column, columns) = match self.get_type(ty)? {
+                Type::Matrix { column, columns } => (*column, *columns),
+                _ => (ty, 1),
+            };
+            let (format, size) = self.vertex_format(column)?;
+
+            for index in 0..columns {
+                inputs.push(VertexInput {
+                    location: location + index,
+                    format,
+                    size,
+                });
+            }
+        }
+        inputs.sort_by_key(|input| input.location);
+
+        Ok(inputs)
+    }
+
+    /// Vertex attribute format a scalar or vector of type `id` is read from.
+    fn vertex_format(&self, id: u32) -> Result<(vk::Format, u32), String> {
+        let (component, count) = match self.get_type(id)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (id, 1),
+        };
+        let (kind, width) = match self.get_type(component)? {
+            Type::Scalar { kind, width } => (*kind, *width),
+            _ => return Err(format!("type %{} cannot be a vertex input", id)),
+        };
+
+        let formats = match (kind, width) {
+            (ScalarKind::Float, 32) => [
+                vk::Format::R32_SFLOAT,
+                vk::Format::R32G32_SFLOAT,
+                vk::Format::R32G32B32_SFLOAT,
+                vk::Format::R32G32B32A32_SFLOAT,
+            ],
+            (ScalarKind::Sint, 32) => [
+                vk::Format::R32_SINT,
+                vk::Format::R32G32_SINT,
+                vk::Format::R32G32B32_SINT,
+                vk::Format::R32G32B32A32_SINT,
+            ],
+            (ScalarKind::Uint, 32) => [
+                vk::Format::R32_UINT,
+                vk::Format::R32G32_UINT,
+                vk::Format::R32G32B32_UINT,
+                vk::Format::R32G32B32A32_UINT,
+            ],
+            (ScalarKind::Float, 64) => [
+                vk::Format::R64_SFLOAT,
+                vk::Format::R64G64_SFLOAT,
+                vk::Format::R64G64B64_SFLOAT,
+                vk::Format::R64G64B64A64_SFLOAT,
+            ],
+            _ => {
+                return Err(format!(
+                    "{}-bit {:?} vertex inputs are not supported",
+                    width, kind
+                ))
+            }
+        };
+
+        let format = (count as usize)
+            .checked_sub(1)
+            .and_then(|index| formats.get(index))
+            .copied()
+            .ok_or_else(|| format!("vector of {} components", count))?;
+
+        Ok((format, count * width / 8))
+    }
+}
+
+fn stage_flags(execution_model: u32) -> Result<vk::ShaderStageFlags, String> {
+    Ok(match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => return Err(format!("unsupported execution model {}", execution_model)),
+    })
+}
+
+/// Decodes a nul-terminated literal string and returns it with the number
+/// of words it occupies.
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes().iter() {
+            if *byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(*byte);
+        }
+    }
+
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::compiler::{compile_shader, ShaderLanguage, ShaderStage};
+
+    fn compile(source: &str, stage: ShaderStage) -> Vec<u32> {
+        compile_shader(source, ShaderLanguage::Glsl, stage)
+            .unwrap()
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// A vertex shader `main` reading one input at location 0, of a vector
+    /// type with `count` float components.
+    fn vertex_module(count: u32) -> Vec<u32> {
+        let main = u32::from_le_bytes(*b"main");
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 11, 0];
+        words.extend(instruction(OP_ENTRY_POINT, &[0, 1, main, 0, 10]));
+        words.extend(instruction(OP_DECORATE, &[10, DECORATION_LOCATION, 0]));
+        words.extend(instruction(OP_TYPE_FLOAT, &[2, 32]));
+        words.extend(instruction(OP_TYPE_VECTOR, &[3, 2, count]));
+        words.extend(instruction(OP_TYPE_POINTER, &[4, STORAGE_CLASS_INPUT, 3]));
+        words.extend(instruction(OP_VARIABLE, &[4, 10, STORAGE_CLASS_INPUT]));
+        words
+    }
+
+    #[test]
+    fn reflects_vertex_shaders() {
+        let words = compile(
+            r#"
+            #version 450
+
+            layout(set = 0, binding = 0) uniform Camera {
+                mat4 view_projection;
+            } camera;
+
+            layout(push_constant) uniform Push {
+                mat4 model;
+                vec4 tint;
+            } push;
+
+            layout(location = 0) in vec3 position;
+            layout(location = 1) in vec2 uv;
+
+            void main() {
+                vec2 rotated = uv * 2.0;
+                gl_Position = camera.view_projection * push.model
+                    * vec4(position + vec3(rotated, 0.0), 1.0) * push.tint.x;
+            }
+        "#,
+            ShaderStage::Vertex,
+        );
+
+        let reflection = reflect(&words).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.entry_point, "main");
+        assert_eq!(reflection.push_constant_size, 80);
+        assert_eq!(
+            reflection.bindings,
+            vec![ReflectedBinding {
+                set: 0,
+                binding: DescriptorBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    count: 1,
+                    stages: vk::ShaderStageFlags::VERTEX,
+                },
+            }]
+        );
+        let input = |location, format, size| VertexInput {
+            location,
+            format,
+            size,
+        };
+        assert_eq!(
+            reflection.vertex_inputs,
+            vec![
+                input(0, vk::Format::R32G32B32_SFLOAT, 12),
+                input(1, vk::Format::R32G32_SFLOAT, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn reflects_compute_bindings() {
+        let words = compile(
+            r#"
+            #version 450
+
+            layout(local_size_x = 64) in;
+
+            layout(set = 1, binding = 2) buffer Particles {
+                vec4 positions[];
+            } particles;
+            layout(set = 0, binding = 1, rgba8) uniform image2D target;
+            layout(set = 0, binding = 0) uniform texture2D source;
+            layout(set = 0, binding = 3) uniform sampler source_sampler;
+
+            void main() {
+                uint index = gl_GlobalInvocationID.x;
+                ivec2 texel = ivec2(index, 0);
+                particles.positions[index] = textureLod(sampler2D(source, source_sampler), vec2(0.5), 0.0);
+                imageStore(target, texel, particles.positions[index]);
+            }
+        "#,
+            ShaderStage::Compute,
+        );
+
+        let reflection = reflect(&words).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::COMPUTE);
+        assert!(reflection.vertex_inputs.is_empty());
+        let bindings: Vec<_> = reflection
+            .bindings
+            .iter()
+            .map(|reflected| {
+                (
+                    reflected.set,
+                    reflected.binding.binding,
+                    reflected.binding.descriptor_type,
+                )
+            })
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![
+                (0, 0, vk::DescriptorType::SAMPLED_IMAGE),
+                (0, 1, vk::DescriptorType::STORAGE_IMAGE),
+                (0, 3, vk::DescriptorType::SAMPLER),
+                (1, 2, vk::DescriptorType::STORAGE_BUFFER),
+            ]
+        );
+    }
+
+    #[test]
+    fn reflects_hand_written_modules() {
+        let reflection = reflect(&vertex_module(4)).unwrap();
+        assert_eq!(
+            reflection.vertex_inputs,
+            vec![VertexInput {
+                location: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                size: 16,
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        assert_eq!(
+            reflect(&vertex_module(0)).unwrap_err(),
+            "vector of 0 components"
+        );
+        assert_eq!(
+            reflect(&vertex_module(5)).unwrap_err(),
+            "vector of 5 components"
+        );
+        assert_eq!(reflect(&[]).unwrap_err(), "not a SPIR-V module");
+        assert_eq!(
+            reflect(&[0x0203_0723, 0x0001_0000, 0, 1, 0]).unwrap_err(),
+            "not a SPIR-V module"
+        );
+
+        let mut truncated = vertex_module(4);
+        truncated.pop();
+        assert_eq!(reflect(&truncated).unwrap_err(), "truncated instruction");
+
+        let mut zero_length = vertex_module(4);
+        zero_length.push(0);
+        assert_eq!(reflect(&zero_length).unwrap_err(), "truncated instruction");
+
+        let mut missing_operands = vertex_module(4);
+        missing_operands.extend(instruction(OP_TYPE_FLOAT, &[]));
+        assert_eq!(
+            reflect(&missing_operands).unwrap_err(),
+            "instruction 22 is missing operands"
+        );
+
+        let no_entry_point = vec![MAGIC, 0x0001_0000, 0, 1, 0];
+        assert_eq!(
+            reflect(&no_entry_point).unwrap_err(),
+            "module has no entry point"
+        );
+    }
+}
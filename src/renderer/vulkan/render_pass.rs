@@ -0,0 +1,137 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+};
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+/// Format, load and store operation of one render pass attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AttachmentKey {
+    pub format: vk::Format,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+}
+
+/// Attachments of a single-subpass render pass. Render passes differing only
+/// in load and store operations are compatible, so pipelines are created
+/// against the one from `RenderPassKey::compatible`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenderPassKey {
+    pub colors: Vec<AttachmentKey>,
+    pub depth: Option<AttachmentKey>,
+}
+
+impl RenderPassKey {
+    pub fn compatible(color_formats: &[vk::Format], depth_format: Option<vk::Format>) -> Self {
+        let attachment = |format| AttachmentKey {
+            format,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+        };
+
+        Self {
+            colors: color_formats.iter().copied().map(attachment).collect(),
+            depth: depth_format.map(attachment),
+        }
+    }
+}
+
+/// Render passes created on demand and kept until the cache is dropped,
+/// which must happen before the device.
+///
+/// Attachments start and end in their attachment layout and the render
+/// passes have no external dependencies: the render graph transitions and
+/// synchronizes attachments with explicit barriers around each pass.
+pub struct RenderPassCache {
+    device: Device,
+    render_passes: Mutex<HashMap<RenderPassKey, vk::RenderPass>>,
+}
+
+impl RenderPassCache {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            render_passes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &RenderPassKey) -> Result<vk::RenderPass, VreError> {
+        let mut render_passes = self.render_passes.lock().unwrap();
+        match render_passes.entry(key.clone()) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => {
+                let render_pass = create_render_pass(&self.device, key)?;
+                Ok(*entry.insert(render_pass))
+            }
+        }
+    }
+}
+
+impl Drop for RenderPassCache {
+    fn drop(&mut self) {
+        for render_pass in self.render_passes.get_mut().unwrap().values() {
+            unsafe {
+                self.device.destroy_render_pass(*render_pass, None);
+            }
+        }
+    }
+}
+
+fn create_render_pass(device: &Device, key: &RenderPassKey) -> Result<vk::RenderPass, VreError> {
+    let attachment = |attachment: &AttachmentKey, layout| {
+        vk::AttachmentDescription::builder()
+            .format(attachment.format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(attachment.load_op)
+            .store_op(attachment.store_op)
+            .stencil_load_op(attachment.load_op)
+            .stencil_store_op(attachment.store_op)
+            .initial_layout(layout)
+            .final_layout(layout)
+            .build()
+    };
+
+    let mut attachments: Vec<vk::AttachmentDescription> = key
+        .colors
+        .iter()
+        .map(|color| attachment(color, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+        .collect();
+    let color_refs: Vec<vk::AttachmentReference> = (0..key.colors.len())
+        .map(|index| vk::AttachmentReference {
+            attachment: index as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect();
+    let depth_ref = key.depth.as_ref().map(|depth| {
+        attachments.push(attachment(
+            depth,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ));
+        vk::AttachmentReference {
+            attachment: key.colors.len() as u32,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        }
+    });
+
+    let mut subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs);
+    if let Some(depth_ref) = depth_ref.as_ref() {
+        subpass = subpass.depth_stencil_attachment(depth_ref);
+    }
+    let subpasses = [subpass.build()];
+
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .build();
+
+    unsafe {
+        device
+            .create_render_pass(&render_pass_create_info, None)
+            .map_err(VreError::device("Could not create render pass."))
+    }
+}
@@ -0,0 +1,525 @@
+use std::{env, ffi::CString, fmt, str::FromStr};
+
+use ash::{version::InstanceV1_0, vk, Instance};
+
+use crate::renderer::VreError;
+use crate::utils;
+
+use super::{device, swapchain, SurfaceBundle};
+
+/// Environment variable used to force a specific physical device.
+pub const DEVICE_OVERRIDE_ENV: &str = "VRE_DEVICE";
+
+/// Forces the physical device used by the backend instead of the highest
+/// scoring one.
+///
+/// Parsed from strings such as `1` (enumeration index), `0x10de:0x2484`
+/// (vendor and device ID in hex) or `llvmpipe` (case-insensitive name
+/// substring).
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceOverride {
+    Index(usize),
+    Name(String),
+    Id { vendor_id: u32, device_id: u32 },
+}
+
+impl DeviceOverride {
+    /// Reads the override from `VRE_DEVICE`, if set and non-empty. Values
+    /// that don't parse are ignored with a warning.
+    pub fn from_env() -> Option<DeviceOverride> {
+        let value = env::var(DEVICE_OVERRIDE_ENV).ok()?;
+        if value.trim().is_empty() {
+            return None;
+        }
+
+        match value.parse() {
+            Ok(device_override) => Some(device_override),
+            Err(err) => {
+                log::warn!(
+                    "Ignoring invalid {} value {:?}: {}.",
+                    DEVICE_OVERRIDE_ENV,
+                    value,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    fn matches(&self, index: usize, properties: &vk::PhysicalDeviceProperties) -> bool {
+        match self {
+            DeviceOverride::Index(override_index) => *override_index == index,
+            DeviceOverride::Name(name) => utils::vk_to_string(&properties.device_name)
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+            DeviceOverride::Id {
+                vendor_id,
+                device_id,
+            } => properties.vendor_id == *vendor_id && properties.device_id == *device_id,
+        }
+    }
+}
+
+impl FromStr for DeviceOverride {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        if let Ok(index) = value.parse::<usize>() {
+            return Ok(DeviceOverride::Index(index));
+        }
+
+        let parse_hex = |id: &str| {
+            let id = id.trim();
+            let id = id
+                .strip_prefix("0x")
+                .or_else(|| id.strip_prefix("0X"))
+                .unwrap_or(id);
+            u32::from_str_radix(id, 16)
+        };
+
+        match value.split_once(':') {
+            Some((vendor_id, device_id)) => Ok(DeviceOverride::Id {
+                vendor_id: parse_hex(vendor_id)?,
+                device_id: parse_hex(device_id)?,
+            }),
+            None => Ok(DeviceOverride::Name(value.to_string())),
+        }
+    }
+}
+
+/// Caps of the scores that only rank devices of the same type. Together with
+/// the features score they stay below the smallest gap between type scores,
+/// so e.g. a CPU device reporting lots of RAM as device-local memory never
+/// beats a GPU.
+const MAX_LIMITS_SCORE: u32 = 20;
+const MAX_MEMORY_SCORE: u32 = 24;
+
+/// Breakdown of how a physical device was ranked. Devices missing a hard
+/// requirement carry a `rejection` reason and are never picked.
+#[derive(Clone, Debug)]
+pub struct DeviceScore {
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub type_score: u32,
+    pub limits_score: u32,
+    pub memory_score: u32,
+    pub features_score: u32,
+    pub rejection: Option<&'static str>,
+}
+
+impl DeviceScore {
+    pub fn total(&self) -> u32 {
+        self.type_score
+            .saturating_add(self.limits_score)
+            .saturating_add(self.memory_score)
+            .saturating_add(self.features_score)
+    }
+
+    pub fn is_suitable(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+impl fmt::Display for DeviceScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} \"{}\" ({:?}): type={} limits={} memory={} features={} total={}",
+            self.index,
+            self.name,
+            self.device_type,
+            self.type_score,
+            self.limits_score,
+            self.memory_score,
+            self.features_score,
+            self.total()
+        )?;
+
+        if let Some(rejection) = self.rejection {
+            write!(f, " [rejected: {}]", rejection)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Scores every physical device and returns the chosen one. A matching and
+/// suitable `device_override` always wins, otherwise the highest total score
+/// is used.
+pub fn pick_physical_device(
+    instance: &Instance,
+    surface_bundle: Option<&SurfaceBundle>,
+    device_extensions: &[CString],
+    device_override: Option<&DeviceOverride>,
+) -> Result<vk::PhysicalDevice, VreError> {
+    let devices = unsafe {
+        instance
+            .enumerate_physical_devices()
+            .map_err(VreError::instance("Could not fetch devices."))?
+    };
+
+    let scores: Vec<DeviceScore> = devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| {
+            score_device(instance, index, *device, surface_bundle, device_extensions)
+        })
+        .collect::<Result<_, _>>()?;
+
+    for score in scores.iter() {
+        log::info!("Physical device {}", score);
+    }
+
+    if let Some(device_override) = device_override {
+        let overridden = devices.iter().zip(scores.iter()).find(|(device, score)| {
+            let properties = unsafe { instance.get_physical_device_properties(**device) };
+            device_override.matches(score.index, &properties)
+        });
+
+        match overridden {
+            Some((device, score)) if score.is_suitable() => {
+                log::info!("Using overridden physical device #{}.", score.index);
+                return Ok(*device);
+            }
+            Some((_, score)) => log::warn!(
+                "Overridden physical device #{} is not suitable, falling back to scoring.",
+                score.index
+            ),
+            None => log::warn!(
+                "No physical device matches override {:?}, falling back to scoring.",
+                device_override
+            ),
+        }
+    }
+
+    let (device, score) = devices
+        .iter()
+        .zip(scores.iter())
+        .filter(|(_, score)| score.is_suitable())
+        .max_by_key(|(_, score)| score.total())
+        .ok_or(VreError::NoSuitableDevice)?;
+
+    log::info!("Using physical device #{}.", score.index);
+
+    Ok(*device)
+}
+
+/// Ranks a physical device by type, limits, device-local memory and optional
+/// features. Hard requirements (queues, extensions and swapchain support when
+/// presenting) only decide whether it can be picked at all.
+pub fn score_device(
+    instance: &Instance,
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+    surface_bundle: Option<&SurfaceBundle>,
+    device_extensions: &[CString],
+) -> Result<DeviceScore, VreError> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    Ok(DeviceScore {
+        rejection: check_requirements(
+            instance,
+            physical_device,
+            surface_bundle,
+            device_extensions,
+        )?,
+        ..rank_device(index, &properties, &features, &memory_properties)
+    })
+}
+
+/// The scores of a device, before its requirements are checked.
+fn rank_device(
+    index: usize,
+    properties: &vk::PhysicalDeviceProperties,
+    features: &vk::PhysicalDeviceFeatures,
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+) -> DeviceScore {
+    let type_score = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 500,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 250,
+        vk::PhysicalDeviceType::CPU => 100,
+        _ => 0,
+    };
+
+    let limits = &properties.limits;
+    let limits_score = (limits.max_image_dimension2_d / 1024)
+        .saturating_add(limits.max_bound_descriptor_sets)
+        .saturating_add(limits.max_push_constants_size / 64)
+        .min(MAX_LIMITS_SCORE);
+
+    let device_local_bytes = memory_properties.memory_heaps
+        [..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .fold(0, |total: vk::DeviceSize, heap| {
+            total.saturating_add(heap.size)
+        });
+    let memory_score =
+        (device_local_bytes / (256 * 1024 * 1024)).min(MAX_MEMORY_SCORE.into()) as u32;
+
+    let optional_features = [
+        features.sampler_anisotropy,
+        features.pipeline_statistics_query,
+        features.fill_mode_non_solid,
+        features.multi_draw_indirect,
+        limits.timestamp_compute_and_graphics,
+    ];
+    let features_score = optional_features
+        .iter()
+        .filter(|feature| **feature == vk::TRUE)
+        .count() as u32
+        * 10;
+
+    DeviceScore {
+        index,
+        name: utils::vk_to_string(&properties.device_name),
+        device_type: properties.device_type,
+        type_score,
+        limits_score,
+        memory_score,
+        features_score,
+        rejection: None,
+    }
+}
+
+fn check_requirements(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    surface_bundle: Option<&SurfaceBundle>,
+    device_extensions: &[CString],
+) -> Result<Option<&'static str>, VreError> {
+    let indices = device::find_queue_family(instance, physical_device, surface_bundle)?;
+
+    match surface_bundle {
+        Some(_) if !indices.is_complete() => return Ok(Some("no graphics or present queue")),
+        None if !indices.is_complete_headless() => return Ok(Some("no graphics queue")),
+        _ => {}
+    }
+
+    if !device::check_device_extension_support(instance, physical_device, device_extensions)? {
+        return Ok(Some("missing required device extensions"));
+    }
+
+    if let Some(surface_bundle) = surface_bundle {
+        let swapchain_details =
+            swapchain::SwapchainSupportDetails::new(physical_device, surface_bundle)?;
+        if swapchain_details.formats.is_empty() || swapchain_details.present_modes.is_empty() {
+            return Ok(Some("inadequate swapchain support"));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(name: &str, device_type: vk::PhysicalDeviceType) -> vk::PhysicalDeviceProperties {
+        let mut properties = vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2484,
+            device_type,
+            ..Default::default()
+        };
+        for (dst, src) in properties.device_name.iter_mut().zip(name.bytes()) {
+            *dst = src as _;
+        }
+        properties.limits.max_image_dimension2_d = 16384;
+        properties.limits.max_bound_descriptor_sets = 8;
+        properties.limits.max_push_constants_size = 256;
+        properties
+    }
+
+    fn memory_properties(
+        heaps: &[(vk::DeviceSize, vk::MemoryHeapFlags)],
+    ) -> vk::PhysicalDeviceMemoryProperties {
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (heap, &(size, flags)) in memory_properties.memory_heaps.iter_mut().zip(heaps) {
+            *heap = vk::MemoryHeap { size, flags };
+        }
+        memory_properties
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!("1".parse(), Ok(DeviceOverride::Index(1)));
+        assert_eq!(" 0 ".parse(), Ok(DeviceOverride::Index(0)));
+        assert_eq!(
+            "0x10de:0x2484".parse(),
+            Ok(DeviceOverride::Id {
+                vendor_id: 0x10de,
+                device_id: 0x2484,
+            })
+        );
+        assert_eq!(
+            "1002 : 0X73BF".parse(),
+            Ok(DeviceOverride::Id {
+                vendor_id: 0x1002,
+                device_id: 0x73bf,
+            })
+        );
+        assert_eq!(
+            "llvmpipe".parse(),
+            Ok(DeviceOverride::Name("llvmpipe".to_string()))
+        );
+        assert!("0x10de:gpu".parse::<DeviceOverride>().is_err());
+        assert!(":".parse::<DeviceOverride>().is_err());
+    }
+
+    #[test]
+    fn matches_overrides() {
+        let properties = properties(
+            "NVIDIA GeForce RTX 3070",
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+        );
+
+        assert!(DeviceOverride::Index(2).matches(2, &properties));
+        assert!(!DeviceOverride::Index(2).matches(1, &properties));
+        assert!(DeviceOverride::Name("geforce".to_string()).matches(0, &properties));
+        assert!(!DeviceOverride::Name("radeon".to_string()).matches(0, &properties));
+        assert!(DeviceOverride::Id {
+            vendor_id: 0x10de,
+            device_id: 0x2484,
+        }
+        .matches(0, &properties));
+        assert!(!DeviceOverride::Id {
+            vendor_id: 0x10de,
+            device_id: 0x2485,
+        }
+        .matches(0, &properties));
+    }
+
+    #[test]
+    fn scores_devices() {
+        let features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: vk::TRUE,
+            multi_draw_indirect: vk::TRUE,
+            ..Default::default()
+        };
+        let memory = memory_properties(&[
+            (8 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL),
+            (16 << 30, vk::MemoryHeapFlags::empty()),
+        ]);
+
+        let discrete = rank_device(
+            0,
+            &properties("discrete", vk::PhysicalDeviceType::DISCRETE_GPU),
+            &features,
+            &memory,
+        );
+        assert_eq!(discrete.name, "discrete");
+        assert_eq!(discrete.type_score, 1000);
+        assert_eq!(discrete.limits_score, MAX_LIMITS_SCORE);
+        assert_eq!(discrete.memory_score, MAX_MEMORY_SCORE);
+        assert_eq!(discrete.features_score, 20);
+        assert_eq!(
+            discrete.total(),
+            1000 + MAX_LIMITS_SCORE + MAX_MEMORY_SCORE + 20
+        );
+        assert!(discrete.is_suitable());
+
+        let integrated = rank_device(
+            1,
+            &properties("integrated", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            &vk::PhysicalDeviceFeatures::default(),
+            &memory_properties(&[(2 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL)]),
+        );
+        assert_eq!(integrated.type_score, 500);
+        assert_eq!(integrated.memory_score, 8);
+        assert_eq!(integrated.features_score, 0);
+        assert!(integrated.total() < discrete.total());
+
+        let cpu = rank_device(
+            2,
+            &properties("cpu", vk::PhysicalDeviceType::CPU),
+            &features,
+            &memory,
+        );
+        assert!(cpu.total() < integrated.total());
+    }
+
+    #[test]
+    fn ranks_device_types_first() {
+        let all_features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: vk::TRUE,
+            pipeline_statistics_query: vk::TRUE,
+            fill_mode_non_solid: vk::TRUE,
+            multi_draw_indirect: vk::TRUE,
+            ..Default::default()
+        };
+        let mut huge_limits = properties("lavapipe", vk::PhysicalDeviceType::CPU);
+        huge_limits.limits.max_bound_descriptor_sets = u32::MAX;
+        huge_limits.limits.timestamp_compute_and_graphics = vk::TRUE;
+
+        // A software driver reporting 512 GiB of system RAM as device-local.
+        let cpu = rank_device(
+            0,
+            &huge_limits,
+            &all_features,
+            &memory_properties(&[(512 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL)]),
+        );
+        let discrete = rank_device(
+            1,
+            &properties("discrete", vk::PhysicalDeviceType::DISCRETE_GPU),
+            &vk::PhysicalDeviceFeatures::default(),
+            &memory_properties(&[(4 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL)]),
+        );
+        assert_eq!(cpu.memory_score, MAX_MEMORY_SCORE);
+        assert_eq!(cpu.limits_score, MAX_LIMITS_SCORE);
+        assert!(cpu.total() < discrete.total());
+
+        // The best possible device of one type stays below the worst one of
+        // the next type up.
+        let best_cpu = rank_device(
+            0,
+            &huge_limits,
+            &all_features,
+            &memory_properties(&[(u64::MAX, vk::MemoryHeapFlags::DEVICE_LOCAL); 2]),
+        );
+        assert!(best_cpu.total() < 250);
+        let workstation = rank_device(
+            2,
+            &properties("integrated", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            &all_features,
+            &memory_properties(&[(128 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL)]),
+        );
+        let small_discrete = rank_device(
+            3,
+            &properties("discrete", vk::PhysicalDeviceType::DISCRETE_GPU),
+            &vk::PhysicalDeviceFeatures::default(),
+            &memory_properties(&[(8 << 30, vk::MemoryHeapFlags::DEVICE_LOCAL)]),
+        );
+        assert!(workstation.total() < small_discrete.total());
+    }
+
+    #[test]
+    fn formats_rejected_scores() {
+        let score = DeviceScore {
+            rejection: Some("no graphics queue"),
+            ..rank_device(
+                3,
+                &properties("llvmpipe", vk::PhysicalDeviceType::CPU),
+                &vk::PhysicalDeviceFeatures::default(),
+                &memory_properties(&[]),
+            )
+        };
+
+        assert!(!score.is_suitable());
+        assert_eq!(
+            score.to_string(),
+            "#3 \"llvmpipe\" (CPU): type=100 limits=20 memory=0 features=0 total=120 \
+             [rejected: no graphics queue]"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::BTreeMap, io::Cursor, sync::Arc};
+
+use ash::{version::DeviceV1_0, vk};
+
+use crate::renderer::VreError;
+
+use super::descriptor::{DescriptorBinding, DescriptorLayoutCache};
+use super::owned::OwnedDevice;
+use super::reflect::{self, Reflection};
+
+/// A `VkShaderModule` and the interface reflected from its SPIR-V.
+/// Destroyed when dropped, keeping the device alive until then.
+pub struct ShaderModule {
+    device: Arc<OwnedDevice>,
+    module: vk::ShaderModule,
+    reflection: Reflection,
+}
+
+impl ShaderModule {
+    /// Creates a module from SPIR-V in either byte order.
+    pub fn new(device: &Arc<OwnedDevice>, spirv: &[u8]) -> Result<Self, VreError> {
+        let code = ash::util::read_spv(&mut Cursor::new(spirv))
+            .map_err(|err| VreError::Shader(err.to_string()))?;
+        let reflection = reflect::reflect(&code).map_err(VreError::Shader)?;
+
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code).build();
+        let module = unsafe {
+            device
+                .create_shader_module(&create_info, None)
+                .map_err(VreError::device("Could not create shader module."))?
+        };
+
+        Ok(Self {
+            device: Arc::clone(device),
+            module,
+            reflection,
+        })
+    }
+
+    pub fn handle(&self) -> vk::ShaderModule {
+        self.module
+    }
+
+    pub fn stage(&self) -> vk::ShaderStageFlags {
+        self.reflection.stage
+    }
+
+    pub fn entry_point(&self) -> &str {
+        &self.reflection.entry_point
+    }
+}
+
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_shader_module(self.module, None);
+        }
+    }
+}
+
+/// A pipeline layout derived from the reflected interface of every stage of
+/// one pipeline. Descriptor sets bound with it must use the same bindings
+/// and stages as its set layouts. Destroyed when dropped, keeping the device
+/// alive until then.
+pub struct PipelineLayout {
+    device: Arc<OwnedDevice>,
+    layout: vk::PipelineLayout,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_range: Option<vk::PushConstantRange>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    vertex_stride: u32,
+}
+
+impl PipelineLayout {
+    /// Merges the bindings of `stages`, which must not contain a stage
+    /// twice. Fails if two stages declare the same binding with a different
+    /// descriptor type or count. Set numbers without bindings get an empty
+    /// layout.
+    pub fn new(
+        device: &Arc<OwnedDevice>,
+        layout_cache: &DescriptorLayoutCache,
+        stages: &[&ShaderModule],
+    ) -> Result<Self, VreError> {
+        let mut all_stages = vk::ShaderStageFlags::empty();
+        let mut sets: BTreeMap<u32, BTreeMap<u32, DescriptorBinding>> = BTreeMap::new();
+        let mut push_constant_size = 0;
+
+        for module in stages.iter() {
+            let reflection = &module.reflection;
+            if all_stages.contains(reflection.stage) {
+                return Err(VreError::Shader(format!(
+                    "{:?} stage is given twice",
+                    reflection.stage
+                )));
+            }
+            all_stages |= reflection.stage;
+
+            for reflected in reflection.bindings.iter() {
+                let binding = reflected.binding;
+                let existing = sets
+                    .entry(reflected.set)
+                    .or_default()
+                    .entry(binding.binding)
+                    .or_insert(DescriptorBinding {
+                        stages: vk::ShaderStageFlags::empty(),
+                        ..binding
+                    });
+
+                if existing.descriptor_type != binding.descriptor_type
+                    || existing.count != binding.count
+                {
+                    return Err(VreError::Shader(format!(
+                        "set {} binding {} is {:?}[{}] in {:?} but {:?}[{}] in {:?}",
+                        reflected.set,
+                        binding.binding,
+                        existing.descriptor_type,
+                        existing.count,
+                        existing.stages,
+                        binding.descriptor_type,
+                        binding.count,
+                        binding.stages
+                    )));
+                }
+                existing.stages |= binding.stages;
+            }
+
+            push_constant_size = push_constant_size.max(reflection.push_constant_size);
+        }
+
+        let set_count = sets.keys().next_back().map_or(0, |set| set + 1);
+        let set_layouts = (0..set_count)
+            .map(|set| {
+                let bindings: Vec<DescriptorBinding> = sets
+                    .get(&set)
+                    .map(|bindings| bindings.values().copied().collect())
+                    .unwrap_or_default();
+                layout_cache.get(&bindings)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // A single range for all stages that use push constants, so they are
+        // always pushed with the same stage flags.
+        let push_constant_range = (push_constant_size > 0).then(|| vk::PushConstantRange {
+            stage_flags: stages
+                .iter()
+                .filter(|module| module.reflection.push_constant_size > 0)
+                .fold(vk::ShaderStageFlags::empty(), |flags, module| {
+                    flags | module.stage()
+                }),
+            offset: 0,
+            size: push_constant_size,
+        });
+
+        let (vertex_attributes, vertex_stride) = stages
+            .iter()
+            .find(|module| module.stage() == vk::ShaderStageFlags::VERTEX)
+            .map(|module| interleaved_attributes(&module.reflection))
+            .unwrap_or_default();
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(
+                push_constant_range
+                    .as_ref()
+                    .map_or(&[], std::slice::from_ref),
+            )
+            .build();
+        let layout = unsafe {
+            device
+                .create_pipeline_layout(&layout_create_info, None)
+                .map_err(VreError::device("Could not create pipeline layout."))?
+        };
+
+        Ok(Self {
+            device: Arc::clone(device),
+            layout,
+            set_layouts,
+            push_constant_range,
+            vertex_attributes,
+            vertex_stride,
+        })
+    }
+
+    pub fn handle(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    /// Layout of every descriptor set, indexed by set number.
+    pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.set_layouts
+    }
+
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constant_range
+    }
+
+    /// Vertex inputs, read from binding 0 as one tightly packed, interleaved
+    /// vertex in location order.
+    pub fn vertex_attributes(&self) -> &[vk::VertexInputAttributeDescription] {
+        &self.vertex_attributes
+    }
+
+    /// Vertex binding 0 matching `vertex_attributes`, or `None` if the vertex
+    /// shader has no inputs.
+    pub fn vertex_binding(&self) -> Option<vk::VertexInputBindingDescription> {
+        (self.vertex_stride > 0).then_some(vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: self.vertex_stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        })
+    }
+}
+
+impl Drop for PipelineLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Attributes of a vertex stage packed into binding 0, and their stride.
+fn interleaved_attributes(
+    reflection: &Reflection,
+) -> (Vec<vk::VertexInputAttributeDescription>, u32) {
+    let mut offset = 0;
+    let attributes = reflection
+        .vertex_inputs
+        .iter()
+        .map(|input| {
+            let attribute = vk::VertexInputAttributeDescription {
+                location: input.location,
+                binding: 0,
+                format: input.format,
+                offset,
+            };
+            offset += input.size;
+            attribute
+        })
+        .collect();
+
+    (attributes, offset)
+}
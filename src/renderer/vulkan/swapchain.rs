@@ -1,6 +1,8 @@
-use ash::{version::DeviceV1_0, vk, Device, Instance};
+use ash::{prelude::VkResult, version::DeviceV1_0, vk, Device, Instance};
 
-use super::{QueueFamilyIndices, SurfaceBundle};
+use crate::renderer::{PresentMode, RendererConfig, SurfaceFormat, VreError};
+
+use super::{texture, QueueFamilyIndices, SurfaceBundle};
 
 pub struct SwapchainSupportDetails {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
@@ -9,69 +11,88 @@ pub struct SwapchainSupportDetails {
 }
 
 impl SwapchainSupportDetails {
-    pub fn new(physical_device: vk::PhysicalDevice, surface_bundle: &SurfaceBundle) -> Self {
+    pub fn new(
+        physical_device: vk::PhysicalDevice,
+        surface_bundle: &SurfaceBundle,
+    ) -> Result<Self, VreError> {
         let capabilities = unsafe {
             surface_bundle
                 .surface_loader
                 .get_physical_device_surface_capabilities(physical_device, surface_bundle.surface)
-                .expect("Failed to get physical device surface capabilities.")
+                .map_err(VreError::surface(
+                    "Failed to get physical device surface capabilities.",
+                ))?
         };
         let formats = unsafe {
             surface_bundle
                 .surface_loader
                 .get_physical_device_surface_formats(physical_device, surface_bundle.surface)
-                .expect("Failed to get physical device surface formats.")
+                .map_err(VreError::surface(
+                    "Failed to get physical device surface formats.",
+                ))?
         };
         let present_modes = unsafe {
             surface_bundle
                 .surface_loader
                 .get_physical_device_surface_present_modes(physical_device, surface_bundle.surface)
-                .expect("Failed to get physical device surface present modes.")
+                .map_err(VreError::surface(
+                    "Failed to get physical device surface present modes.",
+                ))?
         };
 
-        Self {
+        Ok(Self {
             capabilities,
             formats,
             present_modes,
-        }
+        })
     }
 
-    fn choose_format(&self) -> vk::SurfaceFormatKHR {
-        for available_format in self.formats.iter() {
-            if available_format.format == vk::Format::B8G8R8A8_SRGB
-                && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
-                return available_format.clone();
+    /// The first of the preferred formats the surface supports, or else the
+    /// first format it reports.
+    pub fn choose_format(
+        &self,
+        preference: SurfaceFormat,
+    ) -> Result<vk::SurfaceFormatKHR, VreError> {
+        for format in preference.candidates() {
+            let available_format = self.formats.iter().find(|available_format| {
+                available_format.format == *format
+                    && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            });
+
+            if let Some(available_format) = available_format {
+                return Ok(*available_format);
             }
         }
 
-        return self.formats.first().unwrap().clone();
+        self.formats.first().copied().ok_or_else(|| {
+            VreError::surface("Surface reports no formats.")(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
+        })
     }
 
-    fn choose_present_mode(&self) -> vk::PresentModeKHR {
-        for available_present_mode in self.present_modes.iter() {
-            if *available_present_mode == vk::PresentModeKHR::MAILBOX {
-                return available_present_mode.clone();
-            }
-        }
+    fn choose_present_mode(&self, preferred: PresentMode) -> vk::PresentModeKHR {
+        let preferred = preferred.to_vk();
 
-        return vk::PresentModeKHR::FIFO;
+        if self.present_modes.contains(&preferred) {
+            preferred
+        } else {
+            vk::PresentModeKHR::FIFO
+        }
     }
 
-    fn choose_extent(&self) -> vk::Extent2D {
-        if self.capabilities.current_extent.width != u32::max_value() {
+    fn choose_extent(&self, window_extent: vk::Extent2D) -> vk::Extent2D {
+        if self.capabilities.current_extent.width != u32::MAX {
             self.capabilities.current_extent
         } else {
             use num::clamp;
 
             vk::Extent2D::builder()
                 .width(clamp(
-                    crate::WINDOW_WIDTH,
+                    window_extent.width,
                     self.capabilities.min_image_extent.width,
                     self.capabilities.max_image_extent.width,
                 ))
                 .height(clamp(
-                    crate::WINDOW_HEIGHT,
+                    window_extent.height,
                     self.capabilities.min_image_extent.height,
                     self.capabilities.max_image_extent.height,
                 ))
@@ -80,27 +101,47 @@ impl SwapchainSupportDetails {
     }
 }
 
+#[allow(dead_code)]
 pub struct SwapchainBundle {
+    device: Device,
     pub swapchain_loader: ash::extensions::khr::Swapchain,
     pub swapchain: vk::SwapchainKHR,
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    /// `COLOR_ATTACHMENT` plus the transfer usages the surface supports.
+    pub swapchain_usage: vk::ImageUsageFlags,
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_image_views: Vec<vk::ImageView>,
 }
 
 impl SwapchainBundle {
+    /// Creates a swapchain sized to the surface, or to `window_extent` when
+    /// the surface leaves the size up to us, using the present mode and
+    /// format preferred by `config`. Pass the swapchain being replaced as
+    /// `old_swapchain` when recreating, or a null handle.
+    ///
+    /// Returns `None` while the surface has a zero-sized extent, e.g. when
+    /// the window is minimized.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         instance: &Instance,
         device: &Device,
         physical_device: vk::PhysicalDevice,
         surface_bundle: &SurfaceBundle,
-        queue_family: QueueFamilyIndices,
-    ) -> Self {
-        let swapchain_details = SwapchainSupportDetails::new(physical_device, surface_bundle);
-        let surface_format = swapchain_details.choose_format();
-        let present_mode = swapchain_details.choose_present_mode();
-        let extent = swapchain_details.choose_extent();
+        queue_family: &QueueFamilyIndices,
+        window_extent: vk::Extent2D,
+        config: &RendererConfig,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<Option<Self>, VreError> {
+        let swapchain_details = SwapchainSupportDetails::new(physical_device, surface_bundle)?;
+        let surface_format = swapchain_details.choose_format(config.surface_format)?;
+        let present_mode = swapchain_details.choose_present_mode(config.present_mode);
+        let extent = swapchain_details.choose_extent(window_extent);
+
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(None);
+        }
+
         let desired_image_count = swapchain_details.capabilities.min_image_count + 1;
         let desired_image_count = if swapchain_details.capabilities.max_image_count > 0 {
             desired_image_count.min(swapchain_details.capabilities.max_image_count)
@@ -108,26 +149,34 @@ impl SwapchainBundle {
             desired_image_count
         };
 
+        // Images are rendered on the graphics queue and presented from the
+        // present queue, so they must be shared when the families differ.
         let (image_sharing_mode, queue_family_indices) =
-            if queue_family.graphics_family != queue_family.present_family {
-                (
-                    vk::SharingMode::EXCLUSIVE,
-                    vec![
-                        queue_family.graphics_family.unwrap(),
-                        queue_family.present_family.unwrap(),
-                    ],
-                )
-            } else {
-                (vk::SharingMode::EXCLUSIVE, vec![])
+            match (queue_family.graphics_family, queue_family.present_family) {
+                (Some(graphics_family), Some(present_family))
+                    if graphics_family != present_family =>
+                {
+                    (
+                        vk::SharingMode::CONCURRENT,
+                        vec![graphics_family, present_family],
+                    )
+                }
+                _ => (vk::SharingMode::EXCLUSIVE, vec![]),
             };
 
+        // Color attachment usage is always supported. Copies into and out of
+        // the image are allowed where the surface supports them.
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (swapchain_details.capabilities.supported_usage_flags
+                & (vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST));
+
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surface_bundle.surface)
             .min_image_count(desired_image_count)
             .image_color_space(surface_format.color_space)
             .image_format(surface_format.format)
             .image_extent(extent)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(image_sharing_mode)
             .pre_transform(swapchain_details.capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -135,68 +184,150 @@ impl SwapchainBundle {
             .clipped(true)
             .image_array_layers(1)
             .queue_family_indices(&queue_family_indices)
+            .old_swapchain(old_swapchain)
             .build();
 
         let swapchain_loader = ash::extensions::khr::Swapchain::new(instance, device);
         let swapchain = unsafe {
             swapchain_loader
                 .create_swapchain(&swapchain_create_info, None)
-                .expect("Failed to create swapchain.")
-        };
-        let swapchain_images = unsafe {
-            swapchain_loader
-                .get_swapchain_images(swapchain)
-                .expect("Failed to get swapchain images.")
+                .map_err(VreError::swapchain("Failed to create swapchain."))?
         };
 
-        let swapchain_image_views =
-            SwapchainBundle::create_image_views(&swapchain_images, surface_format.format, device);
-
-        Self {
+        // Image views are added once the bundle owns the swapchain, so its
+        // `Drop` cleans up if creating one of them fails.
+        let mut swapchain_bundle = Self {
+            device: device.clone(),
             swapchain_loader,
             swapchain,
             swapchain_format: surface_format.format,
             swapchain_extent: extent,
-            swapchain_images,
-            swapchain_image_views,
+            swapchain_usage: image_usage,
+            swapchain_images: Vec::new(),
+            swapchain_image_views: Vec::new(),
+        };
+
+        swapchain_bundle.swapchain_images = unsafe {
+            swapchain_bundle
+                .swapchain_loader
+                .get_swapchain_images(swapchain)
+                .map_err(VreError::swapchain("Failed to get swapchain images."))?
+        };
+        swapchain_bundle.create_image_views()?;
+
+        Ok(Some(swapchain_bundle))
+    }
+
+    /// Acquires the next image to render into. The returned flag is set when
+    /// the swapchain is suboptimal and should be recreated after this frame.
+    pub fn acquire_next_image(&self, semaphore: vk::Semaphore) -> VkResult<(u32, bool)> {
+        unsafe {
+            self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                semaphore,
+                vk::Fence::null(),
+            )
         }
     }
 
-    fn create_image_views(
-        swapchain_images: &Vec<vk::Image>,
-        swapchain_format: vk::Format,
-        device: &Device,
-    ) -> Vec<vk::ImageView> {
-        swapchain_images
-            .iter()
-            .map(|image| {
-                let image_view_create_info = vk::ImageViewCreateInfo::builder()
-                    .image(*image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(swapchain_format)
-                    .components(
-                        vk::ComponentMapping::builder()
-                            .r(vk::ComponentSwizzle::IDENTITY)
-                            .g(vk::ComponentSwizzle::IDENTITY)
-                            .b(vk::ComponentSwizzle::IDENTITY)
-                            .a(vk::ComponentSwizzle::IDENTITY)
-                            .build(),
-                    )
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    })
-                    .build();
-
-                unsafe {
-                    device
-                        .create_image_view(&image_view_create_info, None)
-                        .expect("Could not create image view.")
-                }
-            })
-            .collect()
+    /// Queues `image_index` for presentation once `wait_semaphores` are
+    /// signaled. Returns whether the swapchain is suboptimal.
+    pub fn present(
+        &self,
+        queue: vk::Queue,
+        image_index: u32,
+        wait_semaphores: &[vk::Semaphore],
+    ) -> VkResult<bool> {
+        let swapchains = [self.swapchain];
+        let image_indices = [image_index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices)
+            .build();
+
+        unsafe { self.swapchain_loader.queue_present(queue, &present_info) }
+    }
+
+    fn create_image_views(&mut self) -> Result<(), VreError> {
+        for image in self.swapchain_images.iter() {
+            let image_view = texture::create_image_view(
+                &self.device,
+                *image,
+                vk::ImageViewType::TYPE_2D,
+                self.swapchain_format,
+                vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+            )?;
+
+            self.swapchain_image_views.push(image_view);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SwapchainBundle {
+    fn drop(&mut self) {
+        unsafe {
+            for image_view in self.swapchain_image_views.iter() {
+                self.device.destroy_image_view(*image_view, None);
+            }
+
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(formats: Vec<vk::SurfaceFormatKHR>) -> SwapchainSupportDetails {
+        SwapchainSupportDetails {
+            capabilities: vk::SurfaceCapabilitiesKHR::default(),
+            formats,
+            present_modes: vec![vk::PresentModeKHR::FIFO],
+        }
+    }
+
+    fn format(format: vk::Format) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    #[test]
+    fn chooses_formats() {
+        let details = details(vec![
+            format(vk::Format::A2B10G10R10_UNORM_PACK32),
+            format(vk::Format::R8G8B8A8_SRGB),
+        ]);
+        assert_eq!(
+            details.choose_format(SurfaceFormat::Srgb).unwrap(),
+            format(vk::Format::R8G8B8A8_SRGB)
+        );
+        assert_eq!(
+            details.choose_format(SurfaceFormat::Unorm).unwrap(),
+            format(vk::Format::A2B10G10R10_UNORM_PACK32)
+        );
+    }
+
+    #[test]
+    fn rejects_surfaces_without_formats() {
+        match details(Vec::new()).choose_format(SurfaceFormat::Srgb) {
+            Err(VreError::Surface { result, .. }) => {
+                assert_eq!(result, vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
+            }
+            other => panic!("unexpected result: {:?}", other.map(|format| format.format)),
+        }
     }
 }
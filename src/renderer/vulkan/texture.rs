@@ -0,0 +1,775 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Device, Instance,
+};
+
+use crate::renderer::VreError;
+
+use super::allocator::{Allocation, Allocator, MemoryLocation};
+use super::buffer::Buffer;
+use super::commands::submit_one_time;
+
+/// Dimensionality of a `Texture` and how many layers it has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    D2,
+    D2Array {
+        layers: u32,
+    },
+    /// Six square 2D layers, ordered +X, -X, +Y, -Y, +Z, -Z.
+    Cube,
+    D3 {
+        depth: u32,
+    },
+}
+
+impl TextureKind {
+    fn image_type(self) -> vk::ImageType {
+        match self {
+            TextureKind::D3 { .. } => vk::ImageType::TYPE_3D,
+            _ => vk::ImageType::TYPE_2D,
+        }
+    }
+
+    fn view_type(self) -> vk::ImageViewType {
+        match self {
+            TextureKind::D2 => vk::ImageViewType::TYPE_2D,
+            TextureKind::D2Array { .. } => vk::ImageViewType::TYPE_2D_ARRAY,
+            TextureKind::Cube => vk::ImageViewType::CUBE,
+            TextureKind::D3 { .. } => vk::ImageViewType::TYPE_3D,
+        }
+    }
+
+    fn layers(self) -> u32 {
+        match self {
+            TextureKind::D2 | TextureKind::D3 { .. } => 1,
+            TextureKind::D2Array { layers } => layers,
+            TextureKind::Cube => 6,
+        }
+    }
+
+    fn depth(self) -> u32 {
+        match self {
+            TextureKind::D3 { depth } => depth,
+            _ => 1,
+        }
+    }
+}
+
+/// Shape, format and usage of a `Texture`. Textures are always sampled and
+/// can be uploaded to; `usage` adds to that.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    pub(crate) kind: TextureKind,
+    pub(crate) format: vk::Format,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) mipmaps: bool,
+    pub(crate) usage: vk::ImageUsageFlags,
+}
+
+impl TextureDesc {
+    pub fn new(kind: TextureKind, format: vk::Format, width: u32, height: u32) -> Self {
+        Self {
+            kind,
+            format,
+            width,
+            height,
+            mipmaps: false,
+            usage: vk::ImageUsageFlags::empty(),
+        }
+    }
+
+    /// Allocates a full mip chain, generated from the base level on upload.
+    /// Ignored for formats that don't support linear blitting.
+    pub fn mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    /// Additional usage, e.g. `COLOR_ATTACHMENT` for render targets.
+    pub fn usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+}
+
+/// A sampled image with a view covering every mip level and layer. Tracks
+/// the layout all of its subresources are in. May outlive the `Renderer` it
+/// was created with.
+pub struct Texture {
+    device: Device,
+    /// Keeps the device alive until the texture is destroyed.
+    allocator: Arc<Allocator>,
+    image: vk::Image,
+    /// `None` until the image memory is bound.
+    allocation: Option<Allocation>,
+    view: vk::ImageView,
+    kind: TextureKind,
+    format: vk::Format,
+    extent: vk::Extent3D,
+    mip_levels: u32,
+    layout: vk::ImageLayout,
+}
+
+impl Texture {
+    /// Creates the texture in `UNDEFINED` layout without any contents.
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        allocator: &Arc<Allocator>,
+        desc: &TextureDesc,
+    ) -> Result<Self, VreError> {
+        if desc.kind == TextureKind::Cube && desc.width != desc.height {
+            return Err(VreError::InvalidResource(format!(
+                "cube texture faces must be square, not {}x{}",
+                desc.width, desc.height
+            )));
+        }
+
+        let extent = vk::Extent3D {
+            width: desc.width,
+            height: desc.height,
+            depth: desc.kind.depth(),
+        };
+        let mip_levels =
+            if desc.mipmaps && supports_linear_blit(instance, physical_device, desc.format) {
+                mip_level_count(extent)
+            } else {
+                if desc.mipmaps {
+                    log::warn!(
+                        "{:?} does not support linear blitting, creating a single mip level.",
+                        desc.format
+                    );
+                }
+                1
+            };
+
+        let mut usage =
+            desc.usage | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST;
+        if mip_levels > 1 {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        let flags = if desc.kind == TextureKind::Cube {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(desc.kind.image_type())
+            .format(desc.format)
+            .extent(extent)
+            .mip_levels(mip_levels)
+            .array_layers(desc.kind.layers())
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        // Handles are filled in one by one so `Drop` releases whatever was
+        // created if a later step fails.
+        let mut texture = Self {
+            device: device.clone(),
+            allocator: allocator.clone(),
+            image: unsafe {
+                device
+                    .create_image(&image_create_info, None)
+                    .map_err(VreError::device("Could not create texture image."))?
+            },
+            allocation: None,
+            view: vk::ImageView::null(),
+            kind: desc.kind,
+            format: desc.format,
+            extent,
+            mip_levels,
+            layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        texture.allocation = Some(allocator.allocate_image(
+            texture.image,
+            vk::ImageTiling::OPTIMAL,
+            MemoryLocation::GpuOnly,
+        )?);
+        texture.view = create_image_view(
+            device,
+            texture.image,
+            desc.kind.view_type(),
+            desc.format,
+            texture.subresource_range(),
+        )?;
+
+        Ok(texture)
+    }
+
+    /// Replaces the contents with `data`, tightly packed texels of the base
+    /// level for every layer in order, and regenerates the mip chain. Blocks
+    /// until the upload has finished on `queue`, which must support graphics
+    /// for blits. Leaves the texture in `SHADER_READ_ONLY_OPTIMAL`. Fails if
+    /// `data` doesn't hold exactly that many texels.
+    pub fn upload(
+        &mut self,
+        data: &[u8],
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) -> Result<(), VreError> {
+        let layers = self.kind.layers();
+        let expected = upload_size(self.format, self.extent, layers).ok_or_else(|| {
+            VreError::InvalidResource(format!("cannot upload {:?} textures", self.format))
+        })?;
+        if data.len() as u64 != expected {
+            return Err(VreError::InvalidResource(format!(
+                "texture upload has {} bytes, but {} layers of {}x{}x{} {:?} texels need {}",
+                data.len(),
+                layers,
+                self.extent.width,
+                self.extent.height,
+                self.extent.depth,
+                self.format,
+                expected
+            )));
+        }
+
+        let device = self.device.clone();
+        let staging = Buffer::staging(&device, &self.allocator, data)?;
+        let aspect_mask = aspect_mask(self.format);
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: self.kind.layers(),
+            })
+            .image_extent(self.extent)
+            .build();
+
+        submit_one_time(&device, command_pool, queue, |command_buffer| unsafe {
+            // The old contents are discarded.
+            self.layout = vk::ImageLayout::UNDEFINED;
+            self.transition(command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.handle(),
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            if self.mip_levels > 1 {
+                self.generate_mips(command_buffer);
+            } else {
+                self.transition(command_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            }
+        })
+    }
+
+    /// Fills every level below the base level by blitting from the level
+    /// above. Expects every level in `TRANSFER_DST_OPTIMAL` and leaves them
+    /// in `SHADER_READ_ONLY_OPTIMAL`.
+    fn generate_mips(&mut self, command_buffer: vk::CommandBuffer) {
+        let aspect_mask = aspect_mask(self.format);
+        let layer_count = self.kind.layers();
+        let level_range = |level| vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: level,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count,
+        };
+        let level_layers = |level| vk::ImageSubresourceLayers {
+            aspect_mask,
+            mip_level: level,
+            base_array_layer: 0,
+            layer_count,
+        };
+        let mut src_extent = self.extent;
+
+        for level in 1..self.mip_levels {
+            let dst_extent = vk::Extent3D {
+                width: (src_extent.width / 2).max(1),
+                height: (src_extent.height / 2).max(1),
+                depth: (src_extent.depth / 2).max(1),
+            };
+            let blit = vk::ImageBlit::builder()
+                .src_subresource(level_layers(level - 1))
+                .src_offsets([vk::Offset3D::default(), extent_to_offset(src_extent)])
+                .dst_subresource(level_layers(level))
+                .dst_offsets([vk::Offset3D::default(), extent_to_offset(dst_extent)])
+                .build();
+
+            record_barrier(
+                &self.device,
+                command_buffer,
+                self.image,
+                level_range(level - 1),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+            unsafe {
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+            }
+            record_barrier(
+                &self.device,
+                command_buffer,
+                self.image,
+                level_range(level - 1),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+
+            src_extent = dst_extent;
+        }
+
+        record_barrier(
+            &self.device,
+            command_buffer,
+            self.image,
+            level_range(self.mip_levels - 1),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        self.layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+    }
+
+    /// Records a barrier moving every subresource from the tracked layout to
+    /// `new_layout`. Does nothing if the texture already is in `new_layout`.
+    pub fn transition(&mut self, command_buffer: vk::CommandBuffer, new_layout: vk::ImageLayout) {
+        if self.layout == new_layout {
+            return;
+        }
+
+        record_barrier(
+            &self.device,
+            command_buffer,
+            self.image,
+            self.subresource_range(),
+            self.layout,
+            new_layout,
+        );
+        self.layout = new_layout;
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn kind(&self) -> TextureKind {
+        self.kind
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// Layout every subresource is in once recorded commands have executed.
+    pub fn layout(&self) -> vk::ImageLayout {
+        self.layout
+    }
+
+    fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: aspect_mask(self.format),
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.kind.layers(),
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_image(self.image, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
+
+/// Filtering and addressing of a sampler. Equal descriptions share one
+/// `VkSampler`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
+
+/// Samplers created on demand and kept until the cache is dropped, which
+/// must happen before the device.
+pub struct SamplerCache {
+    device: Device,
+    samplers: Mutex<HashMap<SamplerDesc, vk::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            samplers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the sampler for `desc`, creating it on first use. Every mip
+    /// level is sampled.
+    pub fn get(&self, desc: &SamplerDesc) -> Result<vk::Sampler, VreError> {
+        let mut samplers = self.samplers.lock().unwrap();
+
+        match samplers.entry(*desc) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => {
+                let sampler_create_info = vk::SamplerCreateInfo::builder()
+                    .mag_filter(desc.mag_filter)
+                    .min_filter(desc.min_filter)
+                    .mipmap_mode(desc.mipmap_mode)
+                    .address_mode_u(desc.address_mode)
+                    .address_mode_v(desc.address_mode)
+                    .address_mode_w(desc.address_mode)
+                    .max_lod(vk::LOD_CLAMP_NONE)
+                    .build();
+
+                let sampler = unsafe {
+                    self.device
+                        .create_sampler(&sampler_create_info, None)
+                        .map_err(VreError::device("Could not create sampler."))?
+                };
+
+                Ok(*entry.insert(sampler))
+            }
+        }
+    }
+}
+
+impl Drop for SamplerCache {
+    fn drop(&mut self) {
+        for sampler in self.samplers.get_mut().unwrap().values() {
+            unsafe {
+                self.device.destroy_sampler(*sampler, None);
+            }
+        }
+    }
+}
+
+/// Creates a view of `image` with identity swizzles.
+pub fn create_image_view(
+    device: &Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    subresource_range: vk::ImageSubresourceRange,
+) -> Result<vk::ImageView, VreError> {
+    let image_view_create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .components(vk::ComponentMapping {
+            r: vk::ComponentSwizzle::IDENTITY,
+            g: vk::ComponentSwizzle::IDENTITY,
+            b: vk::ComponentSwizzle::IDENTITY,
+            a: vk::ComponentSwizzle::IDENTITY,
+        })
+        .subresource_range(subresource_range)
+        .build();
+
+    unsafe {
+        device
+            .create_image_view(&image_view_create_info, None)
+            .map_err(VreError::device("Could not create image view."))
+    }
+}
+
+/// Records a pipeline barrier transitioning `range` of `image` between
+/// layouts, waiting for the accesses `old_layout` implies.
+pub fn record_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access, src_stage) = layout_access(old_layout);
+    let (dst_access, dst_stage) = layout_access(new_layout);
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range)
+        .build();
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+/// Accesses and stages an image in `layout` is typically used with.
+pub fn layout_access(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        _ => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+    }
+}
+
+/// Aspects a view of `format` covers.
+pub fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// Bytes of tightly packed texels covering `extent` in each of `layers`
+/// layers, `None` for formats without a known size.
+fn upload_size(format: vk::Format, extent: vk::Extent3D, layers: u32) -> Option<u64> {
+    let (block, block_size) = texel_block(format)?;
+    let blocks = |texels: u32| texels.div_ceil(block) as u64;
+
+    Some(
+        blocks(extent.width)
+            * blocks(extent.height)
+            * extent.depth as u64
+            * layers as u64
+            * block_size,
+    )
+}
+
+/// Width and height of the texel blocks of `format`, and their size in
+/// bytes. Blocks are single texels for uncompressed formats.
+fn texel_block(format: vk::Format) -> Option<(u32, u64)> {
+    use vk::Format as F;
+
+    let size = match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB | F::S8_UINT => 1,
+        F::R8G8_UNORM
+        | F::R8G8_SNORM
+        | F::R8G8_UINT
+        | F::R8G8_SINT
+        | F::R8G8_SRGB
+        | F::R16_UNORM
+        | F::R16_SNORM
+        | F::R16_UINT
+        | F::R16_SINT
+        | F::R16_SFLOAT
+        | F::D16_UNORM
+        | F::R5G6B5_UNORM_PACK16
+        | F::B5G6R5_UNORM_PACK16
+        | F::R4G4B4A4_UNORM_PACK16
+        | F::B4G4R4A4_UNORM_PACK16 => 2,
+        F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R8G8B8A8_UINT
+        | F::R8G8B8A8_SINT
+        | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM
+        | F::B8G8R8A8_SNORM
+        | F::B8G8R8A8_UINT
+        | F::B8G8R8A8_SINT
+        | F::B8G8R8A8_SRGB
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::A2R10G10B10_UNORM_PACK32
+        | F::B10G11R11_UFLOAT_PACK32
+        | F::E5B9G9R9_UFLOAT_PACK32
+        | F::R16G16_UNORM
+        | F::R16G16_SNORM
+        | F::R16G16_UINT
+        | F::R16G16_SINT
+        | F::R16G16_SFLOAT
+        | F::R32_UINT
+        | F::R32_SINT
+        | F::R32_SFLOAT
+        | F::D32_SFLOAT
+        | F::X8_D24_UNORM_PACK32 => 4,
+        F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::R16G16B16A16_UINT
+        | F::R16G16B16A16_SINT
+        | F::R16G16B16A16_SFLOAT
+        | F::R32G32_UINT
+        | F::R32G32_SINT
+        | F::R32G32_SFLOAT => 8,
+        F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => 12,
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => 16,
+        F::BC1_RGB_UNORM_BLOCK
+        | F::BC1_RGB_SRGB_BLOCK
+        | F::BC1_RGBA_UNORM_BLOCK
+        | F::BC1_RGBA_SRGB_BLOCK
+        | F::BC4_UNORM_BLOCK
+        | F::BC4_SNORM_BLOCK => return Some((4, 8)),
+        F::BC2_UNORM_BLOCK
+        | F::BC2_SRGB_BLOCK
+        | F::BC3_UNORM_BLOCK
+        | F::BC3_SRGB_BLOCK
+        | F::BC5_UNORM_BLOCK
+        | F::BC5_SNORM_BLOCK
+        | F::BC6H_UFLOAT_BLOCK
+        | F::BC6H_SFLOAT_BLOCK
+        | F::BC7_UNORM_BLOCK
+        | F::BC7_SRGB_BLOCK => return Some((4, 16)),
+        _ => return None,
+    };
+
+    Some((1, size))
+}
+
+/// Whether optimally tiled images of `format` can be blitted with linear
+/// filtering, which mip generation relies on.
+fn supports_linear_blit(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let features = unsafe {
+        instance
+            .get_physical_device_format_properties(physical_device, format)
+            .optimal_tiling_features
+    };
+
+    features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
+/// Levels in a full mip chain down to 1x1x1.
+fn mip_level_count(extent: vk::Extent3D) -> u32 {
+    let largest = extent.width.max(extent.height).max(extent.depth).max(1);
+
+    32 - largest.leading_zeros()
+}
+
+fn extent_to_offset(extent: vk::Extent3D) -> vk::Offset3D {
+    vk::Offset3D {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: extent.depth as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32, depth: u32) -> vk::Extent3D {
+        vk::Extent3D {
+            width,
+            height,
+            depth,
+        }
+    }
+
+    #[test]
+    fn upload_sizes() {
+        let rgba = vk::Format::R8G8B8A8_SRGB;
+        assert_eq!(upload_size(rgba, extent(4, 2, 1), 1), Some(32));
+        assert_eq!(upload_size(rgba, extent(4, 4, 1), 6), Some(384));
+        assert_eq!(
+            upload_size(vk::Format::R32G32B32A32_SFLOAT, extent(2, 2, 2), 1),
+            Some(128)
+        );
+        // Partial blocks at the edges are stored whole.
+        assert_eq!(
+            upload_size(vk::Format::BC1_RGBA_UNORM_BLOCK, extent(5, 4, 1), 1),
+            Some(16)
+        );
+        assert_eq!(upload_size(vk::Format::UNDEFINED, extent(1, 1, 1), 1), None);
+    }
+}
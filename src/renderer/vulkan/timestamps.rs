@@ -0,0 +1,444 @@
+use std::{cell::RefCell, collections::VecDeque, fs, io, path::Path};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Device, Instance,
+};
+
+use crate::renderer::{trace::ChromeTrace, VreError};
+
+/// Timing scopes one frame can record; further scopes are dropped.
+const MAX_SCOPES: u32 = 256;
+/// Frames of results kept by `GpuProfiler`.
+const HISTORY_LEN: usize = 240;
+/// Process id GPU scopes are listed under in Chrome traces.
+const TRACE_PID: u32 = 1;
+
+/// How timestamps written on the graphics queue are interpreted.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TimestampLimits {
+    /// Nanoseconds per timestamp tick, `timestampPeriod`.
+    period: f32,
+    /// `timestampValidBits` of the graphics queue family; zero if it
+    /// doesn't support timestamps.
+    valid_bits: u32,
+}
+
+impl TimestampLimits {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        graphics_family: u32,
+    ) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let limits = Self {
+            period: properties.limits.timestamp_period,
+            valid_bits: families[graphics_family as usize].timestamp_valid_bits,
+        };
+
+        if limits.is_supported() {
+            log::info!(
+                "GPU timestamps: {} ns per tick, {} valid bits.",
+                limits.period,
+                limits.valid_bits
+            );
+        } else {
+            log::info!("GPU timestamps are not supported on the graphics queue.");
+        }
+
+        limits
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.valid_bits > 0 && self.period > 0.0
+    }
+
+    /// Clears the bits of `ticks` the queue doesn't write.
+    fn mask(&self, ticks: u64) -> u64 {
+        if self.valid_bits >= 64 {
+            ticks
+        } else {
+            ticks & ((1 << self.valid_bits) - 1)
+        }
+    }
+
+    fn nanoseconds(&self, ticks: u64) -> f64 {
+        ticks as f64 * self.period as f64
+    }
+
+    /// Nanoseconds from `start` to `end`, which may have wrapped around
+    /// the valid bits since.
+    fn duration_ns(&self, start: u64, end: u64) -> f64 {
+        self.nanoseconds(self.mask(end.wrapping_sub(start)))
+    }
+}
+
+/// A timing scope recorded into a frame's command buffer.
+struct ScopeRecord {
+    name: String,
+    depth: usize,
+    /// Query written at the start of the scope; the end is the next one.
+    query: u32,
+}
+
+#[derive(Default)]
+struct ScopeState {
+    frame: u64,
+    scopes: Vec<ScopeRecord>,
+    /// Indices into `scopes` of the open scopes, `None` for scopes dropped
+    /// because the pool was full.
+    open: Vec<Option<usize>>,
+    next_query: u32,
+    /// Whether the recorded scopes were submitted and can be read back.
+    submitted: bool,
+}
+
+/// Timestamp query pool of one frame context. Scopes write a timestamp at
+/// their start and end; the results are read back once the frame's fence
+/// has signaled, so reading never waits for the GPU.
+pub(crate) struct TimestampQueries {
+    device: Device,
+    limits: TimestampLimits,
+    /// Null if timestamps are unsupported, which turns scopes into no-ops.
+    pool: vk::QueryPool,
+    /// Recorded through shared references from render graph passes.
+    state: RefCell<ScopeState>,
+    results: Option<GpuFrameTimings>,
+}
+
+impl TimestampQueries {
+    pub fn new(device: &Device, limits: TimestampLimits) -> Result<Self, VreError> {
+        let mut queries = Self {
+            device: device.clone(),
+            limits,
+            pool: vk::QueryPool::null(),
+            state: RefCell::new(ScopeState::default()),
+            results: None,
+        };
+
+        if limits.is_supported() {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(MAX_SCOPES * 2)
+                .build();
+            queries.pool = unsafe {
+                device
+                    .create_query_pool(&create_info, None)
+                    .map_err(VreError::device("Could not create timestamp query pool."))?
+            };
+        }
+
+        Ok(queries)
+    }
+
+    /// Resets the pool and opens the scope covering frame `frame`. Must be
+    /// recorded outside a render pass, before any other scope.
+    pub fn begin_frame(&self, command_buffer: vk::CommandBuffer, frame: u64) {
+        *self.state.borrow_mut() = ScopeState {
+            frame,
+            ..ScopeState::default()
+        };
+
+        if self.pool != vk::QueryPool::null() {
+            unsafe {
+                self.device
+                    .cmd_reset_query_pool(command_buffer, self.pool, 0, MAX_SCOPES * 2);
+            }
+        }
+        self.begin_scope(command_buffer, "frame");
+    }
+
+    /// Closes every open scope. The frame's results are read back by
+    /// `resolve` once it has been submitted and has finished.
+    pub fn end_frame(&self, command_buffer: vk::CommandBuffer) {
+        self.end_scopes(command_buffer, 0);
+        self.state.borrow_mut().submitted = true;
+    }
+
+    /// Opens a scope nested in the innermost open one and returns its depth.
+    pub fn begin_scope(&self, command_buffer: vk::CommandBuffer, name: &str) -> usize {
+        let mut state = self.state.borrow_mut();
+        let depth = state.open.len();
+
+        if self.pool == vk::QueryPool::null() || state.next_query == MAX_SCOPES * 2 {
+            state.open.push(None);
+            return depth;
+        }
+
+        let query = state.next_query;
+        state.next_query += 2;
+        state.scopes.push(ScopeRecord {
+            name: name.to_string(),
+            depth,
+            query,
+        });
+        let index = state.scopes.len() - 1;
+        state.open.push(Some(index));
+
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.pool,
+                query,
+            );
+        }
+
+        depth
+    }
+
+    /// Closes the innermost open scope if it is at least `min_depth` deep.
+    pub fn end_scope(&self, command_buffer: vk::CommandBuffer, min_depth: usize) {
+        let depth = self.state.borrow().open.len();
+        if depth > min_depth {
+            self.end_scopes(command_buffer, depth - 1);
+        } else {
+            log::warn!("GPU timing scope ended without being started.");
+        }
+    }
+
+    /// Closes the open scopes down to and including the one at `depth`.
+    pub fn end_scopes(&self, command_buffer: vk::CommandBuffer, depth: usize) {
+        let mut state = self.state.borrow_mut();
+
+        while state.open.len() > depth {
+            let index = match state.open.pop().unwrap() {
+                Some(index) => index,
+                None => continue,
+            };
+
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    self.pool,
+                    state.scopes[index].query + 1,
+                );
+            }
+        }
+    }
+
+    /// Reads back the scopes of the last submission. Must only be called
+    /// once it has finished on the GPU.
+    pub fn resolve(&mut self) -> Result<(), VreError> {
+        let state = std::mem::take(self.state.get_mut());
+        if !state.submitted || state.next_query == 0 {
+            return Ok(());
+        }
+
+        let mut ticks = vec![0u64; state.next_query as usize];
+        let result = unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                0,
+                state.next_query,
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match result {
+            Ok(()) => {}
+            // The submission failed, so the queries were never written.
+            Err(vk::Result::NOT_READY) => return Ok(()),
+            Err(err) => {
+                return Err(VreError::device("Failed to read timestamp queries.")(err));
+            }
+        }
+
+        let limits = self.limits;
+        let scopes = state
+            .scopes
+            .into_iter()
+            .map(|scope| {
+                let start = limits.mask(ticks[scope.query as usize]);
+                let end = limits.mask(ticks[scope.query as usize + 1]);
+
+                GpuScope {
+                    name: scope.name,
+                    depth: scope.depth,
+                    start_ns: limits.nanoseconds(start),
+                    duration_ns: limits.duration_ns(start, end),
+                }
+            })
+            .collect();
+
+        self.results = Some(GpuFrameTimings {
+            frame: state.frame,
+            scopes,
+        });
+
+        Ok(())
+    }
+
+    /// The results `resolve` read back, if they haven't been taken yet.
+    pub fn take_results(&mut self) -> Option<GpuFrameTimings> {
+        self.results.take()
+    }
+}
+
+impl Drop for TimestampQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
+
+/// A named GPU timing scope of a frame.
+#[derive(Clone, Debug)]
+pub struct GpuScope {
+    pub name: String,
+    /// Nesting depth: 0 for the scope covering the whole frame, 1 for
+    /// render graph passes and more for scopes inside passes.
+    pub depth: usize,
+    /// Device time the scope started at. Only differences between
+    /// timestamps are meaningful.
+    pub start_ns: f64,
+    pub duration_ns: f64,
+}
+
+/// GPU timings of one frame, its scopes in the order they started.
+#[derive(Clone, Debug)]
+pub struct GpuFrameTimings {
+    /// Number of the frame, counting from the first frame rendered.
+    pub frame: u64,
+    pub scopes: Vec<GpuScope>,
+}
+
+impl GpuFrameTimings {
+    /// GPU time the whole frame took, in milliseconds.
+    pub fn frame_ms(&self) -> f64 {
+        self.scopes
+            .first()
+            .map_or(0.0, |scope| scope.duration_ns / 1_000_000.0)
+    }
+
+    /// The first scope called `name`.
+    pub fn scope(&self, name: &str) -> Option<&GpuScope> {
+        self.scopes.iter().find(|scope| scope.name == name)
+    }
+}
+
+/// GPU timings of the most recent frames, measured with timestamp queries
+/// around every frame, every render graph pass and the scopes passes open
+/// with `PassContext::begin_gpu_scope`. Results arrive once a frame's
+/// fence has signaled, as many frames late as there are frames in flight.
+pub struct GpuProfiler {
+    limits: TimestampLimits,
+    frames: VecDeque<GpuFrameTimings>,
+    next_frame: u64,
+}
+
+impl GpuProfiler {
+    pub(crate) fn new(limits: TimestampLimits) -> Self {
+        Self {
+            limits,
+            frames: VecDeque::with_capacity(HISTORY_LEN),
+            next_frame: 0,
+        }
+    }
+
+    pub(crate) fn limits(&self) -> TimestampLimits {
+        self.limits
+    }
+
+    /// Number of the next frame, counting up.
+    pub(crate) fn next_frame(&mut self) -> u64 {
+        self.next_frame += 1;
+        self.next_frame - 1
+    }
+
+    pub(crate) fn push(&mut self, timings: GpuFrameTimings) {
+        if self.frames.len() == HISTORY_LEN {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timings);
+    }
+
+    /// Whether the graphics queue writes timestamps. If not, no timings
+    /// are ever recorded.
+    pub fn is_supported(&self) -> bool {
+        self.limits.is_supported()
+    }
+
+    /// Nanoseconds per timestamp tick.
+    pub fn timestamp_period(&self) -> f32 {
+        self.limits.period
+    }
+
+    /// Timings of the most recent frame that has finished on the GPU.
+    pub fn latest(&self) -> Option<&GpuFrameTimings> {
+        self.frames.back()
+    }
+
+    /// Timings of up to the last 240 finished frames, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &GpuFrameTimings> {
+        self.frames.iter()
+    }
+
+    /// The recorded frames in the Chrome trace event format, for
+    /// `chrome://tracing` or Perfetto.
+    pub fn chrome_trace(&self) -> String {
+        let origin = self
+            .frames
+            .iter()
+            .flat_map(|frame| frame.scopes.first())
+            .map(|scope| scope.start_ns)
+            .fold(f64::INFINITY, f64::min);
+        let mut trace = ChromeTrace::default();
+        trace.process_name(TRACE_PID, "GPU");
+        trace.thread_name((TRACE_PID, 0), "Graphics queue");
+
+        for frame in self.frames.iter() {
+            for scope in frame.scopes.iter() {
+                trace.complete(
+                    &scope.name,
+                    "gpu",
+                    (TRACE_PID, 0),
+                    (scope.start_ns - origin) / 1000.0,
+                    scope.duration_ns / 1000.0,
+                    Some(serde_json::json!({ "frame": frame.frame })),
+                );
+            }
+        }
+
+        trace.to_json()
+    }
+
+    pub fn save_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.chrome_trace())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(valid_bits: u32) -> TimestampLimits {
+        TimestampLimits {
+            period: 2.0,
+            valid_bits,
+        }
+    }
+
+    #[test]
+    fn masks_invalid_bits() {
+        assert_eq!(limits(36).mask(u64::MAX), (1 << 36) - 1);
+        assert_eq!(limits(36).mask(1 << 36 | 5), 5);
+        assert_eq!(limits(64).mask(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn measures_durations_across_a_wrap() {
+        let limits36 = limits(36);
+        let start = (1 << 36) - 10;
+        let end = limits36.mask(start + 25);
+        assert_eq!(end, 15);
+        assert_eq!(limits36.duration_ns(start, end), 50.0);
+        assert_eq!(limits36.duration_ns(100, 130), 60.0);
+
+        assert_eq!(limits(64).duration_ns(u64::MAX - 4, 5), 20.0);
+    }
+}
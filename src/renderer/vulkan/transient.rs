@@ -0,0 +1,574 @@
+use std::sync::Arc;
+
+use ash::{version::DeviceV1_0, vk, Device};
+
+use crate::renderer::VreError;
+
+use super::allocator::{Allocation, AllocationDesc, Allocator, MemoryLocation, ResourceKind};
+use super::texture;
+
+/// Usage flags an image may have and still be a `TRANSIENT_ATTACHMENT`.
+const ATTACHMENT_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw()
+        | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT.as_raw()
+        | vk::ImageUsageFlags::INPUT_ATTACHMENT.as_raw(),
+);
+
+/// Shape of a transient render graph image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageKey {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransientDesc {
+    Image(ImageKey),
+    Buffer {
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    },
+}
+
+/// A resource the render graph needs for one frame, and the positions of
+/// the first and last pass using it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransientRequest {
+    pub desc: TransientDesc,
+    pub first: usize,
+    pub last: usize,
+}
+
+impl TransientRequest {
+    fn overlaps(&self, other: &TransientRequest) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransientResource {
+    Image(vk::Image, vk::ImageView),
+    Buffer(vk::Buffer),
+}
+
+/// How much memory the render graph's transient resources took in the last
+/// frame, and how much aliasing resources with disjoint lifetimes saved.
+/// Lazily allocated attachments are only counted in `lazy_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransientMemoryReport {
+    /// Transient images and buffers used by the frame.
+    pub resource_count: usize,
+    /// Allocations the aliased resources were placed in.
+    pub allocation_count: usize,
+    /// Bytes the aliased resources would take with their own memory.
+    pub requested_bytes: u64,
+    /// Bytes actually allocated for them.
+    pub allocated_bytes: u64,
+    /// Attachments backed by lazily allocated memory.
+    pub lazy_count: usize,
+    /// Memory requirements of those attachments, which the device only
+    /// commits if they leave tile memory.
+    pub lazy_bytes: u64,
+}
+
+impl TransientMemoryReport {
+    pub fn saved_bytes(&self) -> u64 {
+        self.requested_bytes - self.allocated_bytes
+    }
+}
+
+/// A memory range shared by resources whose lifetimes don't overlap.
+struct Slot {
+    kind: ResourceKind,
+    requirements: vk::MemoryRequirements,
+    /// Indices of the requests placed in the slot, sorted by first use.
+    occupants: Vec<usize>,
+}
+
+/// Transient images and buffers of the render graph for one frame context,
+/// plus the framebuffers it creates while recording.
+///
+/// Resources whose lifetimes don't overlap share memory. The placement is
+/// kept for as long as the graph asks for the same resources, so it only
+/// changes when the graph or the output size does. Resources may only be
+/// replaced or destroyed once the context's previous submission finished.
+pub struct TransientPool {
+    device: Device,
+    allocator: Arc<Allocator>,
+    requests: Vec<TransientRequest>,
+    /// Indexed like `requests`.
+    resources: Vec<TransientResource>,
+    /// One per slot, and one per lazily allocated attachment.
+    allocations: Vec<Allocation>,
+    /// Pairs of requests placed in the same memory, the first one's last
+    /// use before the second one's first use.
+    aliases: Vec<(usize, usize)>,
+    report: TransientMemoryReport,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl TransientPool {
+    pub fn new(device: &Device, allocator: &Arc<Allocator>) -> Self {
+        Self {
+            device: device.clone(),
+            allocator: allocator.clone(),
+            requests: Vec::new(),
+            resources: Vec::new(),
+            allocations: Vec::new(),
+            aliases: Vec::new(),
+            report: TransientMemoryReport::default(),
+            framebuffers: Vec::new(),
+        }
+    }
+
+    /// Makes a resource available for each of `requests`, replacing the
+    /// previous ones unless the requests are unchanged.
+    pub fn acquire(&mut self, requests: &[TransientRequest]) -> Result<(), VreError> {
+        if self.requests == requests {
+            return Ok(());
+        }
+
+        self.release();
+        self.create(requests)?;
+        self.requests = requests.to_vec();
+
+        log::debug!(
+            "Placed {} transient resources in {} bytes, aliasing saved {} bytes.",
+            self.report.resource_count,
+            self.report.allocated_bytes,
+            self.report.saved_bytes()
+        );
+
+        Ok(())
+    }
+
+    /// Resource created for the request at `index` by the last `acquire`.
+    pub fn resource(&self, index: usize) -> TransientResource {
+        self.resources[index]
+    }
+
+    /// Pairs of request indices sharing memory, in the order they use it.
+    /// The second may only be accessed once the first is done with it.
+    pub fn aliases(&self) -> &[(usize, usize)] {
+        &self.aliases
+    }
+
+    pub fn report(&self) -> TransientMemoryReport {
+        self.report
+    }
+
+    fn create(&mut self, requests: &[TransientRequest]) -> Result<(), VreError> {
+        let lazy_supported = self.allocator.supports(!0, MemoryLocation::Lazy);
+
+        // Resources are created unbound, and pushed right away so `release`
+        // destroys them if a later step fails.
+        let mut requirements = Vec::with_capacity(requests.len());
+        for request in requests {
+            let (resource, resource_requirements) = match request.desc {
+                TransientDesc::Image(key) => {
+                    let mut usage = key.usage;
+                    if lazy_supported && ATTACHMENT_USAGE.contains(usage) {
+                        usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+                    }
+                    let image = self.create_image(key, usage)?;
+                    let resource_requirements =
+                        unsafe { self.device.get_image_memory_requirements(image) };
+
+                    (
+                        TransientResource::Image(image, vk::ImageView::null()),
+                        resource_requirements,
+                    )
+                }
+                TransientDesc::Buffer { size, usage } => {
+                    let buffer = self.create_buffer(size, usage)?;
+                    let resource_requirements =
+                        unsafe { self.device.get_buffer_memory_requirements(buffer) };
+
+                    (TransientResource::Buffer(buffer), resource_requirements)
+                }
+            };
+
+            self.resources.push(resource);
+            requirements.push(resource_requirements);
+        }
+
+        let mut report = TransientMemoryReport {
+            resource_count: requests.len(),
+            ..TransientMemoryReport::default()
+        };
+
+        // Lazily allocated attachments take no real memory, so they are not
+        // worth aliasing.
+        let mut placed = Vec::new();
+        for (index, resource) in self.resources.iter().enumerate() {
+            let lazy = match resource {
+                TransientResource::Image(..) => {
+                    is_transient_attachment(&requests[index].desc)
+                        && self
+                            .allocator
+                            .supports(requirements[index].memory_type_bits, MemoryLocation::Lazy)
+                }
+                TransientResource::Buffer(_) => false,
+            };
+
+            if !lazy {
+                placed.push(index);
+                continue;
+            }
+
+            let allocation = self.allocator.allocate(&AllocationDesc {
+                requirements: requirements[index],
+                location: MemoryLocation::Lazy,
+                kind: ResourceKind::Optimal,
+                dedicated: true,
+            })?;
+            let bound = self.bind(index, &allocation);
+            self.allocations.push(allocation);
+            bound?;
+
+            report.lazy_count += 1;
+            report.lazy_bytes += requirements[index].size;
+        }
+
+        let slots = plan_slots(requests, &requirements, &self.resources, &placed);
+        for slot in slots.iter() {
+            let allocation = self.allocator.allocate(&AllocationDesc {
+                requirements: slot.requirements,
+                location: MemoryLocation::GpuOnly,
+                kind: slot.kind,
+                dedicated: false,
+            })?;
+            let bound = slot
+                .occupants
+                .iter()
+                .try_for_each(|&index| self.bind(index, &allocation));
+            self.allocations.push(allocation);
+            bound?;
+
+            report.allocation_count += 1;
+            report.allocated_bytes += slot.requirements.size;
+        }
+        self.aliases = alias_pairs(&slots);
+        report.requested_bytes = placed.iter().map(|&index| requirements[index].size).sum();
+
+        for (index, request) in requests.iter().enumerate() {
+            if let (TransientResource::Image(image, view), TransientDesc::Image(key)) =
+                (&mut self.resources[index], request.desc)
+            {
+                *view = texture::create_image_view(
+                    &self.device,
+                    *image,
+                    vk::ImageViewType::TYPE_2D,
+                    key.format,
+                    vk::ImageSubresourceRange {
+                        aspect_mask: texture::aspect_mask(key.format),
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                )?;
+            }
+        }
+
+        self.report = report;
+
+        Ok(())
+    }
+
+    fn create_image(
+        &self,
+        key: ImageKey,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<vk::Image, VreError> {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(key.format)
+            .extent(vk::Extent3D {
+                width: key.extent.width,
+                height: key.extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        unsafe {
+            self.device
+                .create_image(&image_create_info, None)
+                .map_err(VreError::device("Could not create render graph image."))
+        }
+    }
+
+    fn create_buffer(
+        &self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<vk::Buffer, VreError> {
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        unsafe {
+            self.device
+                .create_buffer(&buffer_create_info, None)
+                .map_err(VreError::device("Could not create render graph buffer."))
+        }
+    }
+
+    /// Binds the resource at `index` to the start of `allocation`.
+    fn bind(&self, index: usize, allocation: &Allocation) -> Result<(), VreError> {
+        unsafe {
+            match self.resources[index] {
+                TransientResource::Image(image, _) => self
+                    .device
+                    .bind_image_memory(image, allocation.memory(), allocation.offset())
+                    .map_err(VreError::device("Could not bind image memory.")),
+                TransientResource::Buffer(buffer) => self
+                    .device
+                    .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+                    .map_err(VreError::device("Could not bind buffer memory.")),
+            }
+        }
+    }
+
+    /// Destroys every resource and frees their memory.
+    fn release(&mut self) {
+        unsafe {
+            for resource in self.resources.drain(..) {
+                match resource {
+                    TransientResource::Image(image, view) => {
+                        self.device.destroy_image_view(view, None);
+                        self.device.destroy_image(image, None);
+                    }
+                    TransientResource::Buffer(buffer) => self.device.destroy_buffer(buffer, None),
+                }
+            }
+        }
+        for allocation in self.allocations.drain(..) {
+            self.allocator.free(allocation);
+        }
+
+        self.requests.clear();
+        self.aliases.clear();
+        self.report = TransientMemoryReport::default();
+    }
+
+    /// Creates a framebuffer that is destroyed by the next `reset`.
+    pub fn framebuffer(
+        &mut self,
+        render_pass: vk::RenderPass,
+        attachments: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<vk::Framebuffer, VreError> {
+        let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+
+        let framebuffer = unsafe {
+            self.device
+                .create_framebuffer(&framebuffer_create_info, None)
+                .map_err(VreError::device("Could not create framebuffer."))?
+        };
+        self.framebuffers.push(framebuffer);
+
+        Ok(framebuffer)
+    }
+
+    /// Destroys the framebuffers of the previous frame. Resources are kept
+    /// for the next `acquire`.
+    pub fn reset(&mut self) {
+        unsafe {
+            for framebuffer in self.framebuffers.drain(..) {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+        }
+    }
+}
+
+impl Drop for TransientPool {
+    fn drop(&mut self) {
+        self.reset();
+        self.release();
+    }
+}
+
+/// Whether the request can be a `TRANSIENT_ATTACHMENT`.
+fn is_transient_attachment(desc: &TransientDesc) -> bool {
+    match desc {
+        TransientDesc::Image(key) => ATTACHMENT_USAGE.contains(key.usage),
+        TransientDesc::Buffer { .. } => false,
+    }
+}
+
+/// Places the requests at `indices` into slots, largest first, sharing a
+/// slot whenever lifetimes don't overlap and memory types are compatible.
+/// Images and buffers never share a slot, which sidesteps
+/// `bufferImageGranularity`.
+fn plan_slots(
+    requests: &[TransientRequest],
+    requirements: &[vk::MemoryRequirements],
+    resources: &[TransientResource],
+    indices: &[usize],
+) -> Vec<Slot> {
+    let mut indices = indices.to_vec();
+    indices.sort_by_key(|&index| std::cmp::Reverse(requirements[index].size));
+
+    let mut slots: Vec<Slot> = Vec::new();
+    for index in indices {
+        let kind = match resources[index] {
+            TransientResource::Image(..) => ResourceKind::Optimal,
+            TransientResource::Buffer(_) => ResourceKind::Linear,
+        };
+        let resource_requirements = requirements[index];
+
+        let slot = slots.iter_mut().find(|slot| {
+            slot.kind == kind
+                && slot.requirements.memory_type_bits & resource_requirements.memory_type_bits != 0
+                && slot
+                    .occupants
+                    .iter()
+                    .all(|&occupant| !requests[occupant].overlaps(&requests[index]))
+        });
+
+        match slot {
+            Some(slot) => {
+                let slot_requirements = &mut slot.requirements;
+                slot_requirements.size = slot_requirements.size.max(resource_requirements.size);
+                slot_requirements.alignment = slot_requirements
+                    .alignment
+                    .max(resource_requirements.alignment);
+                slot_requirements.memory_type_bits &= resource_requirements.memory_type_bits;
+                slot.occupants.push(index);
+                slot.occupants
+                    .sort_by_key(|&occupant| requests[occupant].first);
+            }
+            None => slots.push(Slot {
+                kind,
+                requirements: resource_requirements,
+                occupants: vec![index],
+            }),
+        }
+    }
+
+    slots
+}
+
+/// Pairs of consecutive occupants of each slot, in the order they use it.
+fn alias_pairs(slots: &[Slot]) -> Vec<(usize, usize)> {
+    slots
+        .iter()
+        .flat_map(|slot| slot.occupants.windows(2).map(|pair| (pair[0], pair[1])))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(first: usize, last: usize) -> TransientRequest {
+        TransientRequest {
+            desc: TransientDesc::Buffer {
+                size: 256,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+            },
+            first,
+            last,
+        }
+    }
+
+    fn requirements(size: vk::DeviceSize, memory_type_bits: u32) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        }
+    }
+
+    fn plan(requests: &[TransientRequest], requirements: &[vk::MemoryRequirements]) -> Vec<Slot> {
+        let resources = vec![TransientResource::Buffer(vk::Buffer::null()); requests.len()];
+        let indices: Vec<usize> = (0..requests.len()).collect();
+        plan_slots(requests, requirements, &resources, &indices)
+    }
+
+    #[test]
+    fn shares_slots_between_disjoint_lifetimes() {
+        let requests = [buffer(0, 1), buffer(2, 3)];
+        let slots = plan(
+            &requests,
+            &[requirements(1024, 0b11), requirements(4096, 0b10)],
+        );
+
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].requirements.size, 4096);
+        assert_eq!(slots[0].requirements.memory_type_bits, 0b10);
+        assert_eq!(slots[0].occupants, vec![0, 1]);
+    }
+
+    #[test]
+    fn separates_overlapping_lifetimes() {
+        let requests = [buffer(0, 2), buffer(2, 3)];
+        let slots = plan(
+            &requests,
+            &[requirements(1024, 0b1), requirements(1024, 0b1)],
+        );
+
+        assert_eq!(slots.len(), 2);
+        assert!(alias_pairs(&slots).is_empty());
+    }
+
+    #[test]
+    fn separates_incompatible_memory_types() {
+        let requests = [buffer(0, 1), buffer(2, 3)];
+        let slots = plan(
+            &requests,
+            &[requirements(1024, 0b01), requirements(1024, 0b10)],
+        );
+
+        assert_eq!(slots.len(), 2);
+        assert!(alias_pairs(&slots).is_empty());
+    }
+
+    #[test]
+    fn orders_aliases_by_first_use() {
+        // The largest request is placed first but used last.
+        let requests = [buffer(4, 5), buffer(0, 1), buffer(2, 3)];
+        let slots = plan(
+            &requests,
+            &[
+                requirements(4096, 0b1),
+                requirements(1024, 0b1),
+                requirements(2048, 0b1),
+            ],
+        );
+
+        assert_eq!(slots.len(), 1);
+        assert_eq!(alias_pairs(&slots), vec![(1, 2), (2, 0)]);
+    }
+
+    #[test]
+    fn reports_saved_bytes() {
+        let report = TransientMemoryReport {
+            resource_count: 3,
+            allocation_count: 1,
+            requested_bytes: 7168,
+            allocated_bytes: 4096,
+            ..TransientMemoryReport::default()
+        };
+
+        assert_eq!(report.saved_bytes(), 3072);
+        assert_eq!(TransientMemoryReport::default().saved_bytes(), 0);
+    }
+}
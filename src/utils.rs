@@ -1,14 +1,31 @@
-use std::ffi::CStr;
 use std::os::raw::c_char;
 
+/// Converts a fixed-size, nul-terminated string from a Vulkan struct. Bytes
+/// that aren't valid UTF-8 are replaced rather than rejected.
 pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
-    let raw_string = unsafe {
-        let pointer = raw_string_array.as_ptr();
-        CStr::from_ptr(pointer)
-    };
-
-    raw_string
-        .to_str()
-        .expect("Failed to convert vulkan raw string.")
-        .to_owned()
+    let bytes: Vec<u8> = raw_string_array
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_vulkan_strings() {
+        let raw = |bytes: &[u8]| bytes.iter().map(|&b| b as c_char).collect::<Vec<_>>();
+
+        assert_eq!(vk_to_string(&raw(b"llvmpipe\0garbage")), "llvmpipe");
+        assert_eq!(vk_to_string(&raw(b"no terminator")), "no terminator");
+        assert_eq!(
+            vk_to_string(&raw(b"GPU \xff\xfe\0")),
+            "GPU \u{fffd}\u{fffd}"
+        );
+        assert_eq!(vk_to_string(&raw(b"")), "");
+    }
 }
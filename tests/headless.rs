@@ -0,0 +1,42 @@
+use vre::{Renderer, RendererConfig, VreError};
+
+const WIDTH: u32 = 16;
+const HEIGHT: u32 = 8;
+
+/// Creates a headless renderer, or `None` on machines without a Vulkan
+/// loader or a usable device.
+fn headless_renderer() -> Option<Renderer> {
+    let config = RendererConfig::new().app_name("vre-test");
+
+    match Renderer::new_headless(WIDTH, HEIGHT, &config) {
+        Ok(renderer) => Some(renderer),
+        Err(err @ VreError::Loader(_)) | Err(err @ VreError::NoSuitableDevice) => {
+            eprintln!("Skipping headless test: {}", err);
+            None
+        }
+        Err(err) => panic!("Could not create headless renderer: {}", err),
+    }
+}
+
+#[test]
+fn captures_the_clear_color() {
+    let mut renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return,
+    };
+
+    // 0 and 1 encode to the same sRGB values, so the readback is exact.
+    renderer.set_clear_color([1.0, 0.0, 1.0, 1.0]);
+
+    let capture = renderer
+        .capture_frame()
+        .expect("Could not capture frame.")
+        .expect("Headless renderers always capture.");
+
+    assert_eq!(capture.width, WIDTH);
+    assert_eq!(capture.height, HEIGHT);
+    assert_eq!(capture.pixels.len(), (WIDTH * HEIGHT * 4) as usize);
+    for pixel in capture.pixels.chunks(4) {
+        assert_eq!(pixel, [255, 0, 255, 255]);
+    }
+}